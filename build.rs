@@ -0,0 +1,5 @@
+fn main() {
+    // Node.js addon link settings for the napi feature (see src/node.rs)
+    #[cfg(feature = "napi")]
+    napi_build::setup();
+}
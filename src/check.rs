@@ -0,0 +1,132 @@
+//! Codec invariants of とうばんのしょ strings, for the test suites of code
+//! that stores, forwards or rewrites books.
+//!
+//! Each check returns `Ok(())` when its invariant holds and a
+//! [`ToubanError`] naming the violation otherwise, so it fits an `assert!`
+//! as well as a runtime check (`touban lint` runs [`all`]).
+//!
+//! ```
+//! let book = touban::BookBuilder::new(1, 7).member("たろう").build()?;
+//! touban::check::roundtrip(&book, &touban::HIRAGANA)?;
+//! let hira = touban::encode_book(&book)?;
+//! touban::check::all(&hira)?;
+//! # Ok::<(), touban::ToubanError>(())
+//! ```
+
+use crate::{
+    check_book_len, check_limits, corrupted, decode_book, decode_book_bytes_with, detect_codec,
+    encode_book_with, strip_word_breaks, Book, BookCodec, Result, ToubanError, HIRAGANA,
+};
+
+/// decode ∘ encode = id: encoding `book` with `codec` and decoding the
+/// string gives back the same book, field for field.
+pub fn roundtrip(book: &Book, codec: &dyn BookCodec) -> Result<()> {
+    let text = encode_book_with(book, codec)?;
+    let bytes = decode_book_bytes_with(&text, codec)?;
+    let back: Book = serde_json::from_slice(&bytes).map_err(|_| corrupted("json decode failed"))?;
+    let json = |b: &Book| {
+        serde_json::to_value(b)
+            .map_err(|e| ToubanError::Invalid(format!("serialize book to json: {}", e)))
+    };
+    if json(&back)? != json(book)? {
+        return Err(corrupted("エンコードしてデコードすると内容が変わります"));
+    }
+    Ok(())
+}
+
+/// encode ∘ decode = id: `text` decodes, and re-encoding the book in the
+/// same alphabet yields the exact same string, i.e. it is in canonical form.
+/// [`WORD_BREAK`](crate::WORD_BREAK)s are not part of the canonical form and
+/// are ignored.
+pub fn canonical(text: &str) -> Result<()> {
+    let text = &*strip_word_breaks(text);
+    let book = decode_book(text)?;
+    let again = encode_book_with(&book, detect_codec(text).unwrap_or(&HIRAGANA))?;
+    if again != text {
+        return Err(corrupted(
+            "とうばんのしょ が正規形ではありません（再エンコードすると変わります）",
+        ));
+    }
+    Ok(())
+}
+
+/// `text` and the book it holds are within the limits installed with
+/// [`set_limits`](crate::set_limits): string length, member count, name
+/// length and duty count.
+pub fn size_bounds(text: &str) -> Result<()> {
+    let text = &*strip_word_breaks(text);
+    check_book_len(text)?;
+    check_limits(&decode_book(text)?)
+}
+
+/// Every invariant of a stored string: [`size_bounds`] and [`canonical`]
+/// (which covers the roundtrip of the book it decodes to).
+pub fn all(text: &str) -> Result<()> {
+    size_bounds(text)?;
+    canonical(text)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{base64url_chars_to_text, break_words, encode_book, BookBuilder, CODECS};
+    use base64::engine::general_purpose::URL_SAFE_NO_PAD;
+    use base64::Engine;
+
+    fn sample_book() -> Book {
+        BookBuilder::new(1, 7)
+            .members(["たろう", "はなこ", "じろう"].map(String::from))
+            .build()
+            .expect("book")
+    }
+
+    /// Write arbitrary JSON as hiragana, bypassing the encoder's checks.
+    fn hira_of_json(json: &str) -> String {
+        let b64 = URL_SAFE_NO_PAD.encode(json);
+        base64url_chars_to_text(&HIRAGANA, b64.chars())
+            .collect::<Result<_>>()
+            .expect("hiragana")
+    }
+
+    #[test]
+    fn books_roundtrip_in_every_codec() {
+        let mut book = sample_book();
+        book.add_pair("たろう", "はなこ").expect("pair");
+        for codec in CODECS {
+            if let Err(e) = roundtrip(&book, codec) {
+                panic!("{}: {}", codec.name(), e);
+            }
+        }
+    }
+
+    #[test]
+    fn canonical_accepts_encoder_output_with_word_breaks() {
+        let hira = encode_book(&sample_book()).expect("encode");
+        canonical(&hira).expect("canonical");
+        canonical(&break_words(&hira)).expect("canonical with breaks");
+        all(&hira).expect("all");
+    }
+
+    #[test]
+    fn canonical_rejects_equivalent_spellings() {
+        let pretty = serde_json::to_string_pretty(&sample_book()).expect("json");
+        let hira = hira_of_json(&pretty);
+        decode_book(&hira).expect("still decodes");
+        assert!(matches!(
+            canonical(&hira),
+            Err(ToubanError::CorruptedBook(_))
+        ));
+    }
+
+    #[test]
+    fn size_bounds_rejects_long_names() {
+        let mut book = sample_book();
+        book.members[0].name = "あ".repeat(33);
+        let json = serde_json::to_string(&book).expect("json");
+        assert!(matches!(
+            size_bounds(&hira_of_json(&json)),
+            Err(ToubanError::LimitExceeded(_))
+        ));
+        size_bounds(&encode_book(&sample_book()).expect("encode")).expect("in bounds");
+    }
+}
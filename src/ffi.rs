@@ -0,0 +1,137 @@
+//! C ABI (feature `ffi`) for calling touban from Go, Swift and the like.
+//!
+//! ```c
+//! char *touban_decode(const char *book);              /* book -> JSON */
+//! char *touban_encode(const char *json);              /* JSON -> book */
+//! char *touban_assign(const char *book);              /* book -> updated book */
+//! char *touban_assign_seeded(const char *book, uint64_t seed);
+//! char *touban_last_error(void);
+//! void touban_free(char *s);
+//! ```
+//!
+//! All strings are NUL-terminated UTF-8. Every returned string is allocated
+//! by the library and must be released with `touban_free`. On failure the
+//! functions return NULL and `touban_last_error` returns the message (NULL
+//! if the last call on this thread succeeded).
+
+use crate::{
+    assign, decode_book, encode_book, encode_book_update, Book, Result, ToubanError, HIRAGANA,
+};
+use rand::SeedableRng;
+use rand_chacha::ChaCha8Rng;
+use std::cell::RefCell;
+use std::ffi::{c_char, CStr, CString};
+use std::ptr;
+
+thread_local! {
+    static LAST_ERROR: RefCell<Option<CString>> = const { RefCell::new(None) };
+}
+
+/// Read a C string argument.
+///
+/// # Safety
+///
+/// `s` must be NULL or point to a NUL-terminated string.
+unsafe fn arg<'a>(s: *const c_char) -> Result<&'a str> {
+    if s.is_null() {
+        return Err(ToubanError::Invalid("NULL string argument".to_string()));
+    }
+    CStr::from_ptr(s)
+        .to_str()
+        .map_err(|_| ToubanError::Invalid("argument is not UTF-8".to_string()))
+}
+
+/// Hand `res` over to C: the string on success, else NULL with the error
+/// saved for [`touban_last_error`].
+fn finish(res: Result<String>) -> *mut c_char {
+    let res = res.and_then(|s| {
+        CString::new(s).map_err(|_| ToubanError::Invalid("result contains NUL".to_string()))
+    });
+    let (out, err) = match res {
+        Ok(s) => (s.into_raw(), None),
+        Err(e) => (ptr::null_mut(), CString::new(e.to_string()).ok()),
+    };
+    LAST_ERROR.with(|last| *last.borrow_mut() = err);
+    out
+}
+
+/// Decode a とうばんのしょ into the JSON of its [`Book`].
+///
+/// # Safety
+///
+/// `book` must be NULL or point to a NUL-terminated string.
+#[no_mangle]
+pub unsafe extern "C" fn touban_decode(book: *const c_char) -> *mut c_char {
+    finish(arg(book).and_then(|b| {
+        let book = decode_book(b)?;
+        serde_json::to_string(&book).map_err(|e| ToubanError::Invalid(e.to_string()))
+    }))
+}
+
+/// Encode the JSON of a [`Book`] into a とうばんのしょ.
+///
+/// # Safety
+///
+/// `json` must be NULL or point to a NUL-terminated string.
+#[no_mangle]
+pub unsafe extern "C" fn touban_encode(json: *const c_char) -> *mut c_char {
+    finish(arg(json).and_then(|j| {
+        let book: Book =
+            serde_json::from_str(j).map_err(|e| ToubanError::Invalid(e.to_string()))?;
+        encode_book(&book)
+    }))
+}
+
+/// Draw the next assignment and return the updated とうばんのしょ. A round
+/// planned by `touban assign --rounds` is seated instead once its day has
+/// come.
+///
+/// # Safety
+///
+/// `book` must be NULL or point to a NUL-terminated string.
+#[no_mangle]
+pub unsafe extern "C" fn touban_assign(book: *const c_char) -> *mut c_char {
+    assign_with(book, ChaCha8Rng::from_entropy())
+}
+
+/// [`touban_assign`] with a fixed seed, drawing the same as `touban assign
+/// --seed`.
+///
+/// # Safety
+///
+/// `book` must be NULL or point to a NUL-terminated string.
+#[no_mangle]
+pub unsafe extern "C" fn touban_assign_seeded(book: *const c_char, seed: u64) -> *mut c_char {
+    assign_with(book, ChaCha8Rng::seed_from_u64(seed))
+}
+
+unsafe fn assign_with(book: *const c_char, mut rng: ChaCha8Rng) -> *mut c_char {
+    finish(arg(book).and_then(|b| {
+        let mut book = decode_book(b)?;
+        assign(&mut book, &mut rng)?;
+        encode_book_update(&book, &HIRAGANA)
+    }))
+}
+
+/// The error of the last failed call on this thread, or NULL.
+#[no_mangle]
+pub extern "C" fn touban_last_error() -> *mut c_char {
+    LAST_ERROR.with(|last| {
+        last.borrow()
+            .as_ref()
+            .map_or(ptr::null_mut(), |e| e.clone().into_raw())
+    })
+}
+
+/// Release a string returned by this library.
+///
+/// # Safety
+///
+/// `s` must be NULL or a string returned by a `touban_*` function that has
+/// not been freed yet.
+#[no_mangle]
+pub unsafe extern "C" fn touban_free(s: *mut c_char) {
+    if !s.is_null() {
+        drop(CString::from_raw(s));
+    }
+}
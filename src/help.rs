@@ -0,0 +1,756 @@
+//! Bilingual help catalog: per-subcommand summaries and usage examples in
+//! Japanese and English, applied to the clap command before parsing
+//! according to `--lang`. Every subcommand, nested ones included, has an
+//! entry.
+
+use clap::{Command, ValueEnum};
+
+#[derive(Clone, Copy, PartialEq, ValueEnum)]
+pub enum Lang {
+    Ja,
+    En,
+}
+
+impl Lang {
+    /// `--lang` from the raw arguments (help is rendered before clap has
+    /// parsed anything), else `$LANG`, else Japanese.
+    pub fn detect(args: &[String]) -> Lang {
+        let mut it = args.iter();
+        while let Some(a) = it.next() {
+            let value = match a.strip_prefix("--lang") {
+                Some("") => it.next().map(String::as_str),
+                Some(rest) => rest.strip_prefix('='),
+                None => None,
+            };
+            if let Some(lang) = value.and_then(|v| Lang::from_str(v, true).ok()) {
+                return lang;
+            }
+        }
+        match std::env::var("LANG") {
+            Ok(l) if l.starts_with("en") => Lang::En,
+            _ => Lang::Ja,
+        }
+    }
+}
+
+struct Entry {
+    name: &'static str,
+    about: [&'static str; 2],
+    examples: [&'static str; 2],
+}
+
+const WORKFLOW: [&str; 2] = [
+    "\
+とうばんのしょ は、当番表のすべてを 1 行のひらがなに詰めたものです。
+内容を変えるコマンドは 新しい文字列を出力します。いちばん新しいものを保存して、
+次のコマンドに --book で渡してください。
+
+例:
+  touban create --people 1 --interval 7 --members \"たろう,はなこ,じろう\"
+  touban assign --book <とうばんのしょ>",
+    "\
+A とうばんのしょ (touban book) is the whole roster packed into one line of hiragana.
+Every command that changes it prints a new string: keep the latest one and pass it
+with --book to the next command.
+
+Example:
+  touban create --people 1 --interval 7 --members \"たろう,はなこ,じろう\"
+  touban assign --book <book>",
+];
+
+const ENTRIES: &[Entry] = &[
+    Entry {
+        name: "create",
+        about: [
+            "新しい とうばんのしょ を作ります",
+            "Create a new とうばんのしょ",
+        ],
+        examples: [
+            "例:\n  touban create --people 2 --interval 7 --members \"たろう,はなこ,じろう\"\n  touban create --template school-cleaning --members \"たろう,はなこ,じろう\"\n\n出力の最後の行が とうばんのしょ です。",
+            "Example:\n  touban create --people 2 --interval 7 --members \"たろう,はなこ,じろう\"\n  touban create --template school-cleaning --members \"たろう,はなこ,じろう\"\n\nThe last line of the output is the book.",
+        ],
+    },
+    Entry {
+        name: "show",
+        about: [
+            "とうばんのしょ の中身を表示します",
+            "Show the contents of a とうばんのしょ",
+        ],
+        examples: [
+            "例:\n  touban show --book <とうばんのしょ>\n  touban show --book <とうばんのしょ> --json",
+            "Example:\n  touban show --book <book>\n  touban show --book <book> --json",
+        ],
+    },
+    Entry {
+        name: "add-member",
+        about: [
+            "メンバーを追加します（新しい とうばんのしょ を出力）",
+            "Add a member (prints the updated book)",
+        ],
+        examples: [
+            "例:\n  touban add-member --book <とうばんのしょ> --member さぶろう\n  touban add-member --book <とうばんのしょ> --member しろう --onboarding 2",
+            "Example:\n  touban add-member --book <book> --member さぶろう\n  touban add-member --book <book> --member しろう --onboarding 2",
+        ],
+    },
+    Entry {
+        name: "remove-member",
+        about: [
+            "メンバーを削除します（新しい とうばんのしょ を出力）",
+            "Remove a member (prints the updated book)",
+        ],
+        examples: [
+            "例:\n  touban remove-member --book <とうばんのしょ> --member じろう",
+            "Example:\n  touban remove-member --book <book> --member じろう",
+        ],
+    },
+    Entry {
+        name: "assign",
+        about: [
+            "今回のとうばんを決めます（選ばれた人と 新しい とうばんのしょ を出力）",
+            "Assign this period (prints who was picked and the updated book)",
+        ],
+        examples: [
+            "回数がいちばん少ない人から選ばれます。出力の最後の行を次回の --book に使ってください。\n\n例:\n  touban assign --book <とうばんのしょ>\n  touban assign --book <とうばんのしょ> --seed 20240610 --explain\n  touban assign --book <とうばんのしょ> --seed-from-date 2024-06-10\n  touban assign --book <とうばんのしょ> --grace 24h\n  touban assign --book <とうばんのしょ> --exclude たろう\n  touban assign --book <とうばんのしょ> --include はなこ",
+            "Members with the lowest count are picked first. Use the last line of the output as --book next time.\n\nExample:\n  touban assign --book <book>\n  touban assign --book <book> --seed 20240610 --explain\n  touban assign --book <book> --seed-from-date 2024-06-10\n  touban assign --book <book> --grace 24h\n  touban assign --book <book> --exclude たろう\n  touban assign --book <book> --include はなこ",
+        ],
+    },
+    Entry {
+        name: "confirm",
+        about: [
+            "確定待ちのとうばんを確定します",
+            "Finalize a pending assignment",
+        ],
+        examples: [
+            "例:\n  touban confirm --book <とうばんのしょ>",
+            "Example:\n  touban confirm --book <book>",
+        ],
+    },
+    Entry {
+        name: "reroll",
+        about: [
+            "確定待ち（なければ直前）のとうばんを選び直します",
+            "Redraw the pending (or else the most recent) assignment",
+        ],
+        examples: [
+            "例:\n  touban reroll --book <とうばんのしょ> --object たろう\n  touban reroll --book <とうばんのしょ>",
+            "Example:\n  touban reroll --book <book> --object たろう\n  touban reroll --book <book>",
+        ],
+    },
+    Entry {
+        name: "remind",
+        about: [
+            "今回のとうばんと 未確認の人を表示します",
+            "Show the current assignees and who has not acknowledged",
+        ],
+        examples: [
+            "例:\n  touban remind --book <とうばんのしょ>",
+            "Example:\n  touban remind --book <book>",
+        ],
+    },
+    Entry {
+        name: "ack",
+        about: [
+            "とうばんを確認したことを記録します",
+            "Record that an assignee has seen their duty",
+        ],
+        examples: [
+            "例:\n  touban ack --book <とうばんのしょ> --member たろう",
+            "Example:\n  touban ack --book <book> --member たろう",
+        ],
+    },
+    Entry {
+        name: "list-members",
+        about: [
+            "メンバーの名前と回数を一覧します（大きな とうばんのしょ でも速く動きます）",
+            "List member names and counts (fast even for very large books)",
+        ],
+        examples: [
+            "例:\n  touban list-members --book <とうばんのしょ>",
+            "Example:\n  touban list-members --book <book>",
+        ],
+    },
+    Entry {
+        name: "config",
+        about: [
+            "とうばんのしょ の設定を変えます（新しい とうばんのしょ を出力）",
+            "Change book settings (prints the updated book)",
+        ],
+        examples: [
+            "例:\n  touban config --book <とうばんのしょ> --people 2\n  touban config --book <とうばんのしょ> --avoid-recent 2 --anti-streak true\n  touban config --book <とうばんのしょ> --reset-policy subtract",
+            "Example:\n  touban config --book <book> --people 2\n  touban config --book <book> --avoid-recent 2 --anti-streak true\n  touban config --book <book> --reset-policy subtract",
+        ],
+    },
+    Entry {
+        name: "lint",
+        about: [
+            "とうばんのしょ に問題がないか調べます（見つかれば 0 以外で終了）",
+            "Check a book for problems (exits nonzero if any are found)",
+        ],
+        examples: [
+            "例:\n  touban lint --book <とうばんのしょ>",
+            "Example:\n  touban lint --book <book>",
+        ],
+    },
+    Entry {
+        name: "eq",
+        about: [
+            "2 つの とうばんのしょ の内容が同じかを比べます（ちがえば 0 以外で終了）",
+            "Compare two books by meaning, ignoring encoding differences (exits nonzero if they differ)",
+        ],
+        examples: [
+            "例:\n  touban eq <とうばんのしょ1> <とうばんのしょ2>",
+            "Example:\n  touban eq <book1> <book2>",
+        ],
+    },
+    Entry {
+        name: "stats",
+        about: [
+            "メンバーごとの統計（通算回数と最後のとうばん）を表示します",
+            "Show per-member statistics (lifetime totals and last duty)",
+        ],
+        examples: [
+            "例:\n  touban stats --book <とうばんのしょ>\n  touban stats export --book <とうばんのしょ> -o rounds.csv",
+            "Example:\n  touban stats --book <book>\n  touban stats export --book <book> -o rounds.csv",
+        ],
+    },
+    Entry {
+        name: "stats export",
+        about: [
+            "記録された回を 1 人 1 行（日付・当番・メンバー）の CSV で書き出します",
+            "Dump the recorded rounds as CSV, one row per member (date, duty, member)",
+        ],
+        examples: [
+            "例:\n  touban stats export --book <とうばんのしょ>\n  touban stats export --book <とうばんのしょ> -o rounds.csv\n\n別の当番（add-duty）の回もふくみます。",
+            "Example:\n  touban stats export --book <book>\n  touban stats export --book <book> -o rounds.csv\n\nFurther duties (add-duty) are included.",
+        ],
+    },
+    Entry {
+        name: "set-checklist",
+        about: [
+            "当番のやることリストを置き換えます（新しい とうばんのしょ を出力）",
+            "Replace the duty checklist (prints the updated book)",
+        ],
+        examples: [
+            "例:\n  touban set-checklist --book <とうばんのしょ> --checklist \"ゴミ出し,床そうじ\"\n  touban set-checklist --book <とうばんのしょ> --checklist \"\"",
+            "Example:\n  touban set-checklist --book <book> --checklist \"ゴミ出し,床そうじ\"\n  touban set-checklist --book <book> --checklist \"\"",
+        ],
+    },
+    Entry {
+        name: "tag",
+        about: [
+            "メンバーにタグを付けます（新しい とうばんのしょ を出力）",
+            "Attach a tag to a member (prints the updated book)",
+        ],
+        examples: [
+            "例:\n  touban tag --book <とうばんのしょ> --member はなこ --tag 運転",
+            "Example:\n  touban tag --book <book> --member はなこ --tag 運転",
+        ],
+    },
+    Entry {
+        name: "untag",
+        about: [
+            "メンバーのタグを外します（新しい とうばんのしょ を出力）",
+            "Remove a tag from a member (prints the updated book)",
+        ],
+        examples: [
+            "例:\n  touban untag --book <とうばんのしょ> --member はなこ --tag 運転",
+            "Example:\n  touban untag --book <book> --member はなこ --tag 運転",
+        ],
+    },
+    Entry {
+        name: "set-required-tags",
+        about: [
+            "毎回ふくめるタグを置き換えます（新しい とうばんのしょ を出力）",
+            "Replace the tags every assignment must cover (prints the updated book)",
+        ],
+        examples: [
+            "例:\n  touban set-required-tags --book <とうばんのしょ> --tags 運転\n  touban set-required-tags --book <とうばんのしょ> --tags \"floor:*\"\n\n「floor:*」は floor:1, floor:2, ... のグループごとに 1 人ずつです。",
+            "Example:\n  touban set-required-tags --book <book> --tags 運転\n  touban set-required-tags --book <book> --tags \"floor:*\"\n\n\"floor:*\" asks for one member per group floor:1, floor:2, ...",
+        ],
+    },
+    Entry {
+        name: "set-roles",
+        about: [
+            "毎回割り振る役割を置き換えます（新しい とうばんのしょ を出力）",
+            "Replace the role slots handed out in each assignment (prints the updated book)",
+        ],
+        examples: [
+            "例:\n  touban set-roles --book <とうばんのしょ> --roles \"リーダー,記録係\"",
+            "Example:\n  touban set-roles --book <book> --roles \"リーダー,記録係\"",
+        ],
+    },
+    Entry {
+        name: "set-shifts",
+        about: [
+            "各回をシフトに分け、それぞれの人数を決めます（新しい とうばんのしょ を出力）",
+            "Split each period into shifts with their own headcounts (prints the updated book)",
+        ],
+        examples: [
+            "例:\n  touban set-shifts --book <とうばんのしょ> --shifts \"朝:1,昼:2,夜:1\"\n  touban set-shifts --book <とうばんのしょ> --shifts \"\"",
+            "Example:\n  touban set-shifts --book <book> --shifts \"朝:1,昼:2,夜:1\"\n  touban set-shifts --book <book> --shifts \"\"",
+        ],
+    },
+    Entry {
+        name: "set-shift-prefs",
+        about: [
+            "メンバーの希望シフトと不可シフトを置き換えます（新しい とうばんのしょ を出力）",
+            "Replace a member's preferred and avoided shifts (prints the updated book)",
+        ],
+        examples: [
+            "例:\n  touban set-shift-prefs --book <とうばんのしょ> --member たろう --prefer 朝 --avoid 夜",
+            "Example:\n  touban set-shift-prefs --book <book> --member たろう --prefer 朝 --avoid 夜",
+        ],
+    },
+    Entry {
+        name: "set-exempt",
+        about: [
+            "メンバーが毎回休む日を置き換えます（新しい とうばんのしょ を出力）",
+            "Replace a member's recurring exemptions (prints the updated book)",
+        ],
+        examples: [
+            "例:\n  touban set-exempt --book <とうばんのしょ> --member はなこ --rules \"thu,week1-mon\"\n\nweek1..week5（第 N 週）、mon..sun、その組み合わせ（week1-mon）が使えます。",
+            "Example:\n  touban set-exempt --book <book> --member はなこ --rules \"thu,week1-mon\"\n\nRules are week1..week5 (Nth week of the month), mon..sun, or both as week1-mon.",
+        ],
+    },
+    Entry {
+        name: "set-team",
+        about: [
+            "`--strategy per-team` 用にメンバーのチームを決めます（新しい とうばんのしょ を出力）",
+            "Put a member in a team for `--strategy per-team` (prints the updated book)",
+        ],
+        examples: [
+            "例:\n  touban set-team --book <とうばんのしょ> --member たろう --team 営業\n  touban set-team --book <とうばんのしょ> --member たろう --team \"\"",
+            "Example:\n  touban set-team --book <book> --member たろう --team 営業\n  touban set-team --book <book> --member たろう --team \"\"",
+        ],
+    },
+    Entry {
+        name: "set-weight",
+        about: [
+            "メンバーが選ばれる頻度を変えます（新しい とうばんのしょ を出力）",
+            "Make a member drawn more or less often (prints the updated book)",
+        ],
+        examples: [
+            "例:\n  touban set-weight --book <とうばんのしょ> --member じろう --weight 0.5\n\n1 がふつう、0.5 でおよそ半分、2 でおよそ 2 倍です。",
+            "Example:\n  touban set-weight --book <book> --member じろう --weight 0.5\n\n1 is normal; 0.5 is drawn about half as often, 2 about twice.",
+        ],
+    },
+    Entry {
+        name: "rebalance",
+        about: [
+            "途中で入った・抜けた人がいても 以後の負担が均等になるよう回数をならします（新しい とうばんのしょ を出力）",
+            "Level everyone's count after members joined or left mid-cycle (prints the updated book)",
+        ],
+        examples: [
+            "例:\n  touban rebalance --book <とうばんのしょ> --dry-run\n  touban rebalance --book <とうばんのしょ>",
+            "Example:\n  touban rebalance --book <book> --dry-run\n  touban rebalance --book <book>",
+        ],
+    },
+    Entry {
+        name: "add-duty",
+        about: [
+            "同じメンバーで回す別の当番を追加します（新しい とうばんのしょ を出力）",
+            "Add a further duty rotated over the same roster, with its own counts (prints the updated book)",
+        ],
+        examples: [
+            "例:\n  touban add-duty --book <とうばんのしょ> --name 朝会司会\n  touban assign --book <とうばんのしょ> --duty 朝会司会",
+            "Example:\n  touban add-duty --book <book> --name 朝会司会\n  touban assign --book <book> --duty 朝会司会",
+        ],
+    },
+    Entry {
+        name: "remove-duty",
+        about: [
+            "別の当番を 回数ごと削除します（新しい とうばんのしょ を出力）",
+            "Remove a further duty and its counts (prints the updated book)",
+        ],
+        examples: [
+            "例:\n  touban remove-duty --book <とうばんのしょ> --name 朝会司会",
+            "Example:\n  touban remove-duty --book <book> --name 朝会司会",
+        ],
+    },
+    Entry {
+        name: "add-conflict",
+        about: [
+            "2 人を同じ回に選ばないようにします（新しい とうばんのしょ を出力）",
+            "Never draw two members in the same round (prints the updated book)",
+        ],
+        examples: [
+            "例:\n  touban add-conflict --book <とうばんのしょ> たろう じろう",
+            "Example:\n  touban add-conflict --book <book> たろう じろう",
+        ],
+    },
+    Entry {
+        name: "remove-conflict",
+        about: [
+            "2 人を また同じ回に選べるようにします（新しい とうばんのしょ を出力）",
+            "Let two members be drawn together again (prints the updated book)",
+        ],
+        examples: [
+            "例:\n  touban remove-conflict --book <とうばんのしょ> たろう じろう",
+            "Example:\n  touban remove-conflict --book <book> たろう じろう",
+        ],
+    },
+    Entry {
+        name: "add-pair",
+        about: [
+            "2 人をいつも一緒に選びます（新しい とうばんのしょ を出力）",
+            "Always draw two members together (prints the updated book)",
+        ],
+        examples: [
+            "例:\n  touban add-pair --book <とうばんのしょ> はなこ しろう\n\n教える人と教わる人などに。2 人で 2 席を使います。",
+            "Example:\n  touban add-pair --book <book> はなこ しろう\n\nE.g. a trainer and a trainee; they take two of the seats.",
+        ],
+    },
+    Entry {
+        name: "remove-pair",
+        about: [
+            "ペアを解いて 別々に選ぶようにします（新しい とうばんのしょ を出力）",
+            "Draw two paired members independently again (prints the updated book)",
+        ],
+        examples: [
+            "例:\n  touban remove-pair --book <とうばんのしょ> はなこ しろう",
+            "Example:\n  touban remove-pair --book <book> はなこ しろう",
+        ],
+    },
+    Entry {
+        name: "compact",
+        about: [
+            "長くなりすぎた とうばんのしょ を短くします（新しい とうばんのしょ を出力）",
+            "Shorten a book that has grown too long (prints the updated book)",
+        ],
+        examples: [
+            "例:\n  touban compact --book <とうばんのしょ>\n  touban compact --book <とうばんのしょ> --notes --stats --codec base64\n\n表示済みの引き継ぎを捨て、--codec の文字で書き直します。",
+            "Example:\n  touban compact --book <book>\n  touban compact --book <book> --notes --stats --codec base64\n\nDrops handover notes already shown and re-encodes in the --codec alphabet.",
+        ],
+    },
+    Entry {
+        name: "history",
+        about: [
+            "最近の回を 新しい順に表示します",
+            "List the latest rounds, newest first",
+        ],
+        examples: [
+            "例:\n  touban history --book <とうばんのしょ>",
+            "Example:\n  touban history --book <book>",
+        ],
+    },
+    Entry {
+        name: "topic",
+        about: [
+            "チャンネルトピック用の 1 行（今回のとうばんと次回の日付）を表示します",
+            "Print a one-line channel topic: the current assignees and the next due date",
+        ],
+        examples: [
+            "例:\n  touban topic --book <とうばんのしょ>\n  SLACK_BOT_TOKEN=xoxb-… touban topic --book <とうばんのしょ> --slack-channel C0123456\n\n--slack-channel は slack 機能つきのビルドで使えます。",
+            "Example:\n  touban topic --book <book>\n  SLACK_BOT_TOKEN=xoxb-… touban topic --book <book> --slack-channel C0123456\n\n--slack-channel needs a build with the slack feature.",
+        ],
+    },
+    Entry {
+        name: "me",
+        about: [
+            "1 人分の 今回・次回の予定・これまでの記録・交換の募集 を表示します（DM 向け）",
+            "One member's view: this period, the next duty, past rounds and trade offers (for a direct message)",
+        ],
+        examples: [
+            "例:\n  touban me --book <とうばんのしょ> --member はなこ",
+            "Example:\n  touban me --book <book> --member はなこ",
+        ],
+    },
+    Entry {
+        name: "dashboard",
+        about: [
+            "複数の とうばんのしょ をまとめて 急ぐものから表示します",
+            "Overview of several books at once, the most urgent first",
+        ],
+        examples: [
+            "例:\n  touban dashboard --book そうじ=<とうばんのしょ1> --book ごみ=<とうばんのしょ2>\n  touban dashboard --board --book そうじ=<とうばんのしょ1> --book ごみ=<とうばんのしょ2>\n\n--board は 期限切れ・今日・これから・未定 の列に並べたかんばんで 1 画面に表示します。",
+            "Example:\n  touban dashboard --book そうじ=<book1> --book ごみ=<book2>\n  touban dashboard --board --book そうじ=<book1> --book ごみ=<book2>\n\n--board lays the books out on one screen as a kanban board with overdue, today, upcoming and unassigned columns.",
+        ],
+    },
+    Entry {
+        name: "trades",
+        about: [
+            "とうばんの交換を募集・一覧・引き受けします",
+            "Post, list and accept duty trade offers",
+        ],
+        examples: [
+            "例:\n  touban trades post --book <とうばんのしょ> --member たろう --when 6/10\n  touban trades list --book <とうばんのしょ>\n  touban trades accept --book <とうばんのしょ> --id 1 --member はなこ",
+            "Example:\n  touban trades post --book <book> --member たろう --when 6/10\n  touban trades list --book <book>\n  touban trades accept --book <book> --id 1 --member はなこ",
+        ],
+    },
+    Entry {
+        name: "trades post",
+        about: [
+            "自分のとうばんの交換を募集します（新しい とうばんのしょ を出力）",
+            "Offer one of your duties for trade (prints the updated book)",
+        ],
+        examples: [
+            "例:\n  touban trades post --book <とうばんのしょ> --member たろう --when 6/10",
+            "Example:\n  touban trades post --book <book> --member たろう --when 6/10",
+        ],
+    },
+    Entry {
+        name: "trades list",
+        about: [
+            "出ている交換の募集を一覧します",
+            "List open trade offers",
+        ],
+        examples: [
+            "例:\n  touban trades list --book <とうばんのしょ>",
+            "Example:\n  touban trades list --book <book>",
+        ],
+    },
+    Entry {
+        name: "trades accept",
+        about: [
+            "交換の募集を引き受けます（新しい とうばんのしょ を出力）",
+            "Take over an offered duty (prints the updated book)",
+        ],
+        examples: [
+            "例:\n  touban trades accept --book <とうばんのしょ> --id 1 --member はなこ",
+            "Example:\n  touban trades accept --book <book> --id 1 --member はなこ",
+        ],
+    },
+    Entry {
+        name: "feed",
+        about: [
+            "今回のとうばんを Atom フィードに書き出します",
+            "Write the current assignment as an Atom feed",
+        ],
+        examples: [
+            "例:\n  touban feed --book <とうばんのしょ> --link https://intra.example.com/touban.xml -o touban.xml\n\nassign のたびに書き出して公開してください。",
+            "Example:\n  touban feed --book <book> --link https://intra.example.com/touban.xml -o touban.xml\n\nPublish the file after every assign.",
+        ],
+    },
+    Entry {
+        name: "override",
+        about: [
+            "決まった人の代わりに 日付を決めて別の人が入ります",
+            "Let someone cover for the scheduled assignee on given dates",
+        ],
+        examples: [
+            "例:\n  touban override add --book <とうばんのしょ> --member じろう --from 2024-06-10 --to 2024-06-14 --for たろう\n  touban override list --book <とうばんのしょ>\n  touban override remove --book <とうばんのしょ> --id 1",
+            "Example:\n  touban override add --book <book> --member じろう --from 2024-06-10 --to 2024-06-14 --for たろう\n  touban override list --book <book>\n  touban override remove --book <book> --id 1",
+        ],
+    },
+    Entry {
+        name: "override add",
+        about: [
+            "期間を決めて代わりに入る人を登録します（回数は変わりません。新しい とうばんのしょ を出力）",
+            "Have a member cover on a range of dates; counts are not touched (prints the updated book)",
+        ],
+        examples: [
+            "例:\n  touban override add --book <とうばんのしょ> --member じろう --from 2024-06-10 --to 2024-06-14 --for たろう\n  touban override add --book <とうばんのしょ> --member じろう --from 2024-06-10 --to 2024-06-10\n\n--for がなければ その期間のとうばんを全部引き受けます。",
+            "Example:\n  touban override add --book <book> --member じろう --from 2024-06-10 --to 2024-06-14 --for たろう\n  touban override add --book <book> --member じろう --from 2024-06-10 --to 2024-06-10\n\nWithout --for the member covers the whole assignment.",
+        ],
+    },
+    Entry {
+        name: "override list",
+        about: [
+            "代わりの予定を 古い順に一覧します（後のものが優先）",
+            "List the overrides, oldest first (later ones win)",
+        ],
+        examples: [
+            "例:\n  touban override list --book <とうばんのしょ>",
+            "Example:\n  touban override list --book <book>",
+        ],
+    },
+    Entry {
+        name: "override remove",
+        about: [
+            "代わりの予定を取り消します（新しい とうばんのしょ を出力）",
+            "Remove an override (prints the updated book)",
+        ],
+        examples: [
+            "例:\n  touban override remove --book <とうばんのしょ> --id 1",
+            "Example:\n  touban override remove --book <book> --id 1",
+        ],
+    },
+    Entry {
+        name: "who",
+        about: [
+            "ある日のとうばんを 代わりの予定もふくめて表示します（先の回は見込み）",
+            "Who is on duty on a date, overrides included (projected for future periods)",
+        ],
+        examples: [
+            "例:\n  touban who --book <とうばんのしょ>\n  touban who --book <とうばんのしょ> --date 2024-06-10",
+            "Example:\n  touban who --book <book>\n  touban who --book <book> --date 2024-06-10",
+        ],
+    },
+    Entry {
+        name: "contacts",
+        about: [
+            "メンバーを vCard に書き出したり 連絡先から取り込んだりします",
+            "Export members as vCards or import them from contacts",
+        ],
+        examples: [
+            "例:\n  touban contacts export --book <とうばんのしょ> -o members.vcf\n  touban contacts import --book <とうばんのしょ> contacts.csv",
+            "Example:\n  touban contacts export --book <book> -o members.vcf\n  touban contacts import --book <book> contacts.csv",
+        ],
+    },
+    Entry {
+        name: "contacts export",
+        about: [
+            "メンバー全員を vCard 3.0 で書き出します",
+            "Write every member as a vCard 3.0 entry",
+        ],
+        examples: [
+            "例:\n  touban contacts export --book <とうばんのしょ> -o members.vcf",
+            "Example:\n  touban contacts export --book <book> -o members.vcf",
+        ],
+    },
+    Entry {
+        name: "contacts import",
+        about: [
+            "vCard か Google 連絡先の CSV からメンバーを追加します（新しい とうばんのしょ を出力）",
+            "Add members from a vCard file or a Google Contacts CSV export (prints the updated book)",
+        ],
+        examples: [
+            "例:\n  touban contacts import --book <とうばんのしょ> members.vcf\n  touban contacts import --book <とうばんのしょ> contacts.csv",
+            "Example:\n  touban contacts import --book <book> members.vcf\n  touban contacts import --book <book> contacts.csv",
+        ],
+    },
+    Entry {
+        name: "note",
+        about: [
+            "次のとうばんへの引き継ぎメモを残します",
+            "Leave handover notes for the next assignees",
+        ],
+        examples: [
+            "例:\n  touban note add --book <とうばんのしょ> --member たろう \"ゴミ袋が残り2枚\"",
+            "Example:\n  touban note add --book <book> --member たろう \"ゴミ袋が残り2枚\"",
+        ],
+    },
+    Entry {
+        name: "note add",
+        about: [
+            "今回のとうばんから 次の人へメモを残します（新しい とうばんのしょ を出力）",
+            "Attach a short note for the next assignees (prints the updated book)",
+        ],
+        examples: [
+            "例:\n  touban note add --book <とうばんのしょ> --member たろう \"ゴミ袋が残り2枚\"",
+            "Example:\n  touban note add --book <book> --member たろう \"ゴミ袋が残り2枚\"",
+        ],
+    },
+    Entry {
+        name: "whatif",
+        about: [
+            "メンバーが抜けた場合と比べて 先の負担をシミュレーションします",
+            "Simulate upcoming periods with and without a roster change and compare everyone's load",
+        ],
+        examples: [
+            "例:\n  touban whatif --book <とうばんのしょ> --remove じろう\n  touban whatif --book <とうばんのしょ> --remove \"じろう,しろう\" --periods 24",
+            "Example:\n  touban whatif --book <book> --remove じろう\n  touban whatif --book <book> --remove \"じろう,しろう\" --periods 24",
+        ],
+    },
+    Entry {
+        name: "schedule",
+        about: [
+            "今回と 先の回の見込みを表示します（見込みは確定ではありません）",
+            "List the current assignment and project the coming periods (a forecast, not a commitment)",
+        ],
+        examples: [
+            "例:\n  touban schedule --book <とうばんのしょ>\n  touban schedule --book <とうばんのしょ> --member はなこ --format ics -o hanako.ics\n  touban schedule --book <とうばんのしょ> --format csv --escalation",
+            "Example:\n  touban schedule --book <book>\n  touban schedule --book <book> --member はなこ --format ics -o hanako.ics\n  touban schedule --book <book> --format csv --escalation",
+        ],
+    },
+    Entry {
+        name: "promote-backup",
+        about: [
+            "今回のとうばんの席を 控えの 1 人目に渡します（回数も移ります）",
+            "Hand a current assignee's seat to the first backup from `assign --with-backup`, moving the count with it",
+        ],
+        examples: [
+            "例:\n  touban assign --book <とうばんのしょ> --with-backup 2\n  touban promote-backup --book <とうばんのしょ> --member たろう",
+            "Example:\n  touban assign --book <book> --with-backup 2\n  touban promote-backup --book <book> --member たろう",
+        ],
+    },
+    Entry {
+        name: "defer",
+        about: [
+            "今回休んだ人が 次の回を引き受ける約束をします（新しい とうばんのしょ を出力）",
+            "Promise that a member who sat out takes the next round (prints the updated book)",
+        ],
+        examples: [
+            "例:\n  touban defer --book <とうばんのしょ> --member はなこ",
+            "Example:\n  touban defer --book <book> --member はなこ",
+        ],
+    },
+    Entry {
+        name: "swap",
+        about: [
+            "2 人で番を交換したあと 回数と最後のとうばんを入れ替えます（新しい とうばんのしょ を出力）",
+            "Exchange two members' counts and last duty after they traded turns (prints the updated book)",
+        ],
+        examples: [
+            "例:\n  touban swap --book <とうばんのしょ> --a たろう --b はなこ",
+            "Example:\n  touban swap --book <book> --a たろう --b はなこ",
+        ],
+    },
+    Entry {
+        name: "substitute",
+        about: [
+            "期間の途中で 今回のとうばんの席を別の人に渡します（回数も移ります）",
+            "Hand a current assignee's seat to someone else mid-period, moving the count with it",
+        ],
+        examples: [
+            "例:\n  touban substitute --book <とうばんのしょ> --out たろう --in じろう\n  touban substitute --book <とうばんのしょ> --out たろう\n\n--in がなければ 空いている人のうち回数がいちばん少ない人が入ります。",
+            "Example:\n  touban substitute --book <book> --out たろう --in じろう\n  touban substitute --book <book> --out たろう\n\nWithout --in the free member with the lowest count takes over.",
+        ],
+    },
+    Entry {
+        name: "undo",
+        about: [
+            "直前の assign（確定待ちをふくむ）を取り消し、回数を戻します",
+            "Take back the latest assign (pending or committed), restoring counts",
+        ],
+        examples: [
+            "例:\n  touban undo --book <とうばんのしょ>",
+            "Example:\n  touban undo --book <book>",
+        ],
+    },
+];
+
+/// Apply the catalog for `lang` to the top-level command and its subcommands.
+pub fn localize(cmd: Command, lang: Lang) -> Command {
+    let i = match lang {
+        Lang::Ja => 0,
+        Lang::En => 1,
+    };
+    let mut cmd = cmd.after_long_help(WORKFLOW[i]);
+    for e in ENTRIES {
+        let localized = |sub: Command| sub.about(e.about[i]).after_help(e.examples[i]);
+        // nested subcommands are named "group sub"
+        cmd = match e.name.split_once(' ') {
+            Some((group, sub)) => cmd.mut_subcommand(group, |g| g.mut_subcommand(sub, localized)),
+            None => cmd.mut_subcommand(e.name, localized),
+        };
+    }
+    cmd
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use clap::CommandFactory;
+
+    fn names(cmd: &Command, prefix: &str, out: &mut Vec<String>) {
+        for sub in cmd.get_subcommands() {
+            let name = format!("{}{}", prefix, sub.get_name());
+            names(sub, &format!("{} ", name), out);
+            out.push(name);
+        }
+    }
+
+    #[test]
+    fn every_subcommand_has_an_entry() {
+        let mut all = Vec::new();
+        names(&crate::Cli::command(), "", &mut all);
+        for name in all {
+            assert!(
+                ENTRIES.iter().any(|e| e.name == name),
+                "no help entry for `{}`",
+                name
+            );
+        }
+    }
+}
@@ -0,0 +1,4748 @@
+//! とうばんのしょ: a duty roster packed into a single line of hiragana.
+//!
+//! A [`Book`] is serialized to JSON, encoded as base64url and mapped onto
+//! hiragana, one character per base64url digit. [`decode_book`] and
+//! [`encode_book`] convert between the two; [`assign`] draws and commits
+//! the next assignment.
+//!
+//! ```
+//! use rand::SeedableRng;
+//!
+//! let mut book = touban::BookBuilder::new(1, 7)
+//!     .member("たろう")
+//!     .member("はなこ")
+//!     .build()?;
+//! let mut rng = rand_chacha::ChaCha8Rng::seed_from_u64(1);
+//! touban::assign(&mut book, &mut rng)?;
+//! let hira = touban::encode_book(&book)?;
+//! assert_eq!(touban::decode_book(&hira)?.current.len(), 1);
+//! # Ok::<(), touban::ToubanError>(())
+//! ```
+
+use base64::engine::general_purpose::URL_SAFE_NO_PAD;
+use base64::{DecodeError, Engine};
+use chrono::{Datelike, Local, NaiveDate, TimeZone, Weekday};
+use clap::ValueEnum;
+use rand::prelude::*;
+use rand_chacha::ChaCha8Rng;
+use serde::{Deserialize, Serialize, Serializer};
+use std::borrow::Cow;
+use std::cmp::min;
+use std::collections::{BTreeMap, BTreeSet};
+use std::io;
+use std::str;
+use std::sync::OnceLock;
+
+pub mod check;
+#[cfg(feature = "ffi")]
+pub mod ffi;
+#[cfg(feature = "napi")]
+pub mod node;
+#[cfg(feature = "wasm-bindgen")]
+pub mod wasm;
+
+/// Why an operation on a book failed.
+#[derive(Debug, thiserror::Error)]
+pub enum ToubanError {
+    /// The string is not a valid (canonical) とうばんのしょ
+    #[error("{0}")]
+    CorruptedBook(String),
+    #[error("メンバー「{0}」は見つかりませんでした")]
+    MemberNotFound(String),
+    #[error("メンバー「{0}」は既に存在します")]
+    DuplicateMember(String),
+    #[error("メンバーがいません")]
+    EmptyRoster,
+    /// The book is over one of the installed [`Limits`]
+    #[error("{0}")]
+    LimitExceeded(String),
+    /// The draw cannot satisfy the book's rules (tags, pairing, shifts)
+    #[error("{0}")]
+    Unsatisfiable(String),
+    /// The request does not fit the book's state or is malformed
+    #[error("{0}")]
+    Invalid(String),
+    /// The book is not at the revision the caller expected; someone else
+    /// has probably updated it since
+    #[error("とうばんのしょ の版が {found} です（期待した版は {expected}）。新しい とうばんのしょ を使ってください")]
+    StaleBook { expected: u32, found: u32 },
+    /// Reading or writing a stream failed
+    #[error("{0}")]
+    Io(#[from] std::io::Error),
+}
+
+pub type Result<T, E = ToubanError> = std::result::Result<T, E>;
+
+const BASE64_LEN: u32 = 64; // base64url indices 0..63
+/// Default for [`Book::reset_threshold`].
+pub const RESET_THRESHOLD: u8 = 5;
+/// Default for [`Book::history_limit`].
+pub const HISTORY_LIMIT: u8 = 10;
+const STALE_INTERVALS: u64 = 3; // warn when nobody served for this many intervals
+
+/// One person on the roster. `count` drives the draw: the lowest counts
+/// are picked first.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct Member {
+    pub name: String,
+    pub count: u32,
+    /// Backup member, only drawn when regular members cannot fill the round
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub substitute: bool,
+    /// Free-form labels such as "senior", used by required-tag rules
+    #[serde(
+        default,
+        skip_serializing_if = "Vec::is_empty",
+        serialize_with = "serialize_set"
+    )]
+    pub tags: Vec<String>,
+    /// Remaining assignments in which this new member must be paired with
+    /// an experienced one
+    #[serde(default, skip_serializing_if = "is_zero")]
+    pub onboarding: u8,
+    /// How many times this member filled each role slot
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub role_counts: BTreeMap<String, u32>,
+    /// Unix time (seconds) of the member's most recent assignment
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub last_assigned_at: Option<u64>,
+    /// Lifetime number of assignments; unlike `count` it is never reset
+    #[serde(default, skip_serializing_if = "is_zero_u32")]
+    pub total: u32,
+    /// How many times this member worked each shift
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub shift_counts: BTreeMap<String, u32>,
+    /// Shifts this member would rather work; honored when possible
+    #[serde(
+        default,
+        skip_serializing_if = "Vec::is_empty",
+        serialize_with = "serialize_set"
+    )]
+    pub prefer_shifts: Vec<String>,
+    /// Shifts this member is never put on
+    #[serde(
+        default,
+        skip_serializing_if = "Vec::is_empty",
+        serialize_with = "serialize_set"
+    )]
+    pub avoid_shifts: Vec<String>,
+    /// Recurring dates this member is never drawn on, e.g. "week1" or "week2-fri"
+    #[serde(
+        default,
+        skip_serializing_if = "Vec::is_empty",
+        serialize_with = "serialize_set"
+    )]
+    pub exempt: Vec<String>,
+    /// Selection weight in percent (50 is drawn about half as often); 0
+    /// stands for 100
+    #[serde(default, skip_serializing_if = "is_default_weight")]
+    pub weight: u16,
+    /// The group this member belongs to, for [`StrategyKind::PerTeam`]
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub team: Option<String>,
+}
+
+impl Member {
+    /// Selection weight in percent.
+    pub fn weight(&self) -> u16 {
+        match self.weight {
+            0 => 100,
+            w => w,
+        }
+    }
+
+    /// Set the weight in percent (at least 1); 100 is stored as 0 so the
+    /// encoding stays canonical.
+    pub fn set_weight(&mut self, percent: u16) -> Result<()> {
+        if percent == 0 {
+            return Err(ToubanError::Invalid("weight must be > 0".to_string()));
+        }
+        self.weight = if percent == 100 { 0 } else { percent };
+        Ok(())
+    }
+
+    /// The count scaled by the weight, which is what draws compare: at
+    /// weight 50 every assignment counts double.
+    pub fn load(&self) -> u64 {
+        self.count as u64 * 10_000 / self.weight() as u64
+    }
+}
+
+/// Tags, required tags, shift preferences and exemption rules are sets:
+/// write them sorted and deduplicated so that the order in which they were
+/// added never shows up in the encoding.
+fn serialize_set<S: Serializer>(items: &[String], s: S) -> Result<S::Ok, S::Error> {
+    s.collect_seq(items.iter().collect::<BTreeSet<_>>())
+}
+
+/// Conflicts and pairs are sets of unordered pairs: write each pair and the
+/// set sorted, so neither argument nor insertion order shows up.
+fn serialize_link_set<S: Serializer>(links: &[(String, String)], s: S) -> Result<S::Ok, S::Error> {
+    s.collect_seq(
+        links
+            .iter()
+            .map(|(a, b)| if a <= b { (a, b) } else { (b, a) })
+            .collect::<BTreeSet<_>>(),
+    )
+}
+
+fn is_zero(n: &u8) -> bool {
+    *n == 0
+}
+
+fn is_zero_u32(n: &u32) -> bool {
+    *n == 0
+}
+
+fn is_default_weight(w: &u16) -> bool {
+    matches!(*w, 0 | 100)
+}
+
+fn is_default_reset_threshold(n: &u8) -> bool {
+    matches!(*n, 0 | RESET_THRESHOLD)
+}
+
+fn is_default_history_limit(n: &Option<u8>) -> bool {
+    matches!(*n, None | Some(HISTORY_LIMIT))
+}
+
+fn is_zero_usize(n: &usize) -> bool {
+    *n == 0
+}
+
+/// The whole roster: settings, members and the state of the current
+/// period. This is what a とうばんのしょ string encodes.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct Book {
+    /// Bumped by every change written with [`encode_book_update`], for
+    /// optimistic concurrency checks
+    #[serde(default, skip_serializing_if = "is_zero_u32")]
+    pub revision: u32,
+    pub people: usize,
+    pub interval: usize,
+    pub members: Vec<Member>,
+    /// What the duty entails, printed with every assignment
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub checklist: Vec<String>,
+    /// Every assignment must include at least one member with each of these tags
+    #[serde(
+        default,
+        skip_serializing_if = "Vec::is_empty",
+        serialize_with = "serialize_set"
+    )]
+    pub required_tags: Vec<String>,
+    /// Named role slots (e.g. "リーダー") handed out among the assignees
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub roles: Vec<String>,
+    /// Shifts (e.g. 朝/昼/夜) within each period; their headcounts add up to `people`
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub shifts: Vec<Shift>,
+    /// Pairs of members who must never serve in the same round
+    #[serde(
+        default,
+        skip_serializing_if = "Vec::is_empty",
+        serialize_with = "serialize_link_set"
+    )]
+    pub conflicts: Vec<(String, String)>,
+    /// Pairs of members who always serve together
+    #[serde(
+        default,
+        skip_serializing_if = "Vec::is_empty",
+        serialize_with = "serialize_link_set"
+    )]
+    pub pairs: Vec<(String, String)>,
+    /// Further duties rotated over the same roster; see [`assign_duty`]
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub duties: Vec<Duty>,
+    /// Favor members who served longest ago when drawing among equal counts
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub anti_streak: bool,
+    /// Leave out anyone drawn in this many latest rounds of `history` while
+    /// the others can still fill the round; 0 turns it off
+    #[serde(default, skip_serializing_if = "is_zero")]
+    pub avoid_recent: u8,
+    /// How members with equal counts are ordered; anything but random
+    /// makes the draw fully deterministic
+    #[serde(default, skip_serializing_if = "TieBreak::is_random")]
+    pub tie_break: TieBreak,
+    /// Which [`AssignmentStrategy`] picks the assignees
+    #[serde(default, skip_serializing_if = "StrategyKind::is_default")]
+    pub strategy: StrategyKind,
+    /// Roster position [`StrategyKind::RoundRobin`] starts from next
+    #[serde(default, skip_serializing_if = "is_zero_usize")]
+    pub rr_cursor: usize,
+    /// Counts are reset once anyone in a pool reaches this; 0 stands for
+    /// [`RESET_THRESHOLD`]
+    #[serde(default, skip_serializing_if = "is_default_reset_threshold")]
+    pub reset_threshold: u8,
+    /// How counts are brought back down; the threshold only applies to
+    /// [`ResetPolicy::Zero`]
+    #[serde(default, skip_serializing_if = "ResetPolicy::is_default")]
+    pub reset_policy: ResetPolicy,
+    /// Members drawn by the most recent assign
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub current: Vec<Assignee>,
+    /// Stand-ins for the current assignees, in the order they are promoted
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub backups: Vec<String>,
+    /// Members who sat out and promised to take the next round, seated
+    /// ahead of the draw in this order
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub deferred: Vec<String>,
+    /// Rounds committed ahead by `assign --rounds`, oldest first: already
+    /// counted and in the history, seated by [`seat_planned`] once due
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub planned: Vec<PlannedRound>,
+    /// Draw waiting for `confirm` during its grace period
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub pending: Option<Pending>,
+    /// Open offers from members who want someone else to take a duty
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub trades: Vec<Trade>,
+    /// Date ranges covered by someone other than the scheduled assignee,
+    /// oldest first; see [`on_duty`]
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub overrides: Vec<Override>,
+    /// Notes left by the current assignees for whoever is drawn next
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub notes: Vec<HandoverNote>,
+    /// Notes the previous assignees left for the current ones
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub handover: Vec<HandoverNote>,
+    /// Counts the latest draw's reset changed, as they were before it, so
+    /// [`undo_assignment`] can put them back
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub reset_undo: Vec<(String, u32)>,
+    /// The latest committed rounds, oldest first
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub history: Vec<HistoryEntry>,
+    /// How many rounds `history` keeps; `None` stands for [`HISTORY_LIMIT`]
+    #[serde(default, skip_serializing_if = "is_default_history_limit")]
+    pub history_limit: Option<u8>,
+    /// Unix time (seconds) of the last committed draw; unlike `history`,
+    /// never trimmed
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub drawn_at: Option<u64>,
+}
+
+impl Book {
+    /// Add a member whose count starts at the average of the pool they
+    /// join, so they are neither drawn at once nor skipped for long.
+    pub fn add_member(&mut self, name: String, substitute: bool, onboarding: u8) -> Result<()> {
+        if name.trim().is_empty() {
+            return Err(ToubanError::Invalid("メンバー名が空です".to_string()));
+        }
+        if self.members.iter().any(|m| m.name == name) {
+            return Err(ToubanError::DuplicateMember(name));
+        }
+        let count = joining_count(self, substitute);
+        self.members.push(Member {
+            name,
+            count,
+            substitute,
+            onboarding,
+            ..Default::default()
+        });
+        Ok(())
+    }
+
+    /// Whether `a` and `b` must not serve in the same round.
+    pub fn in_conflict(&self, a: &str, b: &str) -> bool {
+        linked(&self.conflicts, a, b)
+    }
+
+    /// Keep `a` and `b` from ever being drawn together.
+    pub fn add_conflict(&mut self, a: &str, b: &str) -> Result<()> {
+        self.check_link(a, b)?;
+        if self.in_pair(a, b) {
+            return Err(ToubanError::Invalid(format!(
+                "「{}」と「{}」は組になっています（remove-pair を使ってください）",
+                a, b
+            )));
+        }
+        if !self.in_conflict(a, b) {
+            self.conflicts.push((a.to_string(), b.to_string()));
+        }
+        Ok(())
+    }
+
+    /// Let `a` and `b` be drawn together again.
+    pub fn remove_conflict(&mut self, a: &str, b: &str) -> Result<()> {
+        unlink(&mut self.conflicts, a, b)
+    }
+
+    /// Whether drawing `a` or `b` pulls in the other.
+    pub fn in_pair(&self, a: &str, b: &str) -> bool {
+        linked(&self.pairs, a, b)
+    }
+
+    /// Always draw `a` and `b` together; the pair takes two of the
+    /// `people` seats.
+    pub fn add_pair(&mut self, a: &str, b: &str) -> Result<()> {
+        self.check_link(a, b)?;
+        if self.in_conflict(a, b) {
+            return Err(ToubanError::Invalid(format!(
+                "「{}」と「{}」は いっしょにできない組み合わせです（remove-conflict を使ってください）",
+                a, b
+            )));
+        }
+        if !self.in_pair(a, b) {
+            self.pairs.push((a.to_string(), b.to_string()));
+        }
+        Ok(())
+    }
+
+    /// Draw `a` and `b` independently again.
+    pub fn remove_pair(&mut self, a: &str, b: &str) -> Result<()> {
+        unlink(&mut self.pairs, a, b)
+    }
+
+    /// Promise `name` the next round: [`draw_with`] seats them first unless
+    /// they are excluded, until an assignment does.
+    pub fn defer(&mut self, name: &str) -> Result<()> {
+        if !self.members.iter().any(|m| m.name == name) {
+            return Err(ToubanError::MemberNotFound(name.to_string()));
+        }
+        if self.deferred.iter().any(|n| n == name) {
+            return Err(ToubanError::Invalid(format!(
+                "「{}」は もう次のとうばんを約束しています",
+                name
+            )));
+        }
+        self.deferred.push(name.to_string());
+        Ok(())
+    }
+
+    /// Exchange `a`'s and `b`'s assignment state, for two members who traded
+    /// turns between themselves: counts, totals and last-assigned times
+    /// swap, and so do their seats in the current assignment and its
+    /// history entry.
+    pub fn swap_members(&mut self, a: &str, b: &str) -> Result<()> {
+        let find = |name: &str| {
+            self.members
+                .iter()
+                .position(|m| m.name == name)
+                .ok_or_else(|| ToubanError::MemberNotFound(name.to_string()))
+        };
+        let (i, j) = (find(a)?, find(b)?);
+        self.check_link(a, b)?;
+        let (x, y) = (self.members[i].clone(), self.members[j].clone());
+        let m = &mut self.members[i];
+        (m.count, m.total, m.last_assigned_at) = (y.count, y.total, y.last_assigned_at);
+        let m = &mut self.members[j];
+        (m.count, m.total, m.last_assigned_at) = (x.count, x.total, x.last_assigned_at);
+        let other = |n: &mut String| {
+            if n == a {
+                *n = b.to_string();
+            } else if n == b {
+                *n = a.to_string();
+            }
+        };
+        self.current.iter_mut().for_each(|s| other(&mut s.name));
+        if let Some(h) = self.current_round_mut() {
+            h.members.iter_mut().for_each(other);
+        }
+        Ok(())
+    }
+
+    /// The history entry of the round now on duty ([`Book::current`]);
+    /// rounds planned ahead come after it. `None` when nothing is seated or
+    /// the history no longer holds the round.
+    pub fn current_round(&self) -> Option<&HistoryEntry> {
+        let k = self.current_round_index()?;
+        self.history.get(k)
+    }
+
+    pub fn current_round_mut(&mut self) -> Option<&mut HistoryEntry> {
+        let k = self.current_round_index()?;
+        self.history.get_mut(k)
+    }
+
+    fn current_round_index(&self) -> Option<usize> {
+        if self.current.is_empty() {
+            return None;
+        }
+        self.history.len().checked_sub(self.planned.len() + 1)
+    }
+
+    /// Leave a handover note from current assignee `member` for whoever is
+    /// drawn next; the round's history entry keeps a copy.
+    pub fn add_note(&mut self, member: &str, text: &str) -> Result<()> {
+        if !self.members.iter().any(|m| m.name == member) {
+            return Err(ToubanError::MemberNotFound(member.to_string()));
+        }
+        if !self.current.iter().any(|a| a.name == member) {
+            return Err(ToubanError::Invalid(format!(
+                "「{}」は今回のとうばんではありません",
+                member
+            )));
+        }
+        let text = text.trim();
+        if text.is_empty() {
+            return Err(ToubanError::Invalid("引き継ぎメモが空です".to_string()));
+        }
+        let note = HandoverNote {
+            member: member.to_string(),
+            text: text.to_string(),
+        };
+        if let Some(h) = self.current_round_mut() {
+            h.notes.push(note.clone());
+        }
+        self.notes.push(note);
+        Ok(())
+    }
+
+    /// Record that current assignee `name` has seen their duty, on the seat
+    /// and in the round's history entry.
+    pub fn ack(&mut self, name: &str) -> Result<()> {
+        if !self.members.iter().any(|m| m.name == name) {
+            return Err(ToubanError::MemberNotFound(name.to_string()));
+        }
+        let seat = self
+            .current
+            .iter_mut()
+            .find(|a| a.name == name)
+            .ok_or_else(|| {
+                ToubanError::Invalid(format!("「{}」は今回のとうばんではありません", name))
+            })?;
+        seat.acked = true;
+        if let Some(h) = self.current_round_mut() {
+            if !h.acked.iter().any(|n| n == name) {
+                h.acked.push(name.to_string());
+            }
+        }
+        Ok(())
+    }
+
+    /// Add a further duty drawn from the same roster.
+    pub fn add_duty(&mut self, name: &str, people: usize) -> Result<()> {
+        if name.trim().is_empty() || name == "all" {
+            return Err(ToubanError::Invalid(format!(
+                "「{}」は当番の名前に使えません",
+                name
+            )));
+        }
+        if people == 0 {
+            return Err(ToubanError::Invalid("people must be >= 1".to_string()));
+        }
+        if self.duties.iter().any(|d| d.name == name) {
+            return Err(ToubanError::Invalid(format!(
+                "当番「{}」は既にあります",
+                name
+            )));
+        }
+        self.duties.push(Duty {
+            name: name.to_string(),
+            people,
+            counts: BTreeMap::new(),
+            current: Vec::new(),
+            history: Vec::new(),
+            drawn_at: None,
+        });
+        Ok(())
+    }
+
+    /// Drop a further duty along with its counts.
+    pub fn remove_duty(&mut self, name: &str) -> Result<()> {
+        let before = self.duties.len();
+        self.duties.retain(|d| d.name != name);
+        if self.duties.len() == before {
+            return Err(ToubanError::Invalid(format!(
+                "当番「{}」はありません",
+                name
+            )));
+        }
+        Ok(())
+    }
+
+    fn check_link(&self, a: &str, b: &str) -> Result<()> {
+        for name in [a, b] {
+            if !self.members.iter().any(|m| m.name == name) {
+                return Err(ToubanError::MemberNotFound(name.to_string()));
+            }
+        }
+        if a == b {
+            return Err(ToubanError::Invalid(
+                "同じメンバーどうしは指定できません".to_string(),
+            ));
+        }
+        Ok(())
+    }
+
+    /// Remove a member, along with their seat in the current assignment,
+    /// their planned seats, open trade offers, conflicts, pairs and deferral.
+    pub fn remove_member(&mut self, name: &str) -> Result<()> {
+        let pos = self
+            .members
+            .iter()
+            .position(|m| m.name == name)
+            .ok_or_else(|| ToubanError::MemberNotFound(name.to_string()))?;
+        self.members.remove(pos);
+        self.current.retain(|a| a.name != name);
+        self.trades.retain(|t| t.member != name);
+        self.conflicts.retain(|(a, b)| a != name && b != name);
+        self.pairs.retain(|(a, b)| a != name && b != name);
+        self.deferred.retain(|n| n != name);
+        for p in &mut self.planned {
+            p.seats.retain(|a| a.name != name);
+        }
+        for d in &mut self.duties {
+            d.counts.remove(name);
+            d.current.retain(|n| n != name);
+        }
+        self.overrides
+            .retain(|o| o.member != name && o.replaces.as_deref() != Some(name));
+        // keep the round-robin cursor on the same member
+        if self.rr_cursor > pos {
+            self.rr_cursor -= 1;
+        }
+        if self.rr_cursor >= self.members.len() {
+            self.rr_cursor = 0;
+        }
+        Ok(())
+    }
+
+    /// The count at which a pool's counts are reset.
+    pub fn reset_threshold(&self) -> u8 {
+        match self.reset_threshold {
+            0 => RESET_THRESHOLD,
+            n => n,
+        }
+    }
+
+    /// Set the reset threshold (at least 1); the default is stored as 0 so
+    /// the encoding stays canonical.
+    pub fn set_reset_threshold(&mut self, n: u8) -> Result<()> {
+        if n == 0 {
+            return Err(ToubanError::Invalid(
+                "reset threshold must be >= 1".to_string(),
+            ));
+        }
+        self.reset_threshold = if n == RESET_THRESHOLD { 0 } else { n };
+        Ok(())
+    }
+
+    /// How many rounds [`Book::history`] keeps.
+    pub fn history_limit(&self) -> usize {
+        self.history_limit.unwrap_or(HISTORY_LIMIT) as usize
+    }
+
+    /// Keep at most `n` rounds (0 keeps none), dropping the oldest ones now
+    /// if there are more.
+    pub fn set_history_limit(&mut self, n: u8) {
+        self.history_limit = (n != HISTORY_LIMIT).then_some(n);
+        self.trim_history();
+    }
+
+    fn trim_history(&mut self) {
+        let limit = self.history_limit();
+        let excess = self.history.len().saturating_sub(limit);
+        self.history.drain(..excess);
+        for d in &mut self.duties {
+            let excess = d.history.len().saturating_sub(limit);
+            d.history.drain(..excess);
+        }
+    }
+
+    /// Strip optional data to shorten the encoded string. Handover notes
+    /// that were already shown with the last assignment always go.
+    pub fn compact(&mut self, c: Compaction) {
+        self.handover.clear();
+        if c.notes {
+            self.notes.clear();
+        }
+        if c.history {
+            self.history.clear();
+            for d in &mut self.duties {
+                d.history.clear();
+            }
+        }
+        if c.stats {
+            for m in &mut self.members {
+                m.total = 0;
+                m.last_assigned_at = None;
+                m.role_counts.clear();
+                m.shift_counts.clear();
+            }
+        }
+    }
+
+    /// Whether two books have the same logical content, however they were
+    /// encoded (tag order, omitted defaults, ...) and whatever their
+    /// revisions.
+    pub fn semantic_eq(&self, other: &Book) -> bool {
+        self.diff_fields(other).is_empty()
+    }
+
+    /// Names of the top-level fields whose logical content differs.
+    pub fn diff_fields(&self, other: &Book) -> Vec<String> {
+        let to_map = |b: &Book| match serde_json::to_value(b) {
+            Ok(serde_json::Value::Object(m)) => m,
+            _ => serde_json::Map::new(),
+        };
+        let (a, b) = (to_map(self), to_map(other));
+        let keys: BTreeSet<&String> = a.keys().chain(b.keys()).collect();
+        keys.into_iter()
+            .filter(|k| *k != "revision" && a.get(*k) != b.get(*k))
+            .cloned()
+            .collect()
+    }
+}
+
+/// Read-only view of a book that borrows member names from the decoded
+/// JSON instead of cloning them; unknown fields are skipped unparsed. The
+/// rest (checklist, tags, duties, ...) is small and owned.
+#[derive(Debug, Deserialize)]
+pub struct BookView<'a> {
+    #[serde(default)]
+    pub revision: u32,
+    pub people: usize,
+    pub interval: usize,
+    #[serde(borrow)]
+    pub members: Vec<MemberView<'a>>,
+    #[serde(default)]
+    pub checklist: Vec<String>,
+    #[serde(default)]
+    pub required_tags: Vec<String>,
+    #[serde(default)]
+    pub roles: Vec<String>,
+    #[serde(default)]
+    pub shifts: Vec<Shift>,
+    #[serde(default)]
+    pub conflicts: Vec<(String, String)>,
+    #[serde(default)]
+    pub pairs: Vec<(String, String)>,
+    #[serde(default)]
+    pub duties: Vec<Duty>,
+    #[serde(default)]
+    pub anti_streak: bool,
+    #[serde(default)]
+    pub avoid_recent: u8,
+    #[serde(default)]
+    pub tie_break: TieBreak,
+    #[serde(default)]
+    pub strategy: StrategyKind,
+    #[serde(default)]
+    pub rr_cursor: usize,
+    #[serde(default)]
+    pub reset_threshold: u8,
+    #[serde(default)]
+    pub reset_policy: ResetPolicy,
+    #[serde(default)]
+    pub deferred: Vec<String>,
+    #[serde(default)]
+    pub planned: Vec<PlannedRound>,
+    #[serde(default)]
+    pub pending: Option<Pending>,
+    #[serde(default)]
+    pub history_limit: Option<u8>,
+}
+
+impl BookView<'_> {
+    /// See [`Book::reset_threshold`].
+    pub fn reset_threshold(&self) -> u8 {
+        match self.reset_threshold {
+            0 => RESET_THRESHOLD,
+            n => n,
+        }
+    }
+
+    /// See [`Book::history_limit`].
+    pub fn history_limit(&self) -> usize {
+        self.history_limit.unwrap_or(HISTORY_LIMIT) as usize
+    }
+
+    /// Same as [`book_warnings`] for the book this view was read from.
+    pub fn warnings(&self) -> Vec<Warning> {
+        let members: Vec<_> = self
+            .members
+            .iter()
+            .map(|m| (&*m.name, m.count, m.last_assigned_at))
+            .collect();
+        warnings_of(
+            self.people,
+            self.interval,
+            self.reset_policy
+                .is_default()
+                .then(|| self.reset_threshold()),
+            &members,
+        )
+    }
+}
+
+#[derive(Debug, Deserialize)]
+pub struct MemberView<'a> {
+    #[serde(borrow)]
+    pub name: Cow<'a, str>,
+    pub count: u32,
+    #[serde(default)]
+    pub substitute: bool,
+    #[serde(default)]
+    pub tags: Vec<String>,
+    #[serde(default)]
+    pub onboarding: u8,
+    #[serde(default)]
+    pub role_counts: BTreeMap<String, u32>,
+    #[serde(default)]
+    pub last_assigned_at: Option<u64>,
+    #[serde(default)]
+    pub total: u32,
+    #[serde(default)]
+    pub shift_counts: BTreeMap<String, u32>,
+    #[serde(default)]
+    pub prefer_shifts: Vec<String>,
+    #[serde(default)]
+    pub avoid_shifts: Vec<String>,
+    #[serde(default)]
+    pub exempt: Vec<String>,
+    #[serde(default)]
+    pub weight: u16,
+    #[serde(default)]
+    pub team: Option<String>,
+}
+
+impl MemberView<'_> {
+    /// See [`Member::weight`].
+    pub fn weight(&self) -> u16 {
+        match self.weight {
+            0 => 100,
+            w => w,
+        }
+    }
+}
+
+/// A draw kept open for objections until its deadline.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Pending {
+    pub members: Vec<String>,
+    /// Unix time (seconds) when the grace period ends
+    pub deadline: u64,
+    /// Members who objected or were excluded from the draw; left out of
+    /// every reroll of it
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub objected: Vec<String>,
+    /// Recorded in the history once the draw is confirmed
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub note: Option<String>,
+}
+
+/// A round committed ahead of its period; see [`plan_round`].
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PlannedRound {
+    /// Unix time (seconds) the round's period starts
+    pub at: u64,
+    pub seats: Vec<Assignee>,
+}
+
+/// An offer to hand one of a member's duties to someone else.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Trade {
+    pub id: u32,
+    pub member: String,
+    /// Free-form description of the duty on offer, e.g. "6/10"
+    pub when: String,
+}
+
+/// A further rotation over the same roster (e.g. 朝会司会 next to the
+/// book's own duty), with its own headcount and counts.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Duty {
+    pub name: String,
+    pub people: usize,
+    /// Each member's count for this duty; members not listed are at 0
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub counts: BTreeMap<String, u32>,
+    /// Members drawn for this duty by the most recent assign
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub current: Vec<String>,
+    /// Past draws of this duty, oldest first, as many as
+    /// [`Book::history_limit`] keeps
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub history: Vec<DutyRound>,
+    /// Unix time (seconds) of this duty's last draw; never trimmed
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub drawn_at: Option<u64>,
+}
+
+/// One draw of a further duty kept in [`Duty::history`].
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct DutyRound {
+    /// Unix time (seconds) the draw was committed
+    pub at: u64,
+    pub members: Vec<String>,
+}
+
+/// A part of each period (e.g. 朝) with its own headcount.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Shift {
+    pub name: String,
+    pub people: usize,
+}
+
+/// Ready-made settings for common rotations; see
+/// [`BookBuilder::from_template`].
+#[derive(Debug, Clone, Copy, PartialEq, ValueEnum)]
+pub enum Template {
+    /// Classroom cleaning: 4 a week, counts start over once the class has
+    /// gone round
+    SchoolCleaning,
+    /// Office trash: 1 a week, counts lowered together so nobody's turn is lost
+    OfficeTrash,
+    /// On-call: 1 a week, counts never reset, so lifetime shares stay even
+    Oncall,
+}
+
+/// Validated construction of a [`Book`]: `people` must be at least 1 and
+/// member names must be non-empty and unique.
+#[derive(Debug, Default)]
+pub struct BookBuilder {
+    people: usize,
+    interval: usize,
+    members: Vec<(String, bool)>,
+    checklist: Vec<String>,
+    required_tags: Vec<String>,
+    reset_threshold: Option<u8>,
+    reset_policy: ResetPolicy,
+}
+
+impl BookBuilder {
+    /// A book assigning `people` members every `interval` days.
+    pub fn new(people: usize, interval: usize) -> Self {
+        BookBuilder {
+            people,
+            interval,
+            ..Default::default()
+        }
+    }
+
+    /// A book with the settings of `template`; any of them can still be
+    /// changed before [`build`](Self::build).
+    pub fn from_template(template: Template) -> Self {
+        let (people, interval, policy, checklist): (usize, usize, ResetPolicy, &[&str]) =
+            match template {
+                Template::SchoolCleaning => (
+                    4,
+                    7,
+                    ResetPolicy::Zero,
+                    &["机を運ぶ", "床をはく", "黒板をふく", "ゴミを捨てる"],
+                ),
+                Template::OfficeTrash => (
+                    1,
+                    7,
+                    ResetPolicy::Subtract,
+                    &["ゴミ袋を交換", "分別を確認", "集積所へ出す"],
+                ),
+                Template::Oncall => (
+                    1,
+                    7,
+                    ResetPolicy::Never,
+                    &["前任から引き継ぐ", "アラートに対応", "対応記録を残す"],
+                ),
+            };
+        BookBuilder::new(people, interval)
+            .reset_policy(policy)
+            .checklist(checklist.iter().map(|s| s.to_string()).collect())
+    }
+
+    pub fn people(mut self, people: usize) -> Self {
+        self.people = people;
+        self
+    }
+
+    /// Days between assignments.
+    pub fn interval(mut self, days: usize) -> Self {
+        self.interval = days;
+        self
+    }
+
+    pub fn member(mut self, name: impl Into<String>) -> Self {
+        self.members.push((name.into(), false));
+        self
+    }
+
+    pub fn members<I: IntoIterator<Item = String>>(mut self, names: I) -> Self {
+        self.members.extend(names.into_iter().map(|n| (n, false)));
+        self
+    }
+
+    /// A backup member, only drawn when regular members cannot fill a round.
+    pub fn substitute(mut self, name: impl Into<String>) -> Self {
+        self.members.push((name.into(), true));
+        self
+    }
+
+    pub fn substitutes<I: IntoIterator<Item = String>>(mut self, names: I) -> Self {
+        self.members.extend(names.into_iter().map(|n| (n, true)));
+        self
+    }
+
+    pub fn checklist(mut self, items: Vec<String>) -> Self {
+        self.checklist = items;
+        self
+    }
+
+    pub fn required_tags(mut self, tags: Vec<String>) -> Self {
+        self.required_tags = tags;
+        self
+    }
+
+    /// Reset counts at `n` instead of [`RESET_THRESHOLD`].
+    pub fn reset_threshold(mut self, n: u8) -> Self {
+        self.reset_threshold = Some(n);
+        self
+    }
+
+    pub fn reset_policy(mut self, policy: ResetPolicy) -> Self {
+        self.reset_policy = policy;
+        self
+    }
+
+    pub fn build(self) -> Result<Book> {
+        if self.people == 0 {
+            return Err(ToubanError::Invalid("people must be >= 1".to_string()));
+        }
+        let mut book = Book {
+            people: self.people,
+            interval: self.interval,
+            checklist: self.checklist,
+            required_tags: self.required_tags,
+            reset_policy: self.reset_policy,
+            ..Default::default()
+        };
+        if let Some(n) = self.reset_threshold {
+            book.set_reset_threshold(n)?;
+        }
+        for (name, substitute) in self.members {
+            book.add_member(name, substitute, 0)?;
+        }
+        Ok(book)
+    }
+}
+
+/// What [`Book::compact`] strips besides the shown handover notes.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Compaction {
+    /// Notes still waiting for the next assignees
+    pub notes: bool,
+    /// Per-member statistics: lifetime totals, last-assigned times and role
+    /// and shift tallies (role and shift rotation starts over)
+    pub stats: bool,
+    /// The record of past rounds
+    pub history: bool,
+}
+
+/// One committed round kept in [`Book::history`].
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct HistoryEntry {
+    /// Unix time (seconds) the round was committed
+    pub at: u64,
+    pub members: Vec<String>,
+    /// Identifies the round, so copies of a book can be told apart when
+    /// two people assigned from the same one
+    #[serde(default, skip_serializing_if = "is_zero_u32")]
+    pub nonce: u32,
+    /// Why this round went the way it did, for whoever reads the record later
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub note: Option<String>,
+    /// Assignees who acknowledged their duty, in the order they did
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub acked: Vec<String>,
+    /// Seats handed on after the draw (substitute, promote-backup, an
+    /// accepted trade), as (outgoing, incoming), oldest first
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub substitutions: Vec<(String, String)>,
+    /// Handover notes this round's assignees left for the next one
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub notes: Vec<HandoverNote>,
+}
+
+/// Someone covering for the scheduled assignee on a range of dates, as in
+/// an on-call rotation. Counts are not touched.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Override {
+    pub id: u32,
+    /// Who is on duty instead
+    pub member: String,
+    /// First and last covered dates (YYYY-MM-DD), both included
+    pub from: String,
+    pub to: String,
+    /// The assignee being covered; `None` covers the whole assignment
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub replaces: Option<String>,
+}
+
+impl Override {
+    /// Whether the override is in force on `date`.
+    pub fn covers(&self, date: NaiveDate) -> bool {
+        let day = |s: &str| s.parse::<NaiveDate>().ok();
+        matches!((day(&self.from), day(&self.to)), (Some(from), Some(to)) if from <= date && date <= to)
+    }
+}
+
+/// A note from one period's assignees to the next.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct HandoverNote {
+    pub member: String,
+    pub text: String,
+}
+
+/// How members with equal counts are ordered in a draw.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Serialize, Deserialize, ValueEnum)]
+#[serde(rename_all = "lowercase")]
+pub enum TieBreak {
+    /// Shuffle (seeded with --seed)
+    #[default]
+    Random,
+    /// Alphabetical by name
+    Name,
+    /// Roster order
+    Insertion,
+    /// Stable hash of the name
+    Hash,
+}
+
+impl TieBreak {
+    pub fn is_random(&self) -> bool {
+        *self == TieBreak::Random
+    }
+}
+
+/// What happens to a pool's counts as they grow.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Serialize, Deserialize, ValueEnum)]
+#[serde(rename_all = "lowercase")]
+pub enum ResetPolicy {
+    /// Set everyone's count to 0 once anyone reaches the threshold
+    #[default]
+    Zero,
+    /// Subtract the lowest count from everyone before each draw, keeping
+    /// the differences between members
+    Subtract,
+    /// Keep counting
+    Never,
+}
+
+impl ResetPolicy {
+    pub fn is_default(&self) -> bool {
+        *self == ResetPolicy::Zero
+    }
+}
+
+/// The assignment strategies a book can be configured with.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Serialize, Deserialize, ValueEnum)]
+#[serde(rename_all = "kebab-case")]
+pub enum StrategyKind {
+    /// Lowest counts first, honoring tags, pairing and shifts
+    #[default]
+    MinCount,
+    /// Roster order, cycling through from [`Book::rr_cursor`]; no randomness
+    RoundRobin,
+    /// One member from every team, whatever `people` says
+    PerTeam,
+}
+
+impl StrategyKind {
+    pub fn is_default(&self) -> bool {
+        *self == StrategyKind::MinCount
+    }
+
+    /// The strategy implementing this kind.
+    pub fn strategy(&self) -> &'static dyn AssignmentStrategy {
+        match self {
+            StrategyKind::MinCount => &MinCount,
+            StrategyKind::RoundRobin => &RoundRobin,
+            StrategyKind::PerTeam => &PerTeam,
+        }
+    }
+}
+
+/// Something about a book that is allowed but probably not intended.
+#[derive(Debug, Serialize)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum Warning {
+    PeopleExceedsMembers {
+        people: usize,
+        members: usize,
+    },
+    DuplicateName {
+        name: String,
+    },
+    CountAboveThreshold {
+        name: String,
+        count: u32,
+        threshold: u8,
+    },
+    StaleAssignment {
+        days: u64,
+    },
+}
+
+impl std::fmt::Display for Warning {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Warning::PeopleExceedsMembers { people, members } => write!(
+                f,
+                "とうばん人数 ({}) がメンバー数 ({}) より多いです",
+                people, members
+            ),
+            Warning::DuplicateName { name } => {
+                write!(f, "メンバー「{}」が重複しています", name)
+            }
+            Warning::CountAboveThreshold {
+                name,
+                count,
+                threshold,
+            } => write!(
+                f,
+                "「{}」の回数 ({}) がリセットの上限 ({}) を超えています",
+                name, count, threshold
+            ),
+            Warning::StaleAssignment { days } => {
+                write!(f, "最後のとうばんから {} 日たっています", days)
+            }
+        }
+    }
+}
+
+/// A member's seat in the current assignment.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Assignee {
+    pub name: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub role: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub shift: Option<String>,
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub acked: bool,
+    /// Whether this draw used up one of the member's onboarding rounds
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub onboarding: bool,
+    /// The member's `last_assigned_at` before this draw
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub prev_assigned_at: Option<u64>,
+}
+
+/// Resource bounds for hosted/bot deployments, enforced whenever a book is
+/// decoded or encoded.
+#[derive(Debug)]
+pub struct Limits {
+    /// Maximum number of members in a book
+    pub max_members: usize,
+    /// Maximum length of a member name (in characters)
+    pub max_name_len: usize,
+    /// Maximum length of a とうばんのしょ string (in characters)
+    pub max_book_len: usize,
+    /// Maximum number of further duties ([`Book::duties`]) in a book
+    pub max_duties: usize,
+}
+
+impl Default for Limits {
+    fn default() -> Self {
+        Limits {
+            max_members: 100,
+            max_name_len: 32,
+            max_book_len: 20000,
+            max_duties: 20,
+        }
+    }
+}
+
+static LIMITS: OnceLock<Limits> = OnceLock::new();
+
+/// Install process-wide limits; only the first call has any effect, and
+/// books handled before it use the defaults.
+pub fn set_limits(l: Limits) {
+    let _ = LIMITS.set(l);
+}
+
+fn limits() -> &'static Limits {
+    LIMITS.get_or_init(Limits::default)
+}
+
+// --------------------- Book Codecs (base64url <-> text alphabet) ---------------------
+/// A text alphabet for とうばんのしょ strings: each base64url digit of the
+/// encoded JSON is written as one character of the alphabet.
+///
+/// The built-in codecs are listed in [`CODECS`]; embedders can implement the
+/// trait for their own alphabet and use [`encode_book_with`] /
+/// [`decode_book_bytes_with`].
+pub trait BookCodec: Sync {
+    /// Name used to choose the codec, e.g. `--codec katakana`.
+    fn name(&self) -> &'static str;
+    /// The character standing for base64url digit `idx` (0..63).
+    fn digit_char(&self, idx: u32) -> char;
+    /// The base64url digit `ch` stands for, or `None` if `ch` is not part of
+    /// the alphabet.
+    fn char_digit(&self, ch: char) -> Option<u32>;
+}
+
+/// An alphabet of 64 consecutive code points starting at `start`.
+pub struct RangeCodec {
+    pub name: &'static str,
+    pub start: u32,
+}
+
+impl BookCodec for RangeCodec {
+    fn name(&self) -> &'static str {
+        self.name
+    }
+
+    fn digit_char(&self, idx: u32) -> char {
+        std::char::from_u32(self.start + (idx % BASE64_LEN)).unwrap_or('\u{FFFD}')
+    }
+
+    fn char_digit(&self, ch: char) -> Option<u32> {
+        let cp = ch as u32;
+        (self.start..self.start + BASE64_LEN)
+            .contains(&cp)
+            .then(|| cp - self.start)
+    }
+}
+
+/// Plain base64url, for channels that only pass ASCII through.
+pub struct Base64Codec;
+
+impl BookCodec for Base64Codec {
+    fn name(&self) -> &'static str {
+        "base64"
+    }
+
+    fn digit_char(&self, idx: u32) -> char {
+        base64url_digit_char(idx)
+    }
+
+    fn char_digit(&self, ch: char) -> Option<u32> {
+        base64url_char_digit(ch)
+    }
+}
+
+/// 'ぁ'..: the default alphabet.
+pub static HIRAGANA: RangeCodec = RangeCodec {
+    name: "hiragana",
+    start: 0x3041,
+};
+/// 'ァ'..
+pub static KATAKANA: RangeCodec = RangeCodec {
+    name: "katakana",
+    start: 0x30A1,
+};
+/// '🐀'..: the animal emoji block.
+pub static EMOJI: RangeCodec = RangeCodec {
+    name: "emoji",
+    start: 0x1F400,
+};
+
+/// The built-in codecs, in the order decoding probes them. The alphabets
+/// are disjoint, so at most one of them accepts a given string.
+pub static CODECS: [&dyn BookCodec; 4] = [&HIRAGANA, &KATAKANA, &EMOJI, &Base64Codec];
+
+/// Look up a built-in codec by name.
+pub fn codec_by_name(name: &str) -> Option<&'static dyn BookCodec> {
+    CODECS.iter().copied().find(|c| c.name() == name)
+}
+
+/// The built-in codec whose alphabet covers every character of `text`.
+pub fn detect_codec(text: &str) -> Option<&'static dyn BookCodec> {
+    CODECS
+        .iter()
+        .copied()
+        .find(|c| text.chars().all(|ch| c.char_digit(ch).is_some()))
+}
+
+// map base64url char <-> index 0..63
+fn base64url_char_digit(ch: char) -> Option<u32> {
+    let idx = match ch {
+        'A'..='Z' => ch as u32 - 'A' as u32,      // 0..25
+        'a'..='z' => ch as u32 - 'a' as u32 + 26, // 26..51
+        '0'..='9' => ch as u32 - '0' as u32 + 52, // 52..61
+        '-' => 62,
+        '_' => 63,
+        _ => return None,
+    };
+    Some(idx)
+}
+
+fn base64url_digit_char(idx: u32) -> char {
+    match idx % BASE64_LEN {
+        i @ 0..=25 => (b'A' + i as u8) as char,
+        i @ 26..=51 => (b'a' + (i as u8 - 26)) as char,
+        i @ 52..=61 => (b'0' + (i as u8 - 52)) as char,
+        62 => '-',
+        _ => '_',
+    }
+}
+
+/// Lazily transcode base64url chars into `codec`'s alphabet, one char at a
+/// time, so callers can stream from any char source without an intermediate
+/// String.
+pub fn base64url_chars_to_text<'a, I>(
+    codec: &'a dyn BookCodec,
+    chars: I,
+) -> impl Iterator<Item = Result<char>> + 'a
+where
+    I: IntoIterator<Item = char>,
+    I::IntoIter: 'a,
+{
+    chars.into_iter().map(move |ch| {
+        base64url_char_digit(ch)
+            .map(|idx| codec.digit_char(idx))
+            .ok_or_else(|| {
+                ToubanError::Invalid(format!("invalid base64url char encountered: {:?}", ch))
+            })
+    })
+}
+
+/// Lazily transcode chars of `codec`'s alphabet back to base64url.
+pub fn text_chars_to_base64url<'a, I>(
+    codec: &'a dyn BookCodec,
+    chars: I,
+) -> impl Iterator<Item = Result<char>> + 'a
+where
+    I: IntoIterator<Item = char>,
+    I::IntoIter: 'a,
+{
+    chars.into_iter().map(move |ch| {
+        codec
+            .char_digit(ch)
+            .map(base64url_digit_char)
+            .ok_or_else(|| {
+                ToubanError::CorruptedBook(format!(
+                    "invalid {} char encountered: {:?}",
+                    codec.name(),
+                    ch
+                ))
+            })
+    })
+}
+
+/// Map a base64url string onto hiragana, one character per digit.
+pub fn base64url_to_hiragana(b64: &str) -> Result<String> {
+    base64url_chars_to_text(&HIRAGANA, b64.chars()).collect()
+}
+
+/// Inverse of [`base64url_to_hiragana`].
+pub fn hiragana_to_base64url(hira: &str) -> Result<String> {
+    text_chars_to_base64url(&HIRAGANA, hira.chars()).collect()
+}
+
+fn from_io(e: io::Error) -> ToubanError {
+    e.downcast::<ToubanError>().unwrap_or_else(ToubanError::Io)
+}
+
+fn to_io(e: ToubanError) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, e)
+}
+
+/// Streaming decoder: reads a とうばんのしょ string in `codec`'s alphabet
+/// (UTF-8) from `inner` and yields the JSON bytes it encodes, buffering up
+/// to 256 bytes of input and a few bits. [`WORD_BREAK`]s are skipped, and
+/// reading past the installed `max_book_len` fails, so an endless stream
+/// cannot exhaust memory.
+pub struct BookReader<'a, R> {
+    inner: R,
+    codec: &'a dyn BookCodec,
+    buf: [u8; 256],
+    pos: usize,
+    len: usize,
+    acc: u32,
+    bits: u32,
+    chars: usize,
+    eof: bool,
+}
+
+impl<'a, R: io::Read> BookReader<'a, R> {
+    pub fn new(inner: R, codec: &'a dyn BookCodec) -> Self {
+        BookReader {
+            inner,
+            codec,
+            buf: [0; 256],
+            pos: 0,
+            len: 0,
+            acc: 0,
+            bits: 0,
+            chars: 0,
+            eof: false,
+        }
+    }
+
+    fn next_byte(&mut self) -> io::Result<Option<u8>> {
+        if self.pos == self.len {
+            self.len = loop {
+                match self.inner.read(&mut self.buf) {
+                    Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+                    r => break r?,
+                }
+            };
+            self.pos = 0;
+            if self.len == 0 {
+                return Ok(None);
+            }
+        }
+        self.pos += 1;
+        Ok(Some(self.buf[self.pos - 1]))
+    }
+
+    fn next_char(&mut self) -> io::Result<Option<char>> {
+        let Some(first) = self.next_byte()? else {
+            return Ok(None);
+        };
+        let width = match first {
+            0x00..=0x7f => 1,
+            0xc0..=0xdf => 2,
+            0xe0..=0xef => 3,
+            _ => 4,
+        };
+        let mut bytes = [first, 0, 0, 0];
+        for b in bytes.iter_mut().take(width).skip(1) {
+            *b = self
+                .next_byte()?
+                .ok_or_else(|| to_io(corrupted("truncated UTF-8 in とうばんのしょ")))?;
+        }
+        str::from_utf8(&bytes[..width])
+            .ok()
+            .and_then(|s| s.chars().next())
+            .map(Some)
+            .ok_or_else(|| to_io(corrupted("invalid UTF-8 in とうばんのしょ")))
+    }
+
+    /// Take in the next digit; false at the end of the input.
+    fn fill(&mut self) -> io::Result<bool> {
+        let ch = loop {
+            match self.next_char()? {
+                Some(WORD_BREAK) => continue,
+                other => break other,
+            }
+        };
+        let Some(ch) = ch else {
+            // a lone trailing digit or non-zero padding bits are not base64url
+            if self.chars % 4 == 1 || self.acc != 0 {
+                return Err(to_io(corrupted(
+                    "base64url decode failed; maybe corrupted とうばんのしょ",
+                )));
+            }
+            return Ok(false);
+        };
+        self.chars += 1;
+        if self.chars > limits().max_book_len {
+            return Err(to_io(ToubanError::LimitExceeded(format!(
+                "とうばんのしょ が長すぎます（最大 {} 文字）",
+                limits().max_book_len
+            ))));
+        }
+        let digit = self.codec.char_digit(ch).ok_or_else(|| {
+            to_io(ToubanError::CorruptedBook(format!(
+                "invalid {} char encountered: {:?}",
+                self.codec.name(),
+                ch
+            )))
+        })?;
+        self.acc = (self.acc << 6) | digit;
+        self.bits += 6;
+        Ok(true)
+    }
+}
+
+impl<R: io::Read> io::Read for BookReader<'_, R> {
+    fn read(&mut self, out: &mut [u8]) -> io::Result<usize> {
+        let mut n = 0;
+        while n < out.len() {
+            if self.bits >= 8 {
+                self.bits -= 8;
+                out[n] = (self.acc >> self.bits) as u8;
+                self.acc &= (1 << self.bits) - 1;
+                n += 1;
+            } else if self.eof || !self.fill()? {
+                self.eof = true;
+                break;
+            }
+        }
+        Ok(n)
+    }
+}
+
+/// Streaming encoder: takes JSON bytes and writes them to `inner` as a
+/// とうばんのしょ string in `codec`'s alphabet (UTF-8). Call
+/// [`finish`](BookWriter::finish) to write the last digit.
+pub struct BookWriter<'a, W: io::Write> {
+    inner: W,
+    codec: &'a dyn BookCodec,
+    acc: u32,
+    bits: u32,
+    chars: usize,
+}
+
+impl<'a, W: io::Write> BookWriter<'a, W> {
+    pub fn new(inner: W, codec: &'a dyn BookCodec) -> Self {
+        BookWriter {
+            inner,
+            codec,
+            acc: 0,
+            bits: 0,
+            chars: 0,
+        }
+    }
+
+    fn put(&mut self, digit: u32, out: &mut String) -> io::Result<()> {
+        self.chars += 1;
+        if self.chars > limits().max_book_len {
+            return Err(to_io(ToubanError::LimitExceeded(format!(
+                "とうばんのしょ が長すぎます（最大 {} 文字）",
+                limits().max_book_len
+            ))));
+        }
+        out.push(self.codec.digit_char(digit));
+        Ok(())
+    }
+
+    /// Write the final, zero-padded digit and hand back the inner writer.
+    pub fn finish(mut self) -> Result<W> {
+        if self.bits > 0 {
+            let mut out = String::new();
+            let digit = (self.acc << (6 - self.bits)) & (BASE64_LEN - 1);
+            self.put(digit, &mut out).map_err(from_io)?;
+            self.inner.write_all(out.as_bytes())?;
+        }
+        self.inner.flush()?;
+        Ok(self.inner)
+    }
+}
+
+impl<W: io::Write> io::Write for BookWriter<'_, W> {
+    fn write(&mut self, bytes: &[u8]) -> io::Result<usize> {
+        let mut out = String::with_capacity(bytes.len() * 4);
+        for &b in bytes {
+            self.acc = (self.acc << 8) | u32::from(b);
+            self.bits += 8;
+            while self.bits >= 6 {
+                self.bits -= 6;
+                self.put(self.acc >> self.bits, &mut out)?;
+                self.acc &= (1 << self.bits) - 1;
+            }
+        }
+        self.inner.write_all(out.as_bytes())?;
+        Ok(bytes.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+/// Marker [`break_words`] inserts; it is in none of the codec alphabets and
+/// decoding drops it.
+pub const WORD_BREAK: char = '・';
+
+/// Words a long random string should not spell out in a public channel.
+pub const AVOIDED_WORDS: &[&str] = &[
+    "しね",
+    "しぬ",
+    "ばか",
+    "あほ",
+    "くそ",
+    "ぶす",
+    "はげ",
+    "でぶ",
+    "うざい",
+    "ぼけ",
+    "かす",
+    "くず",
+    "ちび",
+    "ぶさいく",
+    "うそつき",
+    "どけち",
+];
+
+/// Break up every occurrence of an [`AVOIDED_WORDS`] entry by putting a
+/// [`WORD_BREAK`] after its first character. The result decodes to the
+/// same book.
+pub fn break_words(text: &str) -> String {
+    let mut out = String::with_capacity(text.len());
+    for (i, ch) in text.char_indices() {
+        out.push(ch);
+        if AVOIDED_WORDS.iter().any(|w| text[i..].starts_with(w)) {
+            out.push(WORD_BREAK);
+        }
+    }
+    out
+}
+
+/// Drop the [`WORD_BREAK`]s from `text`.
+pub fn strip_word_breaks(text: &str) -> Cow<'_, str> {
+    if text.contains(WORD_BREAK) {
+        Cow::Owned(text.chars().filter(|&c| c != WORD_BREAK).collect())
+    } else {
+        Cow::Borrowed(text)
+    }
+}
+
+// --------------------- Encode / Decode Book ---------------------
+/// Check `book` against the limits installed with [`set_limits`].
+pub fn check_limits(book: &Book) -> Result<()> {
+    let lim = limits();
+    if book.members.len() > lim.max_members {
+        return Err(ToubanError::LimitExceeded(format!(
+            "メンバーが多すぎます（{} 人、最大 {} 人）",
+            book.members.len(),
+            lim.max_members
+        )));
+    }
+    if let Some(m) = book
+        .members
+        .iter()
+        .find(|m| m.name.chars().count() > lim.max_name_len)
+    {
+        return Err(ToubanError::LimitExceeded(format!(
+            "名前「{}」が長すぎます（最大 {} 文字）",
+            m.name, lim.max_name_len
+        )));
+    }
+    if book.duties.len() > lim.max_duties {
+        return Err(ToubanError::LimitExceeded(format!(
+            "当番が多すぎます（{} 件、最大 {} 件）",
+            book.duties.len(),
+            lim.max_duties
+        )));
+    }
+    Ok(())
+}
+
+fn check_book_len(hira: &str) -> Result<()> {
+    let len = hira.chars().count();
+    if len > limits().max_book_len {
+        return Err(ToubanError::LimitExceeded(format!(
+            "とうばんのしょ が長すぎます（{} 文字、最大 {} 文字）",
+            len,
+            limits().max_book_len
+        )));
+    }
+    Ok(())
+}
+
+/// Encode `book` into its とうばんのしょ string, in hiragana.
+///
+/// The encoding is canonical: two books with the same logical content always
+/// produce the same string. Fields are written in declaration order, fields
+/// holding their default value (or the value it stands for, like a weight
+/// of 100) are omitted, sets (tags, required tags, shift preferences,
+/// exemption rules, conflicts and pairs) are written sorted, maps (role
+/// counts) are ordered, and no floating-point values are stored. Lists whose
+/// order means something (roles, which break ties in that order, the
+/// checklist, members) are kept as they are. Decoding and re-encoding a canonical string returns it
+/// unchanged, which makes the string usable for dedupe and hash chains.
+pub fn encode_book(book: &Book) -> Result<String> {
+    encode_book_with(book, &HIRAGANA)
+}
+
+/// Encode a changed `book` as its next revision, in `codec`'s alphabet.
+/// Every write of an updated book (the CLI and the bindings alike) goes
+/// through here so that [`Book::revision`] advances with each change;
+/// [`encode_book_with`] writes a book as it is.
+pub fn encode_book_update(book: &Book, codec: &dyn BookCodec) -> Result<String> {
+    let mut book = book.clone();
+    book.revision = book.revision.wrapping_add(1);
+    encode_book_with(&book, codec)
+}
+
+/// Encode `book` using the alphabet of `codec`.
+pub fn encode_book_with(book: &Book, codec: &dyn BookCodec) -> Result<String> {
+    check_limits(book)?;
+    let json = serde_json::to_vec(book)
+        .map_err(|e| ToubanError::Invalid(format!("serialize book to json: {}", e)))?;
+    let b64 = URL_SAFE_NO_PAD.encode(&json);
+    let text: String = base64url_chars_to_text(codec, b64.chars()).collect::<Result<_>>()?;
+    check_book_len(&text)?;
+    Ok(text)
+}
+
+fn corrupted(msg: &str) -> ToubanError {
+    ToubanError::CorruptedBook(msg.to_string())
+}
+
+/// Decode a とうばんのしょ string down to its JSON bytes, probing the
+/// built-in codecs for its alphabet (hiragana when none matches).
+pub fn decode_book_bytes(text: &str) -> Result<Vec<u8>> {
+    let text = strip_word_breaks(text);
+    decode_unbroken(&text, detect_codec(&text).unwrap_or(&HIRAGANA))
+}
+
+/// Decode a string written in `codec`'s alphabet down to its JSON bytes.
+pub fn decode_book_bytes_with(text: &str, codec: &dyn BookCodec) -> Result<Vec<u8>> {
+    decode_unbroken(&strip_word_breaks(text), codec)
+}
+
+// `text` has already had its word breaks stripped.
+fn decode_unbroken(text: &str, codec: &dyn BookCodec) -> Result<Vec<u8>> {
+    check_book_len(text)?;
+    let b64: String = text_chars_to_base64url(codec, text.chars()).collect::<Result<_>>()?;
+    URL_SAFE_NO_PAD
+        .decode(&b64)
+        .map_err(|_| corrupted("base64url decode failed; maybe corrupted とうばんのしょ"))
+}
+
+/// Decode a とうばんのしょ string into a [`Book`].
+pub fn decode_book(hira: &str) -> Result<Book> {
+    let bytes = decode_book_bytes(hira)?;
+    serde_json::from_slice::<Book>(&bytes).map_err(|_| corrupted("json decode failed"))
+}
+
+/// Decode a とうばんのしょ string in `codec`'s alphabet straight from `r`,
+/// e.g. a request body, without collecting the string or its JSON first.
+pub fn decode_book_from<R: io::Read>(r: R, codec: &dyn BookCodec) -> Result<Book> {
+    serde_json::from_reader(BookReader::new(r, codec)).map_err(|e| {
+        if e.is_io() {
+            from_io(e.into())
+        } else {
+            corrupted("json decode failed")
+        }
+    })
+}
+
+/// Encode `book` in `codec`'s alphabet straight into `w`; returns `w`.
+pub fn encode_book_to<W: io::Write>(book: &Book, codec: &dyn BookCodec, w: W) -> Result<W> {
+    check_limits(book)?;
+    let mut out = BookWriter::new(w, codec);
+    serde_json::to_writer(&mut out, book).map_err(|e| {
+        if e.is_io() {
+            from_io(e.into())
+        } else {
+            ToubanError::Invalid(format!("serialize book to json: {}", e))
+        }
+    })?;
+    out.finish()
+}
+
+/// Groups of hiragana that are easily confused when a string is retyped or
+/// read aloud: small/large vowels and っ, and voiced/unvoiced pairs.
+const KANA_CONFUSIONS: [&str; 25] = [
+    "ぁあ",
+    "ぃい",
+    "ぅう",
+    "ぇえ",
+    "ぉお",
+    "かが",
+    "きぎ",
+    "くぐ",
+    "けげ",
+    "こご",
+    "さざ",
+    "しじ",
+    "すず",
+    "せぜ",
+    "そぞ",
+    "ただ",
+    "ちぢ",
+    "っつづ",
+    "てで",
+    "とど",
+    "はばぱ",
+    "ひびぴ",
+    "ふぶぷ",
+    "へべぺ",
+    "ほぼぽ",
+];
+const MAX_CORRECTIONS: usize = 3;
+const REPAIR_BREADTH: usize = 4; // substitutions tried at each step
+
+/// One character changed by [`decode_book_lenient`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Correction {
+    /// Index of the character (in chars, from 0)
+    pub pos: usize,
+    pub from: char,
+    pub to: char,
+}
+
+fn kana_confusions(ch: char) -> impl Iterator<Item = char> {
+    KANA_CONFUSIONS
+        .iter()
+        .find(|g| g.contains(ch))
+        .into_iter()
+        .flat_map(|g| g.chars())
+        .filter(move |&c| c != ch)
+}
+
+const DECODED: (u8, usize) = (3, 0);
+
+/// How far strict decoding of `chars` gets: the stage that fails (0 = kana
+/// and base64, 1 = JSON, 2 = canonical form) and the char index it fails
+/// at. A string that decodes to a canonical book reaches [`DECODED`].
+fn decode_reach(chars: &[char]) -> (u8, usize) {
+    let mut b64 = String::with_capacity(chars.len());
+    for (i, &ch) in chars.iter().enumerate() {
+        match HIRAGANA.char_digit(ch) {
+            Some(d) => b64.push(base64url_digit_char(d)),
+            None => return (0, i),
+        }
+    }
+    let bytes = match URL_SAFE_NO_PAD.decode(&b64) {
+        Ok(bytes) => bytes,
+        Err(DecodeError::InvalidByte(i, _) | DecodeError::InvalidLastSymbol(i, _)) => {
+            return (0, i)
+        }
+        Err(_) => return (0, chars.len()),
+    };
+    let book = match serde_json::from_slice::<Book>(&bytes) {
+        // the JSON is a single line, so the column is the byte offset
+        Err(e) => return (1, e.column().saturating_sub(1) * 4 / 3),
+        Ok(book) => book,
+    };
+    let again: Vec<char> = match encode_book(&book) {
+        Ok(s) => s.chars().collect(),
+        Err(_) => return (2, 0),
+    };
+    match again.iter().zip(chars).position(|(a, b)| a != b) {
+        Some(i) => (2, i),
+        None if again.len() != chars.len() => (2, again.len().min(chars.len())),
+        None => DECODED,
+    }
+}
+
+/// Substitutions that let decoding get past `reach`, as (index,
+/// replacement, new reach): one that decodes outright first, then the
+/// [`REPAIR_BREADTH`] nearest to the failure. The whole string is searched,
+/// since some typos only surface later (a misspelt key is reported at the
+/// end of its object).
+fn repair_candidates(chars: &mut [char], reach: (u8, usize)) -> Vec<(usize, char, (u8, usize))> {
+    let mut found = Vec::new();
+    for i in 0..chars.len() {
+        let orig = chars[i];
+        for alt in kana_confusions(orig) {
+            chars[i] = alt;
+            let r = decode_reach(chars);
+            if r > reach {
+                found.push((i, alt, r));
+            }
+        }
+        chars[i] = orig;
+    }
+    found.sort_by_key(|&(i, _, r)| (r < DECODED, i.abs_diff(reach.1)));
+    found.truncate(REPAIR_BREADTH);
+    found
+}
+
+/// Depth-first search for at most `budget` substitutions that make `chars`
+/// decode, recording them in `fixes`.
+fn repair(
+    chars: &mut [char],
+    reach: (u8, usize),
+    budget: usize,
+    fixes: &mut Vec<Correction>,
+) -> bool {
+    if reach == DECODED {
+        return true;
+    }
+    if budget == 0 {
+        return false;
+    }
+    for (i, alt, r) in repair_candidates(chars, reach) {
+        let from = chars[i];
+        chars[i] = alt;
+        fixes.push(Correction {
+            pos: i,
+            from,
+            to: alt,
+        });
+        if repair(chars, r, budget - 1, fixes) {
+            return true;
+        }
+        fixes.pop();
+        chars[i] = from;
+    }
+    false
+}
+
+/// Decode a hiragana とうばんのしょ, repairing up to a few typos between
+/// easily confused kana (ぁ/あ, か/が, は/ば/ぱ, ...) when strict decoding
+/// fails.
+///
+/// The repaired string must be in canonical form, i.e. exactly what
+/// [`encode_book`] would produce for the decoded book, which rules out most
+/// wrong guesses. A typo that still decodes (inside a name, say) cannot be
+/// told from the real content and is left alone. On failure the error of
+/// the strict decode is returned.
+pub fn decode_book_lenient(hira: &str) -> Result<(Book, Vec<Correction>)> {
+    let err = match decode_book(hira) {
+        Ok(book) => return Ok((book, Vec::new())),
+        Err(e) => e,
+    };
+    let (fixed, fixes) = repaired(hira).ok_or(err)?;
+    Ok((decode_book(&fixed)?, fixes))
+}
+
+/// [`decode_book_lenient`] down to the JSON bytes, for [`view_book`].
+pub fn decode_book_bytes_lenient(hira: &str) -> Result<(Vec<u8>, Vec<Correction>)> {
+    let err = match decode_book_bytes(hira) {
+        Ok(bytes) if serde_json::from_slice::<Book>(&bytes).is_ok() => {
+            return Ok((bytes, Vec::new()))
+        }
+        Ok(_) => corrupted("json decode failed"),
+        Err(e) => e,
+    };
+    let (fixed, fixes) = repaired(hira).ok_or(err)?;
+    Ok((decode_book_bytes(&fixed)?, fixes))
+}
+
+/// `hira` with the typos [`repair`] finds fixed, and the fixes; `None` if
+/// no few substitutions make it decode.
+fn repaired(hira: &str) -> Option<(String, Vec<Correction>)> {
+    let mut chars: Vec<char> = strip_word_breaks(hira).chars().collect();
+    let mut fixes = Vec::new();
+    let reach = decode_reach(&chars);
+    if !repair(&mut chars, reach, MAX_CORRECTIONS, &mut fixes) {
+        return None;
+    }
+    Some((chars.into_iter().collect(), fixes))
+}
+
+/// Parse the JSON of a decoded book into a view borrowing from `bytes`.
+pub fn view_book(bytes: &[u8]) -> Result<BookView<'_>> {
+    serde_json::from_slice::<BookView>(bytes).map_err(|_| corrupted("json decode failed"))
+}
+
+// --------------------- Utilities ---------------------
+/// Current Unix time in seconds.
+#[cfg(all(feature = "wasm-bindgen", target_arch = "wasm32"))]
+pub fn unix_now() -> u64 {
+    // std has no clock on wasm32-unknown-unknown
+    (js_sys::Date::now() / 1000.0) as u64
+}
+
+/// Current Unix time in seconds.
+#[cfg(not(all(feature = "wasm-bindgen", target_arch = "wasm32")))]
+pub fn unix_now() -> u64 {
+    use std::time::{SystemTime, UNIX_EPOCH};
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or(0)
+}
+
+// --------------------- Rules / Lint ---------------------
+/// Parse an exemption rule into its week-of-month and weekday parts; at
+/// least one of them is present.
+pub fn parse_exempt_rule(rule: &str) -> Result<(Option<u32>, Option<Weekday>)> {
+    let bad = || {
+        ToubanError::Invalid(format!(
+            "「{}」は除外ルールとして読めません（例: week1, fri, week2-fri）",
+            rule
+        ))
+    };
+    let mut week = None;
+    let mut weekday = None;
+    for part in rule.split('-') {
+        if let Some(n) = part.strip_prefix("week") {
+            let n: u32 = n.parse().map_err(|_| bad())?;
+            if week.is_some() || !(1..=5).contains(&n) {
+                return Err(bad());
+            }
+            week = Some(n);
+        } else {
+            let d: Weekday = part.parse().map_err(|_| bad())?;
+            if weekday.is_some() {
+                return Err(bad());
+            }
+            weekday = Some(d);
+        }
+    }
+    Ok((week, weekday))
+}
+
+fn exempt_on(rule: &str, date: NaiveDate) -> bool {
+    match parse_exempt_rule(rule) {
+        Ok((week, weekday)) => {
+            week.is_none_or(|w| (date.day() - 1) / 7 + 1 == w)
+                && weekday.is_none_or(|d| date.weekday() == d)
+        }
+        Err(_) => false,
+    }
+}
+
+/// Members whose exemption rules cover today.
+pub fn exempt_today(book: &Book) -> Vec<usize> {
+    exempt_on_date(book, Local::now().date_naive())
+}
+
+/// Members whose exemption rules cover `date`.
+pub fn exempt_on_date(book: &Book, date: NaiveDate) -> Vec<usize> {
+    (0..book.members.len())
+        .filter(|&i| book.members[i].exempt.iter().any(|r| exempt_on(r, date)))
+        .collect()
+}
+
+/// The local date of a unix time (seconds).
+pub fn local_date(unix: u64) -> Option<NaiveDate> {
+    Local
+        .timestamp_opt(unix as i64, 0)
+        .single()
+        .map(|d| d.date_naive())
+}
+
+/// The latest planned round whose period has begun by `date`.
+pub fn planned_on(book: &Book, date: NaiveDate) -> Option<&PlannedRound> {
+    book.planned
+        .iter()
+        .rev()
+        .find(|p| local_date(p.at).is_some_and(|d| d <= date))
+}
+
+/// One interval after the latest assignment, if anyone was ever assigned.
+/// Planned rounds count as assigned.
+pub fn next_due_date(book: &Book) -> Option<NaiveDate> {
+    book.members
+        .iter()
+        .filter_map(|m| m.last_assigned_at)
+        .max()
+        .and_then(local_date)
+        .map(|d| d + chrono::Days::new(book.interval as u64))
+}
+
+/// Walk the coming year of periods and return the first date on which the
+/// exemption rules leave fewer members than the headcount, with how many
+/// are left. Periods start one interval after the latest assignment.
+pub fn capacity_shortfall(book: &Book) -> Option<(NaiveDate, usize)> {
+    if book.interval == 0 || book.people > book.members.len() {
+        return None;
+    }
+    let start = next_due_date(book).unwrap_or_else(|| Local::now().date_naive());
+    let end = start + chrono::Days::new(365);
+    let mut date = start;
+    while date <= end {
+        let available = book
+            .members
+            .iter()
+            .filter(|m| !m.exempt.iter().any(|r| exempt_on(r, date)))
+            .count();
+        if available < book.people {
+            return Some((date, available));
+        }
+        date = date + chrono::Days::new(book.interval as u64);
+    }
+    None
+}
+
+/// Problems that do not stop a book from being used, reported before
+/// commands run.
+pub fn book_warnings(book: &Book) -> Vec<Warning> {
+    let members: Vec<_> = book
+        .members
+        .iter()
+        .map(|m| (m.name.as_str(), m.count, m.last_assigned_at))
+        .collect();
+    warnings_of(
+        book.people,
+        book.interval,
+        book.reset_policy
+            .is_default()
+            .then(|| book.reset_threshold()),
+        &members,
+    )
+}
+
+/// The warnings of a book given its settings and each member's name, count
+/// and last assignment; `threshold` is the count members should stay under,
+/// if any.
+fn warnings_of(
+    people: usize,
+    interval: usize,
+    threshold: Option<u8>,
+    members: &[(&str, u32, Option<u64>)],
+) -> Vec<Warning> {
+    let mut warnings = Vec::new();
+    if people > members.len() {
+        warnings.push(Warning::PeopleExceedsMembers {
+            people,
+            members: members.len(),
+        });
+    }
+    for (i, &(name, count, _)) in members.iter().enumerate() {
+        if members[..i].iter().any(|o| o.0 == name) {
+            warnings.push(Warning::DuplicateName {
+                name: name.to_string(),
+            });
+        }
+        if let Some(threshold) = threshold.filter(|&t| count > t as u32) {
+            warnings.push(Warning::CountAboveThreshold {
+                name: name.to_string(),
+                count,
+                threshold,
+            });
+        }
+    }
+    if let Some(last) = members.iter().filter_map(|m| m.2).max() {
+        let days = unix_now().saturating_sub(last) / (24 * 60 * 60);
+        if days > interval.max(1) as u64 * STALE_INTERVALS {
+            warnings.push(Warning::StaleAssignment { days });
+        }
+    }
+    warnings
+}
+
+/// Every consistency problem in `book`: the warnings plus rules that can
+/// never be satisfied and members or references that can never take effect.
+pub fn lint_book(book: &Book) -> Vec<String> {
+    let mut problems: Vec<String> = book_warnings(book).iter().map(|w| w.to_string()).collect();
+    if book.people == 0 {
+        problems.push("とうばん人数が 0 人です".to_string());
+    }
+    if book.members.iter().any(|m| m.name.trim().is_empty()) {
+        problems.push("名前が空のメンバーがいます".to_string());
+    }
+    if let Some((date, available)) = capacity_shortfall(book) {
+        problems.push(format!(
+            "{} のとうばんは 除外ルールのため 選べる人 ({}) が人数 ({}) より少なくなります",
+            date.format("%Y-%m-%d"),
+            available,
+            book.people
+        ));
+    }
+    let everyone: Vec<usize> = (0..book.members.len()).collect();
+    if let Err(e) = check_required_tags(book, &everyone, &expand_required_tags(book)) {
+        problems.push(e.to_string());
+    }
+    if book.people == 1 {
+        for m in book.members.iter().filter(|m| m.onboarding > 0) {
+            problems.push(format!(
+                "とうばん人数が 1 人なので 新人の「{}」は選ばれません",
+                m.name
+            ));
+        }
+    }
+    let shift_people: usize = book.shifts.iter().map(|s| s.people).sum();
+    if !book.shifts.is_empty() && shift_people != book.people {
+        problems.push(format!(
+            "シフトの人数の合計 ({}) がとうばん人数 ({}) と合いません",
+            shift_people, book.people
+        ));
+    }
+    for s in &book.shifts {
+        let able = book
+            .members
+            .iter()
+            .filter(|m| !m.avoid_shifts.contains(&s.name))
+            .count();
+        if able < s.people {
+            problems.push(format!(
+                "シフト「{}」に入れる人 ({}) が必要な人数 ({}) より少ないです",
+                s.name, able, s.people
+            ));
+        }
+    }
+    if book.roles.len() > book.people {
+        problems.push(format!(
+            "役割 ({}) がとうばん人数 ({}) より多く、配られない役割があります",
+            book.roles.len(),
+            book.people
+        ));
+    }
+    let known = |name: &String| book.members.iter().any(|m| m.name == *name);
+    let dangling = book
+        .current
+        .iter()
+        .map(|a| &a.name)
+        .chain(book.pending.iter().flat_map(|p| &p.members))
+        .chain(book.trades.iter().map(|t| &t.member))
+        .filter(|n| !known(n));
+    for name in dangling {
+        problems.push(format!("いないメンバー「{}」が参照されています", name));
+    }
+    for m in &book.members {
+        for rule in &m.exempt {
+            if let Err(e) = parse_exempt_rule(rule) {
+                problems.push(format!("「{}」: {}", m.name, e));
+            }
+        }
+    }
+    problems
+}
+
+/// Look a member up by name.
+pub fn find_member_mut<'a>(book: &'a mut Book, name: &str) -> Result<&'a mut Member> {
+    book.members
+        .iter_mut()
+        .find(|m| m.name == name)
+        .ok_or_else(|| ToubanError::MemberNotFound(name.to_string()))
+}
+
+/// Whether `a` and `b` are linked in a list of member pairs, either way round.
+fn linked(pairs: &[(String, String)], a: &str, b: &str) -> bool {
+    pairs
+        .iter()
+        .any(|(x, y)| (x == a && y == b) || (x == b && y == a))
+}
+
+fn unlink(pairs: &mut Vec<(String, String)>, a: &str, b: &str) -> Result<()> {
+    if !linked(pairs, a, b) {
+        return Err(ToubanError::Invalid(format!(
+            "「{}」と「{}」の組み合わせは登録されていません",
+            a, b
+        )));
+    }
+    pairs.retain(|(x, y)| !((x == a && y == b) || (x == b && y == a)));
+    Ok(())
+}
+
+/// Who is on duty on `date` when `scheduled` were drawn for it: the
+/// overrides covering that date apply oldest first, so a later one can
+/// cover for whoever an earlier one put in.
+pub fn on_duty(book: &Book, date: NaiveDate, scheduled: &[String]) -> Vec<String> {
+    let mut names = scheduled.to_vec();
+    for o in book.overrides.iter().filter(|o| o.covers(date)) {
+        match &o.replaces {
+            Some(r) => {
+                for n in names.iter_mut().filter(|n| *n == r) {
+                    *n = o.member.clone();
+                }
+            }
+            None => names = vec![o.member.clone()],
+        }
+    }
+    let mut seen = BTreeSet::new();
+    names.retain(|n| seen.insert(n.clone()));
+    names
+}
+
+/// Members linked to `i` through [`Book::pairs`], directly or in a chain,
+/// `i` first.
+pub fn pair_group(book: &Book, i: usize) -> Vec<usize> {
+    let mut group = vec![i];
+    let mut k = 0;
+    while k < group.len() {
+        let name = &book.members[group[k]].name;
+        for (j, m) in book.members.iter().enumerate() {
+            if !group.contains(&j) && book.in_pair(name, &m.name) {
+                group.push(j);
+            }
+        }
+        k += 1;
+    }
+    group
+}
+
+/// Starting count for a new member: the average of the pool they join.
+fn joining_count(book: &Book, substitute: bool) -> u32 {
+    let pool: Vec<&Member> = book
+        .members
+        .iter()
+        .filter(|m| m.substitute == substitute)
+        .collect();
+    if pool.is_empty() {
+        0
+    } else {
+        let s: usize = pool.iter().map(|m| m.count as usize).sum();
+        ((s as f64) / (pool.len() as f64)).round() as u32
+    }
+}
+
+// --------------------- Selection ---------------------
+/// Bring the counts of `pool` down according to the book's
+/// [`ResetPolicy`]; returns whether any count changed.
+pub fn reset_counts(book: &mut Book, pool: &[usize]) -> bool {
+    match book.reset_policy {
+        ResetPolicy::Zero => {}
+        ResetPolicy::Subtract => {
+            let min = pool.iter().map(|&i| book.members[i].count).min();
+            return match min {
+                Some(min) if min > 0 => {
+                    for &i in pool {
+                        book.members[i].count -= min;
+                    }
+                    true
+                }
+                _ => false,
+            };
+        }
+        ResetPolicy::Never => return false,
+    }
+    if pool
+        .iter()
+        .map(|&i| book.members[i].count)
+        .max()
+        .unwrap_or(0)
+        < book.reset_threshold() as u32
+    {
+        return false;
+    }
+    for &i in pool {
+        book.members[i].count = 0;
+    }
+    true
+}
+
+/// Level the counts after members joined or left mid-cycle, so everyone
+/// can expect the same load from here on. Each pool keeps its mean
+/// [`Member::load`], so the cycle is as far along as before, and every
+/// member's count becomes that mean scaled by their own weight, rounded.
+/// Under [`ResetPolicy::Zero`] counts stay below the threshold, so leveling
+/// never forces a reset by itself. Returns the members whose count changed,
+/// with their count from before.
+pub fn rebalance_counts(book: &mut Book) -> Vec<(usize, u32)> {
+    let (regulars, substitutes) = member_pools(book, &[]);
+    let cap = if book.reset_policy.is_default() {
+        book.reset_threshold().saturating_sub(1) as u32
+    } else {
+        u32::MAX
+    };
+    let mut changed = Vec::new();
+    for pool in [regulars, substitutes] {
+        if pool.is_empty() {
+            continue;
+        }
+        let total: u64 = pool.iter().map(|&i| book.members[i].load()).sum();
+        let mean = total as f64 / pool.len() as f64;
+        for i in pool {
+            let m = &mut book.members[i];
+            let count = (mean * m.weight() as f64 / 10_000.0)
+                .round()
+                .min(cap as f64) as u32;
+            if count != m.count {
+                changed.push((i, m.count));
+                m.count = count;
+            }
+        }
+    }
+    changed
+}
+
+/// 64-bit FNV-1a; stable across platforms and releases, unlike std's hasher.
+fn fnv1a(bytes: &[u8]) -> u64 {
+    bytes.iter().fold(0xcbf29ce484222325, |h, &b| {
+        (h ^ b as u64).wrapping_mul(0x100000001b3)
+    })
+}
+
+/// Selection weight for the anti-streak option: grows linearly with the
+/// time since the member last served, reaching 1.0 once a full rotation
+/// (interval × members / people) has passed.
+fn streak_weight(book: &Book, m: &Member, now: u64) -> f64 {
+    let Some(last) = m.last_assigned_at else {
+        return 1.0;
+    };
+    let regulars = book.members.iter().filter(|m| !m.substitute).count();
+    let rotation = (regulars as f64 / book.people.max(1) as f64).max(1.0);
+    let horizon = (book.interval.max(1) * 24 * 60 * 60) as f64 * rotation;
+    (now.saturating_sub(last) as f64 / horizon).clamp(0.05, 1.0)
+}
+
+/// Draw up to `n` members of `pool`, lowest [`Member::load`] first. When
+/// the members at the minimum cannot fill `n` seats, the next-lowest loads
+/// fill the rest.
+fn draw_min_count<R: Rng + ?Sized>(
+    book: &Book,
+    pool: &[usize],
+    n: usize,
+    rng: &mut R,
+) -> Vec<usize> {
+    let mut loads: Vec<u64> = pool.iter().map(|&i| book.members[i].load()).collect();
+    loads.sort_unstable();
+    loads.dedup();
+    let mut picked = Vec::new();
+    for c in loads {
+        if picked.len() >= n {
+            break;
+        }
+        // collect candidates (by index to later update counts)
+        let tier: Vec<usize> = pool
+            .iter()
+            .copied()
+            .filter(|&i| book.members[i].load() == c)
+            .collect();
+        let mut tier = order_tier(book, tier, rng);
+        tier.truncate(n - picked.len());
+        picked.extend(tier);
+    }
+    picked
+}
+
+/// Order members with equal counts by the book's tie-break.
+fn order_tier<R: Rng + ?Sized>(
+    book: &Book,
+    mut candidates_idx: Vec<usize>,
+    rng: &mut R,
+) -> Vec<usize> {
+    if !book.tie_break.is_random() {
+        match book.tie_break {
+            TieBreak::Name => {
+                candidates_idx.sort_by(|&a, &b| book.members[a].name.cmp(&book.members[b].name))
+            }
+            TieBreak::Hash => {
+                candidates_idx.sort_by_key(|&i| fnv1a(book.members[i].name.as_bytes()))
+            }
+            TieBreak::Insertion | TieBreak::Random => {}
+        }
+    } else if book.anti_streak {
+        // weighted shuffle (Efraimidis-Spirakis): recent assignees sink
+        let now = unix_now();
+        let mut keyed: Vec<(f64, usize)> = candidates_idx
+            .iter()
+            .map(|&i| {
+                let w = streak_weight(book, &book.members[i], now);
+                (rng.gen::<f64>().powf(1.0 / w), i)
+            })
+            .collect();
+        keyed.sort_by(|a, b| b.0.total_cmp(&a.0));
+        candidates_idx = keyed.into_iter().map(|(_, i)| i).collect();
+    } else {
+        candidates_idx.shuffle(rng);
+    }
+    candidates_idx
+}
+
+/// The book's required tags with group patterns expanded: "floor:*"
+/// stands for every distinct member tag starting with "floor:".
+pub fn expand_required_tags(book: &Book) -> Vec<String> {
+    let mut out: Vec<String> = Vec::new();
+    for rule in &book.required_tags {
+        let tags: Vec<&String> = match rule.strip_suffix('*') {
+            Some(prefix) => {
+                let mut group: Vec<&String> = book
+                    .members
+                    .iter()
+                    .flat_map(|m| &m.tags)
+                    .filter(|t| t.starts_with(prefix))
+                    .collect();
+                group.sort();
+                group
+            }
+            None => vec![rule],
+        };
+        for t in tags {
+            if !out.contains(t) {
+                out.push(t.clone());
+            }
+        }
+    }
+    out
+}
+
+/// Fail early, naming the culprit, when no draw from `available` can cover
+/// `required` within the book's headcount.
+fn check_required_tags(book: &Book, available: &[usize], required: &[String]) -> Result<()> {
+    let has_tag = |i: usize, tag: &String| book.members[i].tags.contains(tag);
+    for tag in required {
+        if !available.iter().any(|&i| has_tag(i, tag)) {
+            return Err(ToubanError::Unsatisfiable(format!(
+                "タグ「{}」のメンバーがいません",
+                tag
+            )));
+        }
+    }
+    // greedy set cover gives the headcount needed to honor every tag
+    let mut missing: Vec<&String> = required.iter().collect();
+    let mut needed = 0;
+    while !missing.is_empty() {
+        let best = available
+            .iter()
+            .map(|&i| missing.iter().filter(|t| has_tag(i, t)).count())
+            .max()
+            .unwrap_or(0);
+        let Some(&i) = available
+            .iter()
+            .find(|&&i| missing.iter().filter(|t| has_tag(i, t)).count() == best)
+        else {
+            break;
+        };
+        missing.retain(|t| !has_tag(i, t));
+        needed += 1;
+    }
+    if needed > book.people {
+        return Err(ToubanError::Unsatisfiable(format!(
+            "必須タグ（{}）を満たすには {} 人以上必要ですが、とうばん人数は {} 人です",
+            required.join(", "),
+            needed,
+            book.people
+        )));
+    }
+    Ok(())
+}
+
+/// Whether `i` is the only selected member carrying one of the required tags.
+fn is_sole_tag_holder(book: &Book, required: &[String], selected: &[usize], i: usize) -> bool {
+    let has_tag = |j: usize, tag: &String| book.members[j].tags.contains(tag);
+    required
+        .iter()
+        .any(|t| has_tag(i, t) && selected.iter().filter(|&&j| has_tag(j, t)).count() == 1)
+}
+
+/// Make sure `selected` covers every required tag, swapping in the
+/// lowest-count holder of a missing tag when needed.
+fn enforce_required_tags<R: Rng + ?Sized>(
+    book: &Book,
+    required: &[String],
+    regulars: &[usize],
+    substitutes: &[usize],
+    selected: &mut Vec<usize>,
+    rng: &mut R,
+) -> Result<()> {
+    let has_tag = |i: usize, tag: &String| book.members[i].tags.contains(tag);
+    for tag in required {
+        if selected.iter().any(|&i| has_tag(i, tag)) {
+            continue;
+        }
+        let holders = |pool: &[usize]| -> Vec<usize> {
+            pool.iter()
+                .copied()
+                .filter(|&i| has_tag(i, tag) && !selected.contains(&i))
+                .collect()
+        };
+        let mut pool = holders(regulars);
+        if pool.is_empty() {
+            pool = holders(substitutes);
+        }
+        let pick = *draw_min_count(book, &pool, 1, rng).first().ok_or_else(|| {
+            ToubanError::Unsatisfiable(format!("タグ「{}」のメンバーがいません", tag))
+        })?;
+        if selected.len() < book.people {
+            selected.push(pick);
+            continue;
+        }
+        // replace a pick that no other required tag depends on
+        let pos = selected
+            .iter()
+            .rposition(|&i| !is_sole_tag_holder(book, required, selected, i))
+            .ok_or_else(|| {
+                ToubanError::Unsatisfiable(
+                    "とうばん人数が少なすぎて 必須タグを満たせません".to_string(),
+                )
+            })?;
+        selected[pos] = pick;
+    }
+    Ok(())
+}
+
+fn seated_count(book: &Book, selected: &[usize]) -> usize {
+    assign_shifts(book, selected)
+        .iter()
+        .filter(|s| s.is_some())
+        .count()
+}
+
+/// Swap out picks that every open shift seat avoids for members who can
+/// take one, drawing replacements like any other pick.
+fn enforce_shift_avoids<R: Rng + ?Sized>(
+    book: &Book,
+    required: &[String],
+    regulars: &[usize],
+    substitutes: &[usize],
+    selected: &mut [usize],
+    rng: &mut R,
+) -> Result<()> {
+    let seats: usize = book.shifts.iter().map(|s| s.people).sum();
+    let stuck = || {
+        ToubanError::Unsatisfiable("選ばれたメンバーでは 埋められないシフトがあります（set-shift-prefs を見直してください）".to_string())
+    };
+    loop {
+        let seated = seated_count(book, selected);
+        if seated >= min(seats, selected.len()) {
+            return Ok(());
+        }
+        let shifts = assign_shifts(book, selected);
+        let pos = (0..selected.len())
+            .find(|&k| {
+                shifts[k].is_none() && !is_sole_tag_holder(book, required, selected, selected[k])
+            })
+            .ok_or_else(stuck)?;
+        let swapped = [regulars, substitutes].into_iter().find_map(|pool| {
+            let pool: Vec<usize> = pool
+                .iter()
+                .copied()
+                .filter(|i| !selected.contains(i))
+                .collect();
+            draw_min_count(book, &pool, pool.len(), rng)
+                .into_iter()
+                .find(|&c| {
+                    let mut trial = selected.to_vec();
+                    trial[pos] = c;
+                    seated_count(book, &trial) > seated
+                })
+        });
+        selected[pos] = swapped.ok_or_else(stuck)?;
+    }
+}
+
+/// Pull in the partners of everyone drawn, in place of unpaired picks. A
+/// group that cannot be completed this round (someone is away, or it does
+/// not fit) gives up its seats to unpaired members instead.
+fn enforce_pairs<R: Rng + ?Sized>(
+    book: &Book,
+    required: &[String],
+    regulars: &[usize],
+    substitutes: &[usize],
+    selected: &mut Vec<usize>,
+    rng: &mut R,
+) -> Result<()> {
+    if book.pairs.is_empty() {
+        return Ok(());
+    }
+    let stuck = || {
+        ToubanError::Unsatisfiable(
+            "組になっているメンバーをそろえて選べません（add-pair を見直してください）".to_string(),
+        )
+    };
+    let unpaired = |i: usize| pair_group(book, i).len() == 1;
+    // every pass settles one group, and a settled group stays settled
+    for _ in 0..book.members.len() {
+        let Some(group) = selected
+            .iter()
+            .map(|&i| pair_group(book, i))
+            .find(|g| g.iter().any(|j| !selected.contains(j)))
+        else {
+            return Ok(());
+        };
+        let missing: Vec<usize> = group
+            .iter()
+            .copied()
+            .filter(|j| !selected.contains(j))
+            .collect();
+        let available = |j: &usize| regulars.contains(j) || substitutes.contains(j);
+        if group.len() <= book.people && missing.iter().all(available) {
+            let mut trial = selected.clone();
+            for &p in &missing {
+                if trial.len() < book.people {
+                    trial.push(p);
+                } else if let Some(k) = (0..trial.len()).rev().find(|&k| {
+                    unpaired(trial[k]) && !is_sole_tag_holder(book, required, &trial, trial[k])
+                }) {
+                    trial[k] = p;
+                }
+            }
+            if missing.iter().all(|p| trial.contains(p))
+                && trial.iter().any(|&i| book.members[i].onboarding == 0)
+            {
+                *selected = trial;
+                continue;
+            }
+        }
+        for k in 0..selected.len() {
+            if !group.contains(&selected[k]) {
+                continue;
+            }
+            let pick = [regulars, substitutes].into_iter().find_map(|pool| {
+                let pool: Vec<usize> = pool
+                    .iter()
+                    .copied()
+                    .filter(|&i| unpaired(i) && !selected.contains(&i))
+                    .collect();
+                draw_min_count(book, &pool, 1, rng).first().copied()
+            });
+            selected[k] = pick.ok_or_else(stuck)?;
+        }
+    }
+    Err(stuck())
+}
+
+/// Swap out one member of every conflicting pair drawn together for
+/// someone who clashes with nobody already picked. Paired members are
+/// neither swapped out nor in.
+fn enforce_conflicts<R: Rng + ?Sized>(
+    book: &Book,
+    required: &[String],
+    regulars: &[usize],
+    substitutes: &[usize],
+    selected: &mut [usize],
+    rng: &mut R,
+) -> Result<()> {
+    let clashes = |sel: &[usize], i: usize| {
+        sel.iter()
+            .any(|&j| j != i && book.in_conflict(&book.members[i].name, &book.members[j].name))
+    };
+    let stuck = || {
+        ToubanError::Unsatisfiable(
+            "いっしょにできない組み合わせを避けて選べません（add-conflict を見直してください）"
+                .to_string(),
+        )
+    };
+    while selected.iter().any(|&i| clashes(selected, i)) {
+        let seated = seated_count(book, selected);
+        // either side of a pair may give way, the later pick first
+        let swap = (0..selected.len())
+            .rev()
+            .filter(|&k| {
+                clashes(selected, selected[k])
+                    && pair_group(book, selected[k]).len() == 1
+                    && !is_sole_tag_holder(book, required, selected, selected[k])
+            })
+            .find_map(|pos| {
+                [regulars, substitutes].into_iter().find_map(|pool| {
+                    let pool: Vec<usize> = pool
+                        .iter()
+                        .copied()
+                        .filter(|i| !selected.contains(i))
+                        .collect();
+                    draw_min_count(book, &pool, pool.len(), rng)
+                        .into_iter()
+                        .find(|&c| {
+                            let mut trial = selected.to_vec();
+                            trial[pos] = c;
+                            // without undoing what the earlier rules arranged
+                            !clashes(&trial, c)
+                                && pair_group(book, c).len() == 1
+                                && seated_count(book, &trial) >= seated
+                                && trial.iter().any(|&i| book.members[i].onboarding == 0)
+                        })
+                        .map(|c| (pos, c))
+                })
+            });
+        let (pos, c) = swap.ok_or_else(stuck)?;
+        selected[pos] = c;
+    }
+    Ok(())
+}
+
+/// Make sure new members never serve without an experienced member.
+fn enforce_onboarding_pairing<R: Rng + ?Sized>(
+    book: &Book,
+    required: &[String],
+    regulars: &[usize],
+    substitutes: &[usize],
+    selected: &mut Vec<usize>,
+    rng: &mut R,
+) -> Result<()> {
+    let is_new = |i: usize| book.members[i].onboarding > 0;
+    if selected.is_empty() || !selected.iter().all(|&i| is_new(i)) {
+        return Ok(());
+    }
+    let experienced = |pool: &[usize]| -> Vec<usize> {
+        pool.iter()
+            .copied()
+            .filter(|&i| !is_new(i) && !selected.contains(&i))
+            .collect()
+    };
+    let mut pool = experienced(regulars);
+    if pool.is_empty() {
+        pool = experienced(substitutes);
+    }
+    let pick = *draw_min_count(book, &pool, 1, rng)
+        .first()
+        .ok_or_else(|| ToubanError::Unsatisfiable("新人と組める経験者がいません".to_string()))?;
+    if selected.len() < book.people {
+        selected.push(pick);
+        return Ok(());
+    }
+    let pos = selected
+        .iter()
+        .rposition(|&i| !is_sole_tag_holder(book, required, selected, i))
+        .ok_or_else(|| {
+            ToubanError::Unsatisfiable(
+                "とうばん人数が少なすぎて 新人と経験者を組めません".to_string(),
+            )
+        })?;
+    selected[pos] = pick;
+    Ok(())
+}
+
+/// Hand the role slots out among `selected`, giving each role to the
+/// assignee who has filled it least often (earlier draws win ties).
+fn assign_roles(book: &Book, selected: &[usize]) -> Vec<Option<String>> {
+    let mut roles = vec![None; selected.len()];
+    for role in &book.roles {
+        let role_count = |i: usize| book.members[i].role_counts.get(role).copied().unwrap_or(0);
+        let slot = (0..selected.len())
+            .filter(|&k| roles[k].is_none())
+            .min_by_key(|&k| role_count(selected[k]));
+        match slot {
+            Some(k) => roles[k] = Some(role.clone()),
+            None => break,
+        }
+    }
+    roles
+}
+
+/// Try to seat a member on `seat`, moving whoever holds a candidate's seat
+/// elsewhere if needed (one augmenting-path step of bipartite matching).
+fn seat_member(
+    seat: usize,
+    candidates: &[Vec<usize>],
+    seat_of: &mut [Option<usize>],
+    visited: &mut [bool],
+) -> bool {
+    for &k in &candidates[seat] {
+        if visited[k] {
+            continue;
+        }
+        visited[k] = true;
+        let free = match seat_of[k] {
+            None => true,
+            Some(other) => seat_member(other, candidates, seat_of, visited),
+        };
+        if free {
+            seat_of[k] = Some(seat);
+            return true;
+        }
+    }
+    false
+}
+
+/// Fill each shift's seats in order. Members who avoid a shift are never
+/// put on it; among the rest, those who prefer it come first, then whoever
+/// has worked it least. Seats nobody can take are left empty.
+fn assign_shifts(book: &Book, selected: &[usize]) -> Vec<Option<String>> {
+    let seats: Vec<&String> = book
+        .shifts
+        .iter()
+        .flat_map(|s| std::iter::repeat_n(&s.name, s.people))
+        .collect();
+    let candidates: Vec<Vec<usize>> = seats
+        .iter()
+        .map(|&seat| {
+            let mut ks: Vec<usize> = (0..selected.len())
+                .filter(|&k| !book.members[selected[k]].avoid_shifts.contains(seat))
+                .collect();
+            ks.sort_by_key(|&k| {
+                let m = &book.members[selected[k]];
+                (
+                    !m.prefer_shifts.contains(seat),
+                    m.shift_counts.get(seat).copied().unwrap_or(0),
+                )
+            });
+            ks
+        })
+        .collect();
+    let mut seat_of = vec![None; selected.len()];
+    for seat in 0..seats.len() {
+        let mut visited = vec![false; selected.len()];
+        seat_member(seat, &candidates, &mut seat_of, &mut visited);
+    }
+    seat_of
+        .into_iter()
+        .map(|s| s.map(|s| seats[s].clone()))
+        .collect()
+}
+
+/// Split the members not in `excluded` into (regulars, substitutes).
+pub fn member_pools(book: &Book, excluded: &[usize]) -> (Vec<usize>, Vec<usize>) {
+    let (substitutes, regulars) = (0..book.members.len())
+        .filter(|i| !excluded.contains(i))
+        .partition(|&i| book.members[i].substitute);
+    (regulars, substitutes)
+}
+
+/// Draw this round's assignees, leaving out the members in `excluded`.
+pub fn select_members<R: Rng + ?Sized>(
+    book: &Book,
+    excluded: &[usize],
+    rng: &mut R,
+) -> Result<Vec<usize>> {
+    let (regulars, substitutes) = member_pools(book, excluded);
+    if regulars.is_empty() && substitutes.is_empty() {
+        return Err(ToubanError::Unsatisfiable(
+            "選べるメンバーがいません".to_string(),
+        ));
+    }
+    let required = expand_required_tags(book);
+    let available: Vec<usize> = regulars.iter().chain(&substitutes).copied().collect();
+    check_required_tags(book, &available, &required)?;
+    let mut selected_idx = draw_min_count(book, &regulars, book.people, rng);
+    // substitutes only cover seats the available regulars cannot fill
+    let shortfall = book.people.saturating_sub(regulars.len());
+    if shortfall > 0 {
+        selected_idx.extend(draw_min_count(book, &substitutes, shortfall, rng));
+    }
+    enforce_required_tags(
+        book,
+        &required,
+        &regulars,
+        &substitutes,
+        &mut selected_idx,
+        rng,
+    )?;
+    enforce_onboarding_pairing(
+        book,
+        &required,
+        &regulars,
+        &substitutes,
+        &mut selected_idx,
+        rng,
+    )?;
+    enforce_shift_avoids(
+        book,
+        &required,
+        &regulars,
+        &substitutes,
+        &mut selected_idx,
+        rng,
+    )?;
+    enforce_pairs(
+        book,
+        &required,
+        &regulars,
+        &substitutes,
+        &mut selected_idx,
+        rng,
+    )?;
+    enforce_conflicts(
+        book,
+        &required,
+        &regulars,
+        &substitutes,
+        &mut selected_idx,
+        rng,
+    )?;
+    Ok(selected_idx)
+}
+
+/// Picks who serves in a round. Implementations only choose; counting and
+/// recording the assignment is left to [`apply_assignment`].
+pub trait AssignmentStrategy {
+    /// Return the indices of this round's assignees, leaving out the
+    /// members in `excluded`.
+    fn select(&self, book: &Book, excluded: &[usize], rng: &mut dyn RngCore) -> Result<Vec<usize>>;
+}
+
+/// The default strategy: see [`select_members`].
+pub struct MinCount;
+
+impl AssignmentStrategy for MinCount {
+    fn select(&self, book: &Book, excluded: &[usize], rng: &mut dyn RngCore) -> Result<Vec<usize>> {
+        select_members(book, excluded, rng)
+    }
+}
+
+/// Strict roster order: the next `people` regular members from
+/// [`Book::rr_cursor`], wrapping around. Tags, pairing and shift avoidance
+/// are not considered, and substitutes fill any shortfall in roster order.
+/// Someone in conflict with a member already picked, or whose pair does not
+/// fit in the seats left, waits for the next round; partners come along
+/// when either is reached.
+pub struct RoundRobin;
+
+impl AssignmentStrategy for RoundRobin {
+    fn select(
+        &self,
+        book: &Book,
+        excluded: &[usize],
+        _rng: &mut dyn RngCore,
+    ) -> Result<Vec<usize>> {
+        let (regulars, substitutes) = member_pools(book, excluded);
+        if regulars.is_empty() && substitutes.is_empty() {
+            return Err(ToubanError::Unsatisfiable(
+                "選べるメンバーがいません".to_string(),
+            ));
+        }
+        let n = book.members.len();
+        let order = (0..n)
+            .map(|k| (book.rr_cursor + k) % n)
+            .filter(|i| regulars.contains(i))
+            .chain(substitutes.iter().copied());
+        let mut selected_idx: Vec<usize> = Vec::new();
+        let mut skipped = false;
+        for i in order {
+            if selected_idx.len() == book.people {
+                break;
+            }
+            if selected_idx.contains(&i) {
+                continue;
+            }
+            let group = pair_group(book, i);
+            let fits = selected_idx.len() + group.len() <= book.people
+                && group
+                    .iter()
+                    .all(|j| regulars.contains(j) || substitutes.contains(j));
+            let clash = group.iter().any(|&g| {
+                selected_idx
+                    .iter()
+                    .any(|&j| book.in_conflict(&book.members[g].name, &book.members[j].name))
+            });
+            if fits && !clash {
+                selected_idx.extend(group);
+            } else {
+                skipped = true;
+            }
+        }
+        if skipped && selected_idx.len() < book.people {
+            return Err(ToubanError::Unsatisfiable(
+                "いっしょにできない組み合わせのため 名簿の順では埋まりません".to_string(),
+            ));
+        }
+        Ok(selected_idx)
+    }
+}
+
+/// One member from every team (see [`Member::team`]), lowest load first
+/// within it; a team's substitutes serve only when none of its regulars
+/// can. Members without a team are never drawn, `people` is not used, and
+/// of the book's rules only conflicts are honored.
+pub struct PerTeam;
+
+impl AssignmentStrategy for PerTeam {
+    fn select(&self, book: &Book, excluded: &[usize], rng: &mut dyn RngCore) -> Result<Vec<usize>> {
+        let (regulars, substitutes) = member_pools(book, excluded);
+        let teams: BTreeSet<&String> = book
+            .members
+            .iter()
+            .filter_map(|m| m.team.as_ref())
+            .collect();
+        if teams.is_empty() {
+            return Err(ToubanError::Unsatisfiable(
+                "チームが決まっていません（set-team を使ってください）".to_string(),
+            ));
+        }
+        let mut selected_idx: Vec<usize> = Vec::new();
+        for team in teams {
+            let candidates = |pool: &[usize]| -> Vec<usize> {
+                pool.iter()
+                    .copied()
+                    .filter(|&i| book.members[i].team.as_ref() == Some(team))
+                    .filter(|&i| {
+                        !selected_idx.iter().any(|&j| {
+                            book.in_conflict(&book.members[i].name, &book.members[j].name)
+                        })
+                    })
+                    .collect()
+            };
+            let mut pick = draw_min_count(book, &candidates(&regulars), 1, rng);
+            if pick.is_empty() {
+                pick = draw_min_count(book, &candidates(&substitutes), 1, rng);
+            }
+            if pick.is_empty() {
+                return Err(ToubanError::Unsatisfiable(format!(
+                    "チーム「{}」から選べるメンバーがいません",
+                    team
+                )));
+            }
+            selected_idx.extend(pick);
+        }
+        Ok(selected_idx)
+    }
+}
+
+/// Where a round-robin pass over `idxs` began: the member from which the
+/// others follow within the shortest stretch of the roster.
+fn round_robin_start(book: &Book, idxs: &[usize]) -> Option<usize> {
+    let n = book.members.len();
+    let regulars: Vec<usize> = idxs
+        .iter()
+        .copied()
+        .filter(|&i| !book.members[i].substitute)
+        .collect();
+    regulars
+        .iter()
+        .copied()
+        .min_by_key(|&s| regulars.iter().map(|&i| (i + n - s) % n).max().unwrap_or(0))
+}
+
+/// Members drawn in the latest [`Book::avoid_recent`] rounds of the history.
+pub fn recent_assignees(book: &Book) -> Vec<usize> {
+    let names: Vec<&String> = book
+        .history
+        .iter()
+        .rev()
+        .take(book.avoid_recent as usize)
+        .flat_map(|h| &h.members)
+        .collect();
+    (0..book.members.len())
+        .filter(|&i| names.contains(&&book.members[i].name))
+        .collect()
+}
+
+/// Pick this round's assignees with the book's configured strategy.
+pub fn pick_members<R: Rng>(book: &Book, excluded: &[usize], rng: &mut R) -> Result<Vec<usize>> {
+    let recent = recent_assignees(book);
+    if !recent.is_empty() {
+        // only while the regular members left over can still fill the round
+        let wider: Vec<usize> = excluded.iter().chain(&recent).copied().collect();
+        if member_pools(book, &wider).0.len() >= book.people {
+            if let Ok(selected) = book.strategy.strategy().select(book, &wider, rng) {
+                return Ok(selected);
+            }
+        }
+    }
+    book.strategy.strategy().select(book, excluded, rng)
+}
+
+/// Commit `selected` as this round's assignment: bump counts, hand out
+/// roles and shifts, and record the current assignees.
+pub fn apply_assignment(book: &mut Book, selected_idx: &[usize]) {
+    apply_assignment_at(book, selected_idx, unix_now());
+}
+
+/// [`apply_assignment`] stamped at `now` (unix seconds) instead of the
+/// current time.
+pub fn apply_assignment_at(book: &mut Book, selected_idx: &[usize], now: u64) {
+    let seats = seat_round(book, selected_idx, now);
+    record_round(book, &seats, now);
+    book.current = seats;
+    book.drawn_at = Some(now);
+    book.backups.clear();
+    book.handover = std::mem::take(&mut book.notes);
+}
+
+/// Commit a round for the period starting at `at` (unix seconds) without
+/// seating it: the picks are counted and recorded in the history at `at`,
+/// and the current assignment stays until [`seat_planned`] takes over.
+pub fn plan_round(book: &mut Book, selected_idx: &[usize], at: u64) {
+    let seats = seat_round(book, selected_idx, at);
+    record_round(book, &seats, at);
+    book.planned.push(PlannedRound { at, seats });
+}
+
+/// Seat the first planned round once the day its period starts has come.
+/// Returns whether it did; the round was counted when it was planned.
+pub fn seat_planned(book: &mut Book) -> bool {
+    let today = Local::now().date_naive();
+    match book.planned.first() {
+        Some(p) if local_date(p.at).is_some_and(|d| d <= today) => {
+            book.current = book.planned.remove(0).seats;
+            book.backups.clear();
+            book.handover = std::mem::take(&mut book.notes);
+            true
+        }
+        _ => false,
+    }
+}
+
+/// Count `selected_idx` into seats at `at`, with roles and shifts.
+fn seat_round(book: &mut Book, selected_idx: &[usize], at: u64) -> Vec<Assignee> {
+    let roles = assign_roles(book, selected_idx);
+    let shifts = assign_shifts(book, selected_idx);
+    let shift_pos = |s: &Option<String>| {
+        s.as_ref()
+            .and_then(|s| book.shifts.iter().position(|x| x.name == *s))
+            .unwrap_or(usize::MAX)
+    };
+    // seat the assignees shift by shift; without shifts the draw order is kept
+    let mut order: Vec<usize> = (0..selected_idx.len()).collect();
+    order.sort_by_key(|&k| shift_pos(&shifts[k]));
+    book.deferred
+        .retain(|n| !selected_idx.iter().any(|&i| book.members[i].name == *n));
+    let mut seats = Vec::new();
+    for k in order {
+        let a = seat(
+            book,
+            selected_idx[k],
+            roles[k].clone(),
+            shifts[k].clone(),
+            at,
+        );
+        seats.push(a);
+    }
+    if book.strategy == StrategyKind::RoundRobin {
+        // continue after the last regular member seated this round
+        if let Some(&last) = selected_idx
+            .iter()
+            .rev()
+            .find(|&&i| !book.members[i].substitute)
+        {
+            book.rr_cursor = (last + 1) % book.members.len();
+        }
+    }
+    seats
+}
+
+/// Record a round seated at `at` in the history.
+fn record_round(book: &mut Book, seats: &[Assignee], at: u64) {
+    let members: Vec<String> = seats.iter().map(|a| a.name.clone()).collect();
+    let nonce = fnv1a(format!("{}{:?}{:?}", at, members, book.history.last()).as_bytes()) as u32;
+    book.history.push(HistoryEntry {
+        at,
+        members,
+        nonce,
+        note: None,
+        acked: Vec::new(),
+        substitutions: Vec::new(),
+        notes: Vec::new(),
+    });
+    book.trim_history();
+}
+
+/// Count member `i` into a seat with `role` and `shift` at `now`.
+fn seat(
+    book: &mut Book,
+    i: usize,
+    role: Option<String>,
+    shift: Option<String>,
+    now: u64,
+) -> Assignee {
+    let wrap = book.reset_policy.is_default();
+    let threshold = book.reset_threshold() as u32;
+    let m = &mut book.members[i];
+    if let Some(s) = &shift {
+        *m.shift_counts.entry(s.clone()).or_insert(0) += 1;
+    }
+    // increment count with wrap past the threshold -> 0
+    let newc = m.count.saturating_add(1);
+    m.count = if wrap && newc > threshold { 0 } else { newc };
+    let onboarding = m.onboarding > 0;
+    m.onboarding = m.onboarding.saturating_sub(1);
+    let prev_assigned_at = m.last_assigned_at.replace(now);
+    m.total += 1;
+    if let Some(r) = &role {
+        *m.role_counts.entry(r.clone()).or_insert(0) += 1;
+    }
+    Assignee {
+        name: m.name.clone(),
+        role,
+        shift,
+        acked: false,
+        onboarding,
+        prev_assigned_at,
+    }
+}
+
+/// Take back what [`seat`] counted for `a`.
+fn unseat(m: &mut Member, a: &Assignee) {
+    m.count = m.count.saturating_sub(1);
+    if a.onboarding {
+        m.onboarding += 1;
+    }
+    m.last_assigned_at = a.prev_assigned_at;
+    m.total = m.total.saturating_sub(1);
+    if let Some(shift) = &a.shift {
+        if let Some(n) = m.shift_counts.get_mut(shift) {
+            *n = n.saturating_sub(1);
+            if *n == 0 {
+                m.shift_counts.remove(shift);
+            }
+        }
+    }
+    if let Some(role) = &a.role {
+        if let Some(n) = m.role_counts.get_mut(role) {
+            *n = n.saturating_sub(1);
+            if *n == 0 {
+                m.role_counts.remove(role);
+            }
+        }
+    }
+}
+
+/// Rank up to `n` stand-ins from the members neither `excluded` nor
+/// `selected`, lowest load first; substitutes only after the regulars.
+/// Call before the assignment is applied, while counts are as drawn.
+pub fn pick_backups<R: Rng>(
+    book: &Book,
+    excluded: &[usize],
+    selected: &[usize],
+    n: usize,
+    rng: &mut R,
+) -> Vec<usize> {
+    let left_out: Vec<usize> = excluded.iter().chain(selected).copied().collect();
+    let (regulars, substitutes) = member_pools(book, &left_out);
+    let mut backups = draw_min_count(book, &regulars, n, rng);
+    let shortfall = n - backups.len();
+    backups.extend(draw_min_count(book, &substitutes, shortfall, rng));
+    backups
+}
+
+/// Everyone who can be called on this round, in escalation order: the
+/// `selected` assignees, then the other members not `excluded` ranked like
+/// [`pick_backups`] (lowest load first, substitutes last).
+pub fn escalation_order<R: Rng>(
+    book: &Book,
+    excluded: &[usize],
+    selected: &[usize],
+    rng: &mut R,
+) -> Vec<usize> {
+    let rest = pick_backups(book, excluded, selected, book.members.len(), rng);
+    selected.iter().copied().chain(rest).collect()
+}
+
+/// Give `name`'s seat in the current assignment to the first backup who
+/// may serve with the others (no conflict, no pair to bring along). The
+/// count credit moves with the seat, role and shift included. Returns the
+/// promoted member's name.
+pub fn promote_backup(book: &mut Book, name: &str) -> Result<String> {
+    let pos = book
+        .current
+        .iter()
+        .position(|a| a.name == name)
+        .ok_or_else(|| {
+            ToubanError::Invalid(format!("「{}」は今回のとうばんではありません", name))
+        })?;
+    let fits = |b: &str| {
+        book.current
+            .iter()
+            .all(|a| a.name != b && (a.name == name || !book.in_conflict(&a.name, b)))
+    };
+    let (k, i) = book
+        .backups
+        .iter()
+        .enumerate()
+        .filter(|(_, b)| fits(b))
+        .find_map(|(k, b)| {
+            let i = book.members.iter().position(|m| m.name == *b)?;
+            (pair_group(book, i).len() == 1).then_some((k, i))
+        })
+        .ok_or_else(|| ToubanError::Invalid("代われる控えがいません".to_string()))?;
+    book.backups.remove(k);
+    hand_over(book, pos, i);
+    Ok(book.members[i].name.clone())
+}
+
+/// Move seat `pos` of the current assignment to member `i`: the outgoing
+/// assignee's count is taken back and `i` is counted in, role and shift
+/// included, and the round's history entry follows and records the
+/// hand-over.
+fn hand_over(book: &mut Book, pos: usize, i: usize) {
+    let old = book.current[pos].clone();
+    if let Some(m) = book.members.iter_mut().find(|m| m.name == old.name) {
+        unseat(m, &old);
+    }
+    let a = seat(book, i, old.role, old.shift, unix_now());
+    if let Some(h) = book.current_round_mut() {
+        for n in h.members.iter_mut().filter(|n| **n == old.name) {
+            *n = a.name.clone();
+        }
+        h.substitutions.push((old.name.clone(), a.name.clone()));
+    }
+    book.backups.retain(|b| *b != a.name);
+    book.current[pos] = a;
+}
+
+/// Hand `out`'s seat in the current assignment to `into`, or else to the
+/// member with the lowest load who is not exempt today, not already
+/// serving, not in conflict with the others and not paired. Returns the
+/// incoming member's name.
+pub fn substitute<R: Rng>(
+    book: &mut Book,
+    out: &str,
+    into: Option<&str>,
+    rng: &mut R,
+) -> Result<String> {
+    let pos = book
+        .current
+        .iter()
+        .position(|a| a.name == out)
+        .ok_or_else(|| {
+            ToubanError::Invalid(format!("「{}」は今回のとうばんではありません", out))
+        })?;
+    let serving: Vec<usize> = (0..book.members.len())
+        .filter(|&i| book.current.iter().any(|a| a.name == book.members[i].name))
+        .collect();
+    let i = match into {
+        Some(name) => {
+            let i = book
+                .members
+                .iter()
+                .position(|m| m.name == name)
+                .ok_or_else(|| ToubanError::MemberNotFound(name.to_string()))?;
+            if serving.contains(&i) {
+                return Err(ToubanError::Invalid(format!(
+                    "「{}」は既に今回のとうばんです",
+                    name
+                )));
+            }
+            i
+        }
+        None => {
+            let mut left_out = exempt_today(book);
+            left_out.extend((0..book.members.len()).filter(|&j| {
+                pair_group(book, j).len() > 1
+                    || book
+                        .current
+                        .iter()
+                        .any(|a| a.name != out && book.in_conflict(&a.name, &book.members[j].name))
+            }));
+            *pick_backups(book, &left_out, &serving, 1, rng)
+                .first()
+                .ok_or_else(|| {
+                    ToubanError::Unsatisfiable("代われるメンバーがいません".to_string())
+                })?
+        }
+    };
+    hand_over(book, pos, i);
+    Ok(book.members[i].name.clone())
+}
+
+/// Complete trade `id`: the offering member's seat in the current
+/// assignment goes to `taker`, counts and history included (see
+/// [`substitute`]), and the trade is closed. Refused, with the book
+/// untouched, when the offering member has no seat, `taker` already has
+/// one, either of them is paired, or `taker` is in conflict with someone on
+/// duty or avoids the seat's shift.
+pub fn accept_trade(book: &mut Book, id: u32, taker: &str) -> Result<Trade> {
+    let k = book.trades.iter().position(|t| t.id == id).ok_or_else(|| {
+        ToubanError::Invalid(format!("交換の募集 #{} は見つかりませんでした", id))
+    })?;
+    let giver = book.trades[k].member.clone();
+    if giver == taker {
+        return Err(ToubanError::Invalid(
+            "自分の募集は引き受けられません".to_string(),
+        ));
+    }
+    let i = book
+        .members
+        .iter()
+        .position(|m| m.name == taker)
+        .ok_or_else(|| ToubanError::MemberNotFound(taker.to_string()))?;
+    let pos = book
+        .current
+        .iter()
+        .position(|a| a.name == giver)
+        .ok_or_else(|| {
+            ToubanError::Invalid(format!("「{}」は今回のとうばんではありません", giver))
+        })?;
+    if book.current.iter().any(|a| a.name == taker) {
+        return Err(ToubanError::Invalid(format!(
+            "「{}」は既に今回のとうばんです",
+            taker
+        )));
+    }
+    let paired = |name: &str| {
+        book.members
+            .iter()
+            .position(|m| m.name == name)
+            .is_some_and(|j| pair_group(book, j).len() > 1)
+    };
+    if let Some(name) = [giver.as_str(), taker].into_iter().find(|n| paired(n)) {
+        return Err(ToubanError::Invalid(format!(
+            "「{}」はペアになっているので交換できません",
+            name
+        )));
+    }
+    if let Some(a) = book
+        .current
+        .iter()
+        .find(|a| a.name != giver && book.in_conflict(&a.name, taker))
+    {
+        return Err(ToubanError::Invalid(format!(
+            "「{}」と「{}」は同じ回にできません",
+            taker, a.name
+        )));
+    }
+    if let Some(shift) = &book.current[pos].shift {
+        if book.members[i].avoid_shifts.contains(shift) {
+            return Err(ToubanError::Invalid(format!(
+                "「{}」は {} のシフトに入れません",
+                taker, shift
+            )));
+        }
+    }
+    hand_over(book, pos, i);
+    Ok(book.trades.remove(k))
+}
+
+/// Draw and commit the next round of the further duty `name`: its
+/// counts are reset and bumped like the book's own, and today's exemptions
+/// and `excluded` apply. Duties always go by lowest count; tags, pairs and
+/// conflicts are honored, shifts, roles and the history are not. Returns
+/// the indices of the members drawn.
+pub fn assign_duty<R: Rng>(
+    book: &mut Book,
+    name: &str,
+    excluded: &[usize],
+    rng: &mut R,
+) -> Result<Vec<usize>> {
+    let d = book
+        .duties
+        .iter()
+        .position(|d| d.name == name)
+        .ok_or_else(|| ToubanError::Invalid(format!("当番「{}」はありません", name)))?;
+    let mut view = book.clone();
+    view.people = book.duties[d].people;
+    view.strategy = StrategyKind::MinCount;
+    view.shifts.clear();
+    view.roles.clear();
+    view.history.clear();
+    for m in &mut view.members {
+        m.count = book.duties[d].counts.get(&m.name).copied().unwrap_or(0);
+    }
+    let (regulars, substitutes) = member_pools(&view, &[]);
+    reset_counts(&mut view, &regulars);
+    reset_counts(&mut view, &substitutes);
+    let mut left_out = exempt_today(book);
+    left_out.extend(excluded);
+    let selected = pick_members(&view, &left_out, rng)?;
+    let wrap = view.reset_policy.is_default();
+    let threshold = view.reset_threshold() as u32;
+    for &i in &selected {
+        let c = view.members[i].count.saturating_add(1);
+        view.members[i].count = if wrap && c > threshold { 0 } else { c };
+    }
+    let duty = &mut book.duties[d];
+    duty.counts = view
+        .members
+        .iter()
+        .filter(|m| m.count > 0)
+        .map(|m| (m.name.clone(), m.count))
+        .collect();
+    duty.current = selected
+        .iter()
+        .map(|&i| view.members[i].name.clone())
+        .collect();
+    let now = unix_now();
+    duty.history.push(DutyRound {
+        at: now,
+        members: duty.current.clone(),
+    });
+    duty.drawn_at = Some(now);
+    book.trim_history();
+    Ok(selected)
+}
+
+/// A draw that has not been committed yet.
+#[derive(Debug)]
+pub struct Draw {
+    /// Indices of the drawn members
+    pub selected: Vec<usize>,
+    /// Members left out because their exemption rules cover today
+    pub exempt: Vec<usize>,
+    /// Whether the regular members' counts were reset (or, under
+    /// [`ResetPolicy::Subtract`], lowered) before drawing
+    pub reset: bool,
+    /// Whether the substitutes' counts were reset before drawing
+    pub reset_substitutes: bool,
+}
+
+/// Draw the next assignment: bring counts down per the reset policy and
+/// pick members, leaving out today's exemptions. Nothing is committed;
+/// pass `selected` to [`apply_assignment`] or keep it pending.
+pub fn draw<R: Rng>(book: &mut Book, rng: &mut R) -> Result<Draw> {
+    draw_with(book, &[], &[], rng)
+}
+
+/// [`draw`] for this round only leaving `excluded` (e.g. members who are
+/// away) out and seating `included` (volunteers) first, even when exempt.
+/// Deferred members (see [`Book::defer`]) join the volunteers while seats,
+/// pairs and conflicts allow. The strategy fills the remaining seats.
+/// Excluded members' counts still take part in the reset.
+pub fn draw_with<R: Rng>(
+    book: &mut Book,
+    excluded: &[usize],
+    included: &[usize],
+    rng: &mut R,
+) -> Result<Draw> {
+    draw_on(book, Local::now().date_naive(), excluded, included, rng)
+}
+
+/// [`draw_with`] for the period starting on `date`, whose exemptions apply.
+pub fn draw_on<R: Rng>(
+    book: &mut Book,
+    date: NaiveDate,
+    excluded: &[usize],
+    included: &[usize],
+    rng: &mut R,
+) -> Result<Draw> {
+    // volunteers bring their partners along
+    let mut included = included.to_vec();
+    for i in included.clone() {
+        for j in pair_group(book, i) {
+            if !included.contains(&j) {
+                included.push(j);
+            }
+        }
+    }
+    // then whoever promised to take this round
+    for name in &book.deferred {
+        let Some(i) = book.members.iter().position(|m| m.name == *name) else {
+            continue;
+        };
+        let group = pair_group(book, i);
+        let fits = !included.contains(&i)
+            && group.iter().all(|j| !excluded.contains(j))
+            && included.len() + group.iter().filter(|j| !included.contains(j)).count()
+                <= book.people
+            && group.iter().all(|&j| {
+                included
+                    .iter()
+                    .all(|&k| !book.in_conflict(&book.members[j].name, &book.members[k].name))
+            });
+        if fits {
+            for j in group {
+                if !included.contains(&j) {
+                    included.push(j);
+                }
+            }
+        }
+    }
+    let included = &included[..];
+    if let Some(&i) = included.iter().find(|i| excluded.contains(i)) {
+        return Err(ToubanError::Invalid(format!(
+            "「{}」を含めることと除くことは同時にできません",
+            book.members[i].name
+        )));
+    }
+    for (k, &a) in included.iter().enumerate() {
+        if let Some(&b) = included[k + 1..]
+            .iter()
+            .find(|&&b| book.in_conflict(&book.members[a].name, &book.members[b].name))
+        {
+            return Err(ToubanError::Invalid(format!(
+                "「{}」と「{}」は いっしょにとうばんに入れません",
+                book.members[a].name, book.members[b].name
+            )));
+        }
+    }
+    if included.len() > book.people {
+        return Err(ToubanError::Invalid(format!(
+            "{} 人の とうばんに {} 人は入りません",
+            book.people,
+            included.len()
+        )));
+    }
+    if book.members.is_empty() {
+        return Err(ToubanError::EmptyRoster);
+    }
+    if book.pending.is_some() {
+        return Err(ToubanError::Invalid(
+            "確定待ちの とうばんがあります（confirm か reroll を使ってください）".to_string(),
+        ));
+    }
+    let (regulars, substitutes) = member_pools(book, &[]);
+    let before: Vec<u32> = book.members.iter().map(|m| m.count).collect();
+    // reset when any count >= threshold (regulars and substitutes are tracked separately)
+    let reset = reset_counts(book, &regulars);
+    let reset_substitutes = reset_counts(book, &substitutes);
+    book.reset_undo = book
+        .members
+        .iter()
+        .zip(before)
+        .filter(|(m, c)| m.count != *c)
+        .map(|(m, c)| (m.name.clone(), c))
+        .collect();
+    let mut exempt = exempt_on_date(book, date);
+    exempt.retain(|i| !included.contains(i));
+    let mut selected = included.to_vec();
+    if included.len() < book.people {
+        // the strategy fills the other seats from everyone else, with the
+        // required tags the volunteers already cover taken off
+        let mut rest = book.clone();
+        rest.people -= included.len();
+        rest.required_tags
+            .retain(|t| !included.iter().any(|&i| book.members[i].tags.contains(t)));
+        // nor anyone who may not serve alongside a volunteer
+        let partners = (0..book.members.len()).filter(|&j| {
+            included
+                .iter()
+                .any(|&i| book.in_conflict(&book.members[i].name, &book.members[j].name))
+        });
+        let left_out: Vec<usize> = exempt
+            .iter()
+            .chain(excluded)
+            .chain(included)
+            .copied()
+            .chain(partners)
+            .collect();
+        selected.extend(pick_members(&rest, &left_out, rng)?);
+    }
+    Ok(Draw {
+        selected,
+        exempt,
+        reset,
+        reset_substitutes,
+    })
+}
+
+/// Draw and commit the next assignment; the assignees end up in
+/// `book.current`. A round committed ahead by [`plan_round`] is seated
+/// instead of drawn, and refused before the day its period starts.
+pub fn assign<R: Rng>(book: &mut Book, rng: &mut R) -> Result<Draw> {
+    if let Some(at) = book.planned.first().map(|p| p.at) {
+        if !seat_planned(book) {
+            let date = local_date(at).map_or("?".to_string(), |d| d.to_string());
+            return Err(ToubanError::Invalid(format!(
+                "{} からの回まで決めてあります（その日になったら assign してください）",
+                date
+            )));
+        }
+        let selected = book
+            .current
+            .iter()
+            .filter_map(|a| book.members.iter().position(|m| m.name == a.name))
+            .collect();
+        return Ok(Draw {
+            selected,
+            exempt: Vec::new(),
+            reset: false,
+            reset_substitutes: false,
+        });
+    }
+    let d = draw(book, rng)?;
+    apply_assignment(book, &d.selected);
+    Ok(d)
+}
+
+/// Successive assignment rounds projected from a book, without printing and
+/// without touching the original; see [`rotation`].
+pub struct Rotation {
+    book: Book,
+    rng: ChaCha8Rng,
+    /// Start of the next projected period
+    date: NaiveDate,
+    done: bool,
+}
+
+/// One round yielded by a [`Rotation`].
+#[derive(Debug, Clone)]
+pub struct Round {
+    /// The day the round's period starts
+    pub date: NaiveDate,
+    /// Names drawn this round, in seating order
+    pub selected: Vec<String>,
+    /// The book after the round was committed
+    pub book: Book,
+}
+
+/// The draw seed derived from `date`: its digits read as a number, so
+/// 2024-06-10 gives 20240610.
+pub fn date_seed(date: NaiveDate) -> u64 {
+    date.year() as u64 * 10000 + date.month() as u64 * 100 + date.day() as u64
+}
+
+/// Project the rotation of `book` round by round, one interval apart from
+/// the next due date (today if nobody was assigned yet). Each round is
+/// drawn like `assign` draws it: with the exemptions of its own date and
+/// pending deferrals seated first. The same seed always yields the same
+/// rounds; any pending draw is ignored. The iterator ends after the first
+/// error.
+pub fn rotation(book: &Book, seed: u64) -> Rotation {
+    let mut book = book.clone();
+    book.pending = None;
+    let date = next_due_date(&book).unwrap_or_else(|| Local::now().date_naive());
+    Rotation {
+        book,
+        rng: ChaCha8Rng::seed_from_u64(seed),
+        date,
+        done: false,
+    }
+}
+
+impl Iterator for Rotation {
+    type Item = Result<Round>;
+
+    fn next(&mut self) -> Option<Result<Round>> {
+        if self.done {
+            return None;
+        }
+        let book = &mut self.book;
+        if book.members.is_empty() {
+            self.done = true;
+            return Some(Err(ToubanError::EmptyRoster));
+        }
+        let date = self.date;
+        self.date = date + chrono::Days::new(book.interval as u64);
+        match draw_on(book, date, &[], &[], &mut self.rng) {
+            Ok(d) => {
+                // stamped at the period's start, so the next due date follows
+                let at = date
+                    .and_hms_opt(0, 0, 0)
+                    .and_then(|t| Local.from_local_datetime(&t).earliest())
+                    .map_or_else(unix_now, |t| t.timestamp() as u64);
+                apply_assignment_at(book, &d.selected, at);
+                Some(Ok(Round {
+                    date,
+                    selected: book.current.iter().map(|a| a.name.clone()).collect(),
+                    book: book.clone(),
+                }))
+            }
+            Err(e) => {
+                self.done = true;
+                Some(Err(e))
+            }
+        }
+    }
+}
+
+/// Take back the latest assign: drop a pending draw, or the last planned
+/// round, or revert the current assignment, and restore the counts its
+/// reset changed. Returns the names that had been drawn. The assignment
+/// before it is not brought back.
+pub fn undo_assignment(book: &mut Book) -> Result<Vec<String>> {
+    let names = if let Some(p) = book.pending.take() {
+        p.members
+    } else if let Some(p) = book.planned.pop() {
+        book.history.pop();
+        for a in &p.seats {
+            if let Some(m) = book.members.iter_mut().find(|m| m.name == a.name) {
+                unseat(m, a);
+            }
+        }
+        p.seats.into_iter().map(|a| a.name).collect()
+    } else if book.current.is_empty() {
+        return Err(ToubanError::Invalid(
+            "取り消せる とうばんがありません".to_string(),
+        ));
+    } else {
+        revert_assignment(book)?
+            .into_iter()
+            .map(|i| book.members[i].name.clone())
+            .collect()
+    };
+    restore_reset(book);
+    Ok(names)
+}
+
+/// Put back the counts the last draw's reset changed.
+fn restore_reset(book: &mut Book) {
+    for (name, count) in std::mem::take(&mut book.reset_undo) {
+        if let Some(m) = book.members.iter_mut().find(|m| m.name == name) {
+            m.count = count;
+        }
+    }
+}
+
+/// Take back the current assignment, restoring the assignees' counts.
+/// Returns the indices of the members who had been assigned. Refused while
+/// rounds are planned, since those were drawn after it.
+pub fn revert_assignment(book: &mut Book) -> Result<Vec<usize>> {
+    if !book.planned.is_empty() {
+        return Err(ToubanError::Invalid(
+            "先の回まで決めてあるので 今回は変えられません（undo で後ろの回から取り消してください）"
+                .to_string(),
+        ));
+    }
+    let mut reverted = Vec::new();
+    if !book.current.is_empty() {
+        book.history.pop();
+        book.drawn_at = None;
+    }
+    // notes go back to waiting for whoever is drawn instead
+    let mut handover = std::mem::take(&mut book.handover);
+    handover.append(&mut book.notes);
+    book.notes = handover;
+    book.backups.clear();
+    for a in std::mem::take(&mut book.current) {
+        let Some(i) = book.members.iter().position(|m| m.name == a.name) else {
+            continue;
+        };
+        unseat(&mut book.members[i], &a);
+        reverted.push(i);
+    }
+    if book.strategy == StrategyKind::RoundRobin {
+        if let Some(start) = round_robin_start(book, &reverted) {
+            book.rr_cursor = start;
+        }
+    }
+    Ok(reverted)
+}
+
+/// Draw the pending assignment, or else the current one, again, the way
+/// [`draw_with`] does: the counts the earlier draw's reset changed are put
+/// back first, and a pending draw's objected members, or the previous
+/// assignees, are left out. A committed round keeps its note. The book is
+/// unchanged on error.
+pub fn reroll_assignment<R: Rng>(book: &mut Book, rng: &mut R) -> Result<Draw> {
+    let mut next = book.clone();
+    let d = match next.pending.take() {
+        Some(mut p) => {
+            if unix_now() >= p.deadline {
+                return Err(ToubanError::Invalid(
+                    "猶予期間は終わっています（confirm で確定してください）".to_string(),
+                ));
+            }
+            let excluded: Vec<usize> = p
+                .objected
+                .iter()
+                .filter_map(|name| next.members.iter().position(|m| m.name == *name))
+                .collect();
+            restore_reset(&mut next);
+            let d = draw_with(&mut next, &excluded, &[], rng)?;
+            p.members = d
+                .selected
+                .iter()
+                .map(|&i| next.members[i].name.clone())
+                .collect();
+            next.pending = Some(p);
+            d
+        }
+        None if next.current.is_empty() => {
+            return Err(ToubanError::Invalid(
+                "まだ とうばんが 割り当てられていません".to_string(),
+            ))
+        }
+        None => {
+            let note = next.current_round().and_then(|h| h.note.clone());
+            // previous assignees are neither re-picked nor counted twice
+            let excluded = revert_assignment(&mut next)?;
+            restore_reset(&mut next);
+            let d = draw_with(&mut next, &excluded, &[], rng)?;
+            apply_assignment(&mut next, &d.selected);
+            if let Some(h) = next.current_round_mut() {
+                h.note = note;
+            }
+            d
+        }
+    };
+    *book = next;
+    Ok(d)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sample_book() -> Book {
+        BookBuilder::new(1, 7)
+            .members(["たろう", "はなこ", "じろう"].map(String::from))
+            .build()
+            .expect("book")
+    }
+
+    fn roster(people: usize, names: &[&str]) -> Book {
+        BookBuilder::new(people, 7)
+            .members(names.iter().map(|n| n.to_string()))
+            .build()
+            .expect("book")
+    }
+
+    /// The names of the members at `idxs`.
+    fn picked<'a>(book: &'a Book, idxs: &[usize]) -> Vec<&'a str> {
+        idxs.iter()
+            .map(|&i| book.members[i].name.as_str())
+            .collect()
+    }
+
+    #[test]
+    fn encoding_roundtrips() {
+        let mut book = sample_book();
+        book.add_conflict("たろう", "はなこ").expect("conflict");
+        book.members[0].tags = vec!["senior".to_string()];
+        let hira = encode_book(&book).expect("encode");
+        let again = decode_book(&hira).expect("decode");
+        assert_eq!(encode_book(&again).expect("encode"), hira);
+        assert_eq!(
+            serde_json::to_value(&again).expect("json"),
+            serde_json::to_value(&book).expect("json")
+        );
+    }
+
+    #[test]
+    fn encoding_is_canonical() {
+        let mut a = sample_book();
+        a.add_conflict("たろう", "はなこ").expect("conflict");
+        a.add_pair("じろう", "はなこ").expect("pair");
+        a.members[0].tags = vec!["senior".to_string(), "driver".to_string()];
+        let mut b = sample_book();
+        b.add_pair("はなこ", "じろう").expect("pair");
+        b.add_conflict("はなこ", "たろう").expect("conflict");
+        b.members[0].tags = vec!["driver".to_string(), "senior".to_string()];
+        assert_eq!(
+            encode_book(&a).expect("encode"),
+            encode_book(&b).expect("encode")
+        );
+    }
+
+    #[test]
+    fn updates_advance_the_revision() {
+        let mut book = sample_book();
+        book.revision = 4;
+        let next = encode_book_update(&book, &HIRAGANA).expect("encode");
+        assert_eq!(decode_book(&next).expect("decode").revision, 5);
+        // a plain encode writes the book as it is
+        let same = encode_book(&book).expect("encode");
+        assert_eq!(decode_book(&same).expect("decode").revision, 4);
+    }
+
+    #[test]
+    fn streams_match_the_string_codec() {
+        let mut book = sample_book();
+        book.add_pair("たろう", "はなこ").expect("pair");
+        for codec in CODECS {
+            let text = encode_book_with(&book, codec).expect("encode");
+            let written = encode_book_to(&book, codec, Vec::new()).expect("write");
+            assert_eq!(String::from_utf8(written).expect("utf-8"), text);
+            // a reader that hands out a few bytes at a time, splitting chars
+            let broken = break_words(&text);
+            let chunks = io::Read::chain(&broken.as_bytes()[..5], &broken.as_bytes()[5..]);
+            let back = decode_book_from(chunks, codec).expect("read");
+            assert_eq!(encode_book_with(&back, codec).expect("encode"), text);
+        }
+    }
+
+    #[test]
+    fn stream_errors_keep_their_kind() {
+        let mut hira = encode_book(&sample_book()).expect("encode");
+        hira.insert(3, 'x');
+        assert!(matches!(
+            decode_book_from(hira.as_bytes(), &HIRAGANA),
+            Err(ToubanError::CorruptedBook(_))
+        ));
+    }
+
+    #[test]
+    fn swapping_an_unknown_member_changes_nothing() {
+        let mut book = sample_book();
+        book.members[0].count = 2;
+        let before = encode_book(&book).expect("encode");
+        assert!(matches!(
+            book.swap_members("たろう", "さぶろう"),
+            Err(ToubanError::MemberNotFound(name)) if name == "さぶろう"
+        ));
+        assert_eq!(encode_book(&book).expect("encode"), before);
+        book.swap_members("たろう", "はなこ").expect("swap");
+        assert_eq!((book.members[0].count, book.members[1].count), (0, 2));
+    }
+
+    #[test]
+    fn encoding_enforces_the_duty_limit() {
+        let mut book = sample_book();
+        for k in 0..Limits::default().max_duties {
+            book.add_duty(&format!("当番{}", k), 1).expect("duty");
+        }
+        encode_book(&book).expect("at the limit");
+        book.add_duty("もうひとつ", 1).expect("duty");
+        assert!(matches!(
+            encode_book(&book),
+            Err(ToubanError::LimitExceeded(_))
+        ));
+    }
+
+    #[test]
+    fn acks_and_hand_overs_land_in_the_current_round() {
+        let mut book = sample_book();
+        let mut rng = ChaCha8Rng::seed_from_u64(1);
+        assign(&mut book, &mut rng).expect("assign");
+        let next = pick_members(&book, &[], &mut rng).expect("pick");
+        plan_round(&mut book, &next, unix_now() + 7 * 86400);
+        let on_duty = book.current[0].name.clone();
+        book.ack(&on_duty).expect("ack");
+        let free = book
+            .members
+            .iter()
+            .find(|m| m.name != on_duty)
+            .expect("free member")
+            .name
+            .clone();
+        substitute(&mut book, &on_duty, Some(&free), &mut rng).expect("substitute");
+        let h = &book.history[0];
+        assert_eq!(h.acked, std::slice::from_ref(&on_duty));
+        assert_eq!(h.substitutions, [(on_duty, free.clone())]);
+        assert_eq!(h.members, [free]);
+        let planned = &book.history[1];
+        assert!(planned.acked.is_empty() && planned.substitutions.is_empty());
+    }
+
+    #[test]
+    fn handover_notes_stay_with_their_round() {
+        let mut book = sample_book();
+        let mut rng = ChaCha8Rng::seed_from_u64(2);
+        assign(&mut book, &mut rng).expect("assign");
+        let writer = book.current[0].name.clone();
+        book.add_note(&writer, " ゴミ袋が残り2枚 ").expect("note");
+        assign(&mut book, &mut rng).expect("assign");
+        let note = HandoverNote {
+            member: writer,
+            text: "ゴミ袋が残り2枚".to_string(),
+        };
+        assert_eq!(book.handover, std::slice::from_ref(&note));
+        assert_eq!(book.history[0].notes, [note]);
+        assert!(book.history[1].notes.is_empty());
+    }
+
+    #[test]
+    fn book_view_matches_the_book() {
+        let mut book = sample_book();
+        book.people = 5;
+        book.reset_threshold = 1;
+        book.members[0].count = 3;
+        book.members[1].weight = 150;
+        let bytes = serde_json::to_vec(&book).expect("json");
+        let view = view_book(&bytes).expect("view");
+        assert!(matches!(view.members[0].name, Cow::Borrowed(_)));
+        assert_eq!(view.reset_threshold(), book.reset_threshold());
+        assert_eq!(view.history_limit(), book.history_limit());
+        assert_eq!(view.members[1].weight(), book.members[1].weight());
+        let text = |w: Vec<Warning>| w.iter().map(|w| w.to_string()).collect::<Vec<_>>();
+        assert_eq!(text(view.warnings()).len(), 2);
+        assert_eq!(text(view.warnings()), text(book_warnings(&book)));
+    }
+
+    /// たろう and はなこ on duty, with たろう offering the seat as trade #1.
+    fn book_with_trade(edit: impl FnOnce(&mut Book)) -> Book {
+        let mut book = BookBuilder::new(2, 7)
+            .members(["たろう", "はなこ", "じろう", "さぶろう"].map(String::from))
+            .build()
+            .expect("book");
+        edit(&mut book);
+        apply_assignment(&mut book, &[0, 1]);
+        book.trades.push(Trade {
+            id: 1,
+            member: "たろう".to_string(),
+            when: "6/10".to_string(),
+        });
+        book
+    }
+
+    #[test]
+    fn accepting_a_trade_moves_the_seat_and_its_counts() {
+        let mut book = book_with_trade(|b| b.roles = vec!["司会".to_string()]);
+        let role = book.current[0].role.clone().expect("role");
+        let trade = accept_trade(&mut book, 1, "じろう").expect("accept");
+        assert_eq!(trade.member, "たろう");
+        assert!(book.trades.is_empty());
+        assert_eq!(book.current[0].name, "じろう");
+        assert_eq!(book.current[0].role.as_ref(), Some(&role));
+        let giver = &book.members[0];
+        assert_eq!((giver.count, giver.total), (0, 0));
+        assert_eq!(giver.last_assigned_at, None);
+        assert!(giver.role_counts.is_empty());
+        let taker = &book.members[2];
+        assert_eq!((taker.count, taker.total), (1, 1));
+        assert!(taker.last_assigned_at.is_some());
+        assert_eq!(taker.role_counts.get(&role), Some(&1));
+        let h = book.current_round().expect("round");
+        assert_eq!(h.members, ["じろう", "はなこ"]);
+        assert_eq!(
+            h.substitutions,
+            [("たろう".to_string(), "じろう".to_string())]
+        );
+    }
+
+    #[test]
+    fn trades_that_break_a_rule_are_refused() {
+        type Edit = fn(&mut Book);
+        let cases: [(&str, Edit, &str); 4] = [
+            ("はなこ", |_| {}, "既に今回のとうばん"),
+            (
+                "じろう",
+                |b| b.add_conflict("じろう", "はなこ").expect("conflict"),
+                "同じ回にできません",
+            ),
+            (
+                "じろう",
+                |b| b.add_pair("じろう", "さぶろう").expect("pair"),
+                "ペア",
+            ),
+            (
+                "じろう",
+                |b| {
+                    b.shifts = vec![Shift {
+                        name: "朝".to_string(),
+                        people: 2,
+                    }];
+                    b.members[2].avoid_shifts = vec!["朝".to_string()];
+                },
+                "シフトに入れません",
+            ),
+        ];
+        for (taker, edit, reason) in cases {
+            let mut book = book_with_trade(edit);
+            let before = serde_json::to_value(&book).expect("json");
+            match accept_trade(&mut book, 1, taker) {
+                Err(ToubanError::Invalid(msg)) => assert!(msg.contains(reason), "{}", msg),
+                other => panic!("{}: {:?}", reason, other),
+            }
+            assert_eq!(serde_json::to_value(&book).expect("json"), before);
+        }
+    }
+
+    #[test]
+    fn assign_seats_planned_rounds_when_due() {
+        let mut book = sample_book();
+        let now = unix_now();
+        apply_assignment_at(&mut book, &[0], now);
+        plan_round(&mut book, &[1], now + 7 * 86400);
+        let mut rng = ChaCha8Rng::seed_from_u64(1);
+        let before = serde_json::to_value(&book).expect("json");
+        assert!(matches!(
+            assign(&mut book, &mut rng),
+            Err(ToubanError::Invalid(_))
+        ));
+        assert_eq!(serde_json::to_value(&book).expect("json"), before);
+        // the planned round's day has come
+        book.planned[0].at = now;
+        let d = assign(&mut book, &mut rng).expect("assign");
+        assert_eq!(d.selected, [1]);
+        assert_eq!(book.current[0].name, "はなこ");
+        assert!(book.planned.is_empty());
+        assert_eq!(book.history.len(), 2);
+        assert_eq!(book.members[1].count, 1);
+        assert_eq!(book.current_round().expect("round").members, ["はなこ"]);
+    }
+
+    #[test]
+    fn reroll_draws_like_assign_and_keeps_the_note() {
+        for seed in 0..8 {
+            let mut book = sample_book();
+            book.add_member("さぶろう".to_string(), false, 0)
+                .expect("member");
+            apply_assignment(&mut book, &[0]);
+            book.current_round_mut().expect("round").note = Some("雨".to_string());
+            book.defer("さぶろう").expect("defer");
+            let mut rng = ChaCha8Rng::seed_from_u64(seed);
+            let d = reroll_assignment(&mut book, &mut rng).expect("reroll");
+            assert_eq!(d.selected, [3]);
+            assert_eq!(book.current[0].name, "さぶろう");
+            assert!(book.deferred.is_empty());
+            assert_eq!(book.history.len(), 1);
+            let h = book.current_round().expect("round");
+            assert_eq!(h.members, ["さぶろう"]);
+            assert_eq!(h.note.as_deref(), Some("雨"));
+            assert_eq!(book.members[0].count, 0);
+        }
+    }
+
+    #[test]
+    fn reroll_keeps_the_reset_undoable() {
+        let mut book = sample_book();
+        book.reset_threshold = 1;
+        for m in &mut book.members {
+            m.count = 1;
+        }
+        let mut rng = ChaCha8Rng::seed_from_u64(4);
+        let d = assign(&mut book, &mut rng).expect("assign");
+        assert!(d.reset);
+        let first = book.current[0].name.clone();
+        let d = reroll_assignment(&mut book, &mut rng).expect("reroll");
+        assert!(d.reset);
+        assert_ne!(book.current[0].name, first);
+        assert_eq!(book.reset_undo.len(), 3);
+        undo_assignment(&mut book).expect("undo");
+        assert!(book.members.iter().all(|m| m.count == 1));
+    }
+
+    #[test]
+    fn reroll_of_a_pending_draw_leaves_objections_out() {
+        let mut book = sample_book();
+        let mut rng = ChaCha8Rng::seed_from_u64(1);
+        let d = draw(&mut book, &mut rng).expect("draw");
+        let drawn = book.members[d.selected[0]].name.clone();
+        book.pending = Some(Pending {
+            members: vec![drawn.clone()],
+            deadline: unix_now() + 3600,
+            objected: vec![drawn.clone()],
+            note: None,
+        });
+        for _ in 0..4 {
+            reroll_assignment(&mut book, &mut rng).expect("reroll");
+            let p = book.pending.as_ref().expect("pending");
+            assert!(!p.members.contains(&drawn));
+        }
+        assert!(book.current.is_empty() && book.history.is_empty());
+        book.pending.as_mut().expect("pending").deadline = 0;
+        assert!(reroll_assignment(&mut book, &mut rng).is_err());
+    }
+
+    #[test]
+    fn rounds_before_a_planned_one_cannot_be_taken_back() {
+        let mut book = sample_book();
+        let now = unix_now();
+        apply_assignment_at(&mut book, &[0], now);
+        plan_round(&mut book, &[1], now + 7 * 86400);
+        let before = serde_json::to_value(&book).expect("json");
+        assert!(revert_assignment(&mut book).is_err());
+        let mut rng = ChaCha8Rng::seed_from_u64(1);
+        assert!(reroll_assignment(&mut book, &mut rng).is_err());
+        assert_eq!(serde_json::to_value(&book).expect("json"), before);
+    }
+
+    #[test]
+    fn equal_books_encode_the_same_however_built() {
+        let mut a = sample_book();
+        a.required_tags = vec!["鍵".to_string(), "車".to_string()];
+        a.members[0].prefer_shifts = vec!["朝".to_string(), "夜".to_string()];
+        a.members[0].avoid_shifts = vec!["昼".to_string(), "夕".to_string()];
+        a.members[0].exempt = vec!["week2".to_string(), "fri".to_string()];
+        a.members[1].set_weight(100).expect("weight");
+        a.set_history_limit(HISTORY_LIMIT);
+        a.set_reset_threshold(RESET_THRESHOLD).expect("threshold");
+        let mut b = sample_book();
+        b.required_tags = vec!["車".to_string(), "鍵".to_string(), "車".to_string()];
+        b.members[0].prefer_shifts = vec!["夜".to_string(), "朝".to_string()];
+        b.members[0].avoid_shifts = vec!["夕".to_string(), "昼".to_string()];
+        b.members[0].exempt = vec!["fri".to_string(), "week2".to_string()];
+        b.members[1].weight = 100;
+        b.history_limit = Some(HISTORY_LIMIT);
+        b.reset_threshold = RESET_THRESHOLD;
+        assert!(a.semantic_eq(&b));
+        let hira = encode_book(&a).expect("encode");
+        assert_eq!(encode_book(&b).expect("encode"), hira);
+        crate::check::canonical(&hira).expect("canonical");
+        // roles break ties in order, so their order is kept
+        a.roles = vec!["司会".to_string(), "記録".to_string()];
+        b.roles = vec!["記録".to_string(), "司会".to_string()];
+        assert_ne!(
+            encode_book(&a).expect("encode"),
+            encode_book(&b).expect("encode")
+        );
+    }
+
+    #[test]
+    fn counts_keep_growing_when_never_reset() {
+        let mut book = sample_book();
+        book.reset_policy = ResetPolicy::Never;
+        for (m, c) in book.members.iter_mut().zip([301, 299, 302]) {
+            m.count = c;
+        }
+        let mut rng = ChaCha8Rng::seed_from_u64(1);
+        for expected in ["はなこ", "はなこ"] {
+            assign(&mut book, &mut rng).expect("assign");
+            assert_eq!(book.current[0].name, expected);
+        }
+        let counts: Vec<u32> = book.members.iter().map(|m| m.count).collect();
+        assert_eq!(counts, [301, 301, 302]);
+        let back = decode_book(&encode_book(&book).expect("encode")).expect("decode");
+        assert_eq!(back.members[1].count, 301);
+    }
+
+    #[test]
+    fn every_draw_covers_the_required_tags() {
+        let mut book = roster(3, &["たろう", "はなこ", "じろう", "さぶろう", "しろう"]);
+        book.required_tags = vec!["鍵".to_string(), "階:*".to_string()];
+        book.members[1].tags = vec!["階:2".to_string()];
+        book.members[2].tags = vec!["階:2".to_string()];
+        book.members[4].tags = vec!["鍵".to_string(), "階:1".to_string()];
+        assert_eq!(expand_required_tags(&book), ["鍵", "階:1", "階:2"]);
+        let mut rng = ChaCha8Rng::seed_from_u64(3);
+        for _ in 0..10 {
+            let d = assign(&mut book, &mut rng).expect("assign");
+            let names = picked(&book, &d.selected);
+            assert_eq!(names.len(), 3);
+            assert!(names.contains(&"しろう"), "{:?}", names);
+            assert!(names.iter().any(|n| ["はなこ", "じろう"].contains(n)));
+        }
+        // さぶろう and たろう still take their turns in the free seat
+        assert!(book.members[0].total > 0 && book.members[3].total > 0);
+    }
+
+    #[test]
+    fn required_tags_nobody_can_cover_are_refused() {
+        let mut book = roster(1, &["たろう", "はなこ"]);
+        book.members[0].tags = vec!["鍵".to_string()];
+        book.members[1].tags = vec!["車".to_string()];
+        let mut rng = ChaCha8Rng::seed_from_u64(1);
+        let cases = [(vec!["船"], "「船」"), (vec!["鍵", "車"], "2 人以上")];
+        for (tags, reason) in cases {
+            book.required_tags = tags.into_iter().map(String::from).collect();
+            match draw(&mut book, &mut rng) {
+                Err(ToubanError::Unsatisfiable(msg)) => assert!(msg.contains(reason), "{}", msg),
+                other => panic!("{}: {:?}", reason, other),
+            }
+        }
+    }
+
+    #[test]
+    fn new_members_are_paired_with_experienced_ones() {
+        let mut book = roster(2, &["じろう", "さぶろう"]);
+        book.add_member("しろう".to_string(), false, 2)
+            .expect("member");
+        book.add_member("ごろう".to_string(), false, 2)
+            .expect("member");
+        // left alone, the draw would seat the two newcomers together
+        for m in &mut book.members {
+            m.count = if m.onboarding > 0 { 0 } else { 3 };
+        }
+        let mut rng = ChaCha8Rng::seed_from_u64(5);
+        let d = assign(&mut book, &mut rng).expect("assign");
+        let names = picked(&book, &d.selected);
+        assert!(names.contains(&"じろう") || names.contains(&"さぶろう"));
+        let new: Vec<&Assignee> = book.current.iter().filter(|a| a.onboarding).collect();
+        assert_eq!(new.len(), 1);
+        let i = book
+            .members
+            .iter()
+            .position(|m| m.name == new[0].name)
+            .expect("member");
+        assert_eq!(book.members[i].onboarding, 1);
+        undo_assignment(&mut book).expect("undo");
+        assert_eq!(book.members[i].onboarding, 2);
+        for _ in 0..10 {
+            assign(&mut book, &mut rng).expect("assign");
+            assert!(!book.current.iter().all(|a| a.onboarding));
+        }
+        assert!(book.members.iter().all(|m| m.onboarding == 0));
+    }
+
+    #[test]
+    fn lenient_decoding_undoes_kana_typos() {
+        let hira = encode_book(&sample_book()).expect("encode");
+        let mut chars: Vec<char> = hira.chars().collect();
+        // two typos that strict decoding trips over, far apart
+        let mut typos = Vec::new();
+        for start in [0, chars.len() / 2] {
+            let (pos, alt) = (start..chars.len())
+                .find_map(|i| {
+                    let orig = chars[i];
+                    kana_confusions(orig).find_map(|alt| {
+                        chars[i] = alt;
+                        let broken = decode_book(&chars.iter().collect::<String>()).is_err();
+                        chars[i] = orig;
+                        broken.then_some((i, alt))
+                    })
+                })
+                .expect("a breaking typo");
+            typos.push(Correction {
+                pos,
+                from: alt,
+                to: chars[pos],
+            });
+            chars[pos] = alt;
+        }
+        let typo: String = chars.into_iter().collect();
+        assert!(matches!(
+            decode_book(&typo),
+            Err(ToubanError::CorruptedBook(_))
+        ));
+        let (book, mut fixes) = decode_book_lenient(&break_words(&typo)).expect("lenient");
+        assert_eq!(encode_book(&book).expect("encode"), hira);
+        fixes.sort_by_key(|c| c.pos);
+        assert_eq!(fixes, typos);
+        let (bytes, fixes) = decode_book_bytes_lenient(&typo).expect("lenient bytes");
+        assert_eq!(bytes, decode_book_bytes(&hira).expect("bytes"));
+        assert_eq!(fixes.len(), 2);
+        // a clean book needs no repair, and junk is not "repaired" into one
+        assert!(decode_book_lenient(&hira).expect("lenient").1.is_empty());
+        assert!(matches!(
+            decode_book_lenient("これは とうばんのしょ ではない"),
+            Err(ToubanError::CorruptedBook(_))
+        ));
+    }
+
+    #[test]
+    fn broken_words_decode_to_the_same_book() {
+        assert_eq!(break_words("ばかしねくそ"), "ば・かし・ねく・そ");
+        let (book, hira) = (0..)
+            .map(|k| {
+                let book = roster(1, &[&format!("m{}", k), "たろう"]);
+                let hira = encode_book(&book).expect("encode");
+                (book, hira)
+            })
+            .find(|(_, hira)| AVOIDED_WORDS.iter().any(|w| hira.contains(w)))
+            .expect("a book spelling a word");
+        let broken = break_words(&hira);
+        assert!(AVOIDED_WORDS.iter().all(|w| !broken.contains(w)));
+        assert_eq!(strip_word_breaks(&broken), hira);
+        let back = decode_book(&broken).expect("decode");
+        assert_eq!(back.members[0].name, book.members[0].name);
+        assert_eq!(
+            decode_book_bytes_with(&broken, &HIRAGANA).expect("bytes"),
+            decode_book_bytes(&hira).expect("bytes")
+        );
+        crate::check::canonical(&broken).expect("canonical");
+    }
+
+    #[test]
+    fn excluded_members_sit_out_but_still_reset() {
+        let mut book = roster(2, &["たろう", "はなこ", "じろう", "さぶろう"]);
+        let mut rng = ChaCha8Rng::seed_from_u64(1);
+        for _ in 0..6 {
+            let d = draw_with(&mut book, &[0], &[], &mut rng).expect("draw");
+            assert!(!d.selected.contains(&0));
+            apply_assignment(&mut book, &d.selected);
+        }
+        assert_eq!(book.members[0].total, 0);
+        // whoever is away still takes part in the reset
+        book.members[0].count = RESET_THRESHOLD as u32;
+        let d = draw_with(&mut book, &[0], &[], &mut rng).expect("draw");
+        assert!(d.reset);
+        assert!(book.members.iter().all(|m| m.count == 0));
+        assert!(matches!(
+            draw_with(&mut book, &[0, 1, 2, 3], &[], &mut rng),
+            Err(ToubanError::Unsatisfiable(_))
+        ));
+    }
+
+    #[test]
+    fn volunteers_take_their_seats_first() {
+        let mut book = roster(2, &["たろう", "はなこ", "じろう", "さぶろう"]);
+        book.members[0].exempt = ["mon", "tue", "wed", "thu", "fri", "sat", "sun"]
+            .map(String::from)
+            .to_vec();
+        book.members[0].count = 4;
+        let mut rng = ChaCha8Rng::seed_from_u64(1);
+        for _ in 0..4 {
+            let d = draw_with(&mut book, &[], &[0], &mut rng).expect("draw");
+            assert_eq!((d.selected[0], d.selected.len()), (0, 2));
+            assert!(!d.exempt.contains(&0));
+        }
+        // a volunteer brings their partner along
+        book.add_pair("たろう", "はなこ").expect("pair");
+        let d = draw_with(&mut book, &[], &[0], &mut rng).expect("draw");
+        assert_eq!(picked(&book, &d.selected), ["たろう", "はなこ"]);
+        book.add_conflict("じろう", "さぶろう").expect("conflict");
+        let refused: [(&[usize], &[usize]); 3] = [(&[0], &[0]), (&[], &[2, 3]), (&[], &[0, 2])];
+        for (excluded, included) in refused {
+            assert!(matches!(
+                draw_with(&mut book, excluded, included, &mut rng),
+                Err(ToubanError::Invalid(_))
+            ));
+        }
+    }
+
+    #[test]
+    fn round_robin_walks_the_roster_in_order() {
+        let mut book = roster(2, &["たろう", "はなこ", "じろう", "さぶろう", "しろう"]);
+        book.strategy = StrategyKind::RoundRobin;
+        let mut rng = ChaCha8Rng::seed_from_u64(1);
+        let mut rounds = Vec::new();
+        for _ in 0..3 {
+            let d = assign(&mut book, &mut rng).expect("assign");
+            rounds.push(d.selected);
+        }
+        assert_eq!(rounds, [vec![0, 1], vec![2, 3], vec![4, 0]]);
+        assert_eq!(book.rr_cursor, 1);
+        // undo puts the cursor back where the round began
+        undo_assignment(&mut book).expect("undo");
+        assert_eq!(book.rr_cursor, 4);
+        // someone in conflict with a pick waits for the next round
+        book.add_conflict("しろう", "たろう").expect("conflict");
+        let d = assign(&mut book, &mut rng).expect("assign");
+        assert_eq!(picked(&book, &d.selected), ["しろう", "はなこ"]);
+        assert_eq!(book.rr_cursor, 2);
+    }
+
+    #[test]
+    fn conflicting_members_are_never_drawn_together() {
+        let mut book = roster(2, &["たろう", "はなこ", "じろう", "さぶろう"]);
+        book.add_conflict("たろう", "はなこ").expect("conflict");
+        book.add_conflict("はなこ", "たろう")
+            .expect("same conflict");
+        assert_eq!(book.conflicts.len(), 1);
+        assert!(book.add_pair("たろう", "はなこ").is_err());
+        // the counts alone would draw the two of them together
+        book.members[2].count = 2;
+        book.members[3].count = 2;
+        let mut rng = ChaCha8Rng::seed_from_u64(2);
+        for _ in 0..12 {
+            let d = draw(&mut book, &mut rng).expect("draw");
+            let names = picked(&book, &d.selected);
+            assert!(!(names.contains(&"たろう") && names.contains(&"はなこ")));
+            assert_eq!(names.len(), 2);
+        }
+        let mut pair = roster(2, &["たろう", "はなこ"]);
+        pair.add_conflict("たろう", "はなこ").expect("conflict");
+        assert!(matches!(
+            draw(&mut pair, &mut rng),
+            Err(ToubanError::Unsatisfiable(_))
+        ));
+        book.remove_conflict("はなこ", "たろう").expect("remove");
+        assert!(book.conflicts.is_empty());
+    }
+
+    #[test]
+    fn rebalance_levels_each_pool_by_weight() {
+        let mut book = roster(1, &["たろう", "はなこ", "じろう", "さぶろう"]);
+        book.add_member("しろう".to_string(), true, 0)
+            .expect("member");
+        for (m, c) in book.members.iter_mut().zip([4, 0, 3, 2, 1]) {
+            m.count = c;
+        }
+        book.members[3].set_weight(50).expect("weight");
+        // loads 400, 0, 300 and 400 average 275: 2.75 seats each, half that at weight 50
+        assert_eq!(rebalance_counts(&mut book), [(0, 4), (1, 0), (3, 2)]);
+        let counts: Vec<u32> = book.members.iter().map(|m| m.count).collect();
+        assert_eq!(counts, [3, 3, 3, 1, 1]);
+        assert!(rebalance_counts(&mut book).is_empty());
+        // leveling never pushes a count up to the reset
+        for (m, c) in book.members.iter_mut().zip([4, 4, 4, 4]) {
+            m.count = c;
+        }
+        rebalance_counts(&mut book);
+        assert!(book.members[..3].iter().all(|m| m.count == 4));
+        book.reset_policy = ResetPolicy::Never;
+        rebalance_counts(&mut book);
+        assert!(book.members[..3].iter().all(|m| m.count == 5));
+    }
+
+    #[test]
+    fn paired_members_are_drawn_together_or_not_at_all() {
+        let mut book = roster(3, &["たろう", "はなこ", "じろう", "さぶろう", "しろう"]);
+        book.add_pair("たろう", "しろう").expect("pair");
+        book.add_pair("しろう", "たろう").expect("same pair");
+        assert_eq!(book.pairs.len(), 1);
+        assert!(book.add_conflict("たろう", "しろう").is_err());
+        // しろう alone would not be drawn soon
+        book.members[4].count = 3;
+        let mut rng = ChaCha8Rng::seed_from_u64(6);
+        let mut together = 0;
+        for _ in 0..10 {
+            let d = assign(&mut book, &mut rng).expect("assign");
+            let names = picked(&book, &d.selected);
+            assert_eq!(names.len(), 3);
+            assert_eq!(
+                names.contains(&"たろう"),
+                names.contains(&"しろう"),
+                "{:?}",
+                names
+            );
+            together += names.contains(&"たろう") as usize;
+        }
+        assert!(together > 0);
+        // with one partner away, neither serves
+        for _ in 0..4 {
+            let d = draw_with(&mut book, &[4], &[], &mut rng).expect("draw");
+            assert!(!d.selected.contains(&0));
+        }
+        // partners of partners come along too
+        book.add_pair("しろう", "じろう").expect("pair");
+        assert_eq!(pair_group(&book, 0), [0, 4, 2]);
+    }
+
+    #[test]
+    fn per_team_draws_one_member_from_each_team() {
+        let mut book = roster(1, &["たろう", "はなこ", "じろう", "しろう"]);
+        book.strategy = StrategyKind::PerTeam;
+        let mut rng = ChaCha8Rng::seed_from_u64(1);
+        assert!(matches!(
+            draw(&mut book, &mut rng),
+            Err(ToubanError::Unsatisfiable(_))
+        ));
+        book.add_member("さぶろう".to_string(), true, 0)
+            .expect("member");
+        for (i, team) in [(0, "赤"), (1, "赤"), (2, "青"), (4, "青")] {
+            book.members[i].team = Some(team.to_string());
+        }
+        for _ in 0..6 {
+            let d = assign(&mut book, &mut rng).expect("assign");
+            let teams: Vec<&str> = d
+                .selected
+                .iter()
+                .map(|&i| book.members[i].team.as_deref().expect("team"))
+                .collect();
+            assert_eq!(teams, ["赤", "青"]);
+            // substitutes wait while a regular of their team can serve
+            assert!(!d.selected.contains(&4));
+        }
+        assert_eq!(book.members[3].total, 0);
+        assert_eq!((book.members[0].total, book.members[1].total), (3, 3));
+        let d = draw_with(&mut book, &[2], &[], &mut rng).expect("draw");
+        assert!(d.selected.contains(&4));
+        match draw_with(&mut book, &[2, 4], &[], &mut rng) {
+            Err(ToubanError::Unsatisfiable(msg)) => assert!(msg.contains("「青」"), "{}", msg),
+            other => panic!("{:?}", other),
+        }
+    }
+
+    #[test]
+    fn overrides_apply_oldest_first_within_their_dates() {
+        let mut book = roster(2, &["たろう", "はなこ", "じろう", "さぶろう"]);
+        let cover = |id, member: &str, from: &str, to: &str, replaces: Option<&str>| Override {
+            id,
+            member: member.to_string(),
+            from: from.to_string(),
+            to: to.to_string(),
+            replaces: replaces.map(String::from),
+        };
+        book.overrides = vec![
+            cover(1, "じろう", "2026-06-01", "2026-06-07", Some("たろう")),
+            cover(2, "さぶろう", "2026-06-05", "2026-06-05", Some("じろう")),
+            cover(3, "はなこ", "2026-06-10", "2026-06-11", None),
+            cover(4, "はなこ", "6/12", "6/13", None),
+            cover(5, "はなこ", "2026-06-20", "2026-06-30", Some("たろう")),
+        ];
+        let scheduled = ["たろう", "はなこ"].map(String::from);
+        let on = |d: &str| on_duty(&book, d.parse().expect("date"), &scheduled);
+        assert_eq!(on("2026-05-31"), ["たろう", "はなこ"]);
+        assert_eq!(on("2026-06-01"), ["じろう", "はなこ"]);
+        assert_eq!(on("2026-06-05"), ["さぶろう", "はなこ"]);
+        assert_eq!(on("2026-06-07"), ["じろう", "はなこ"]);
+        assert_eq!(on("2026-06-11"), ["はなこ"]);
+        // an override with unreadable dates covers nothing
+        assert_eq!(on("2026-06-12"), ["たろう", "はなこ"]);
+        // nor is anyone on duty twice
+        assert_eq!(on("2026-06-20"), ["はなこ"]);
+    }
+
+    #[test]
+    fn deferred_members_take_the_next_rounds_in_order() {
+        let mut book = roster(1, &["たろう", "はなこ", "じろう", "さぶろう"]);
+        for m in &mut book.members[2..] {
+            m.count = 3;
+        }
+        book.defer("さぶろう").expect("defer");
+        book.defer("じろう").expect("defer");
+        assert!(matches!(book.defer("じろう"), Err(ToubanError::Invalid(_))));
+        assert!(matches!(
+            book.defer("しろう"),
+            Err(ToubanError::MemberNotFound(_))
+        ));
+        let mut rng = ChaCha8Rng::seed_from_u64(1);
+        // a promise waits while the member is away
+        let d = draw_with(&mut book, &[3], &[], &mut rng).expect("draw");
+        assert_eq!(picked(&book, &d.selected), ["じろう"]);
+        let d = draw_with(&mut book, &[], &[1], &mut rng).expect("draw");
+        assert_eq!(picked(&book, &d.selected), ["はなこ"]);
+        for expected in ["さぶろう", "じろう"] {
+            let d = assign(&mut book, &mut rng).expect("assign");
+            assert_eq!(picked(&book, &d.selected), [expected]);
+        }
+        assert!(book.deferred.is_empty());
+    }
+}
@@ -1,58 +1,242 @@
+mod help;
+
 use anyhow::{anyhow, Context, Result};
-use base64::engine::general_purpose::URL_SAFE_NO_PAD;
-use base64::Engine;
-use clap::{Parser, Subcommand};
+use chrono::{Local, NaiveDate, TimeZone};
+use clap::builder::PossibleValuesParser;
+use clap::{Args, CommandFactory, FromArgMatches, Parser, Subcommand, ValueEnum};
+use help::Lang;
 use rand::prelude::*;
 use rand_chacha::ChaCha8Rng;
-use serde::{Deserialize, Serialize};
-use std::cmp::min;
-use std::str;
-
-const HIRAGANA_START: u32 = 0x3041; // 'ぁ'
-const BASE64_LEN: u32 = 64; // base64url indices 0..63
-
-#[derive(Debug, Serialize, Deserialize)]
-struct Member {
-    name: String,
-    count: u8,
-}
-
-#[derive(Debug, Serialize, Deserialize)]
-struct Book {
-    people: usize,
-    interval: usize,
-    members: Vec<Member>,
-}
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::OnceLock;
+use touban::{
+    accept_trade, apply_assignment, assign_duty, book_warnings, decode_book_bytes_lenient,
+    decode_book_lenient, draw_on, encode_book_update, escalation_order, expand_required_tags,
+    find_member_mut, lint_book, member_pools, next_due_date, on_duty, parse_exempt_rule,
+    pick_backups, plan_round, planned_on, promote_backup, rebalance_counts, recent_assignees,
+    reroll_assignment, set_limits, undo_assignment, unix_now, view_book, Assignee, Book,
+    BookBuilder, BookCodec, Compaction, Correction, Draw, HistoryEntry, Limits, Override, Pending,
+    ResetPolicy, Shift, StrategyKind, Template, TieBreak, ToubanError, Trade, Warning,
+};
 
 #[derive(Parser)]
 #[command(
     name = "touban",
     about = "とうばんのしょ CLI (hiragana single-line state)"
 )]
-
 struct Cli {
     #[command(subcommand)]
     cmd: Commands,
+    #[command(flatten)]
+    limits: LimitArgs,
+    /// Plain output for assistive technology: no emoji or decorative marks,
+    /// every list line starts with a label
+    #[arg(long, global = true)]
+    screen_reader: bool,
+    /// Language of the help text (defaults to $LANG, else Japanese)
+    #[arg(long, global = true, value_enum)]
+    lang: Option<Lang>,
+    /// Alphabet for the とうばんのしょ strings this run prints (input in any
+    /// of them is recognized)
+    #[arg(
+        long,
+        global = true,
+        env = "TOUBAN_CODEC",
+        default_value = "hiragana",
+        value_parser = PossibleValuesParser::new(touban::CODECS.map(|c| c.name()))
+    )]
+    codec: String,
+    /// Repair typos between easily confused kana (ぁ/あ, か/が, ...) in
+    /// --book when it does not decode as is
+    #[arg(long, global = true)]
+    lenient: bool,
+    /// Break up unfortunate words (しね, ばか, ...) in printed とうばんのしょ
+    /// strings with a '・', which decoding ignores
+    #[arg(long, global = true, env = "TOUBAN_AVOID_WORDS")]
+    avoid_words: bool,
+    /// Print updated とうばんのしょ strings a second time as plain base64url,
+    /// on the line after
+    #[arg(long, global = true, env = "TOUBAN_ALSO_BASE64")]
+    also_base64: bool,
+    /// Fail with exit code 7 unless --book is at this revision (see show),
+    /// so scripts never update an outdated copy; read-only commands ignore it
+    #[arg(long, global = true)]
+    expect_revision: Option<u32>,
+}
+
+/// Command-line form of [`touban::Limits`].
+#[derive(Args, Debug)]
+struct LimitArgs {
+    /// Maximum number of members in a book
+    #[arg(long, global = true, env = "TOUBAN_MAX_MEMBERS", default_value_t = 100)]
+    max_members: usize,
+    /// Maximum length of a member name (in characters)
+    #[arg(long, global = true, env = "TOUBAN_MAX_NAME_LEN", default_value_t = 32)]
+    max_name_len: usize,
+    /// Maximum length of a とうばんのしょ string (in characters)
+    #[arg(
+        long,
+        global = true,
+        env = "TOUBAN_MAX_BOOK_LEN",
+        default_value_t = 20000
+    )]
+    max_book_len: usize,
+    /// Maximum number of further duties (add-duty) in a book
+    #[arg(long, global = true, env = "TOUBAN_MAX_DUTIES", default_value_t = 20)]
+    max_duties: usize,
+}
+
+/// Options of `create`.
+#[derive(Args, Debug)]
+struct CreateArgs {
+    /// Start from the settings of a common rotation; the other options
+    /// override them
+    #[arg(long, value_enum)]
+    template: Option<Template>,
+    /// How many people to assign each time
+    #[arg(long, required_unless_present = "template")]
+    people: Option<usize>,
+    /// Interval in days
+    #[arg(long, required_unless_present = "template")]
+    interval: Option<usize>,
+    /// Comma-separated member names, e.g. "たろう,はなこ,じろう"
+    #[arg(long)]
+    members: Option<String>,
+    /// Comma-separated substitute (backup) member names
+    #[arg(long)]
+    substitutes: Option<String>,
+    /// Comma-separated checklist of tasks, e.g. "ゴミ袋を交換,床をはく"
+    #[arg(long)]
+    checklist: Option<String>,
+    /// Comma-separated tags every assignment must cover, e.g. "senior";
+    /// "floor:*" requires one member from each "floor:..." tag
+    #[arg(long)]
+    require_tags: Option<String>,
+    #[command(flatten)]
+    reset: ResetArgs,
+}
+
+/// Options of `assign`.
+#[derive(Args, Debug)]
+struct AssignArgs {
+    /// Optional deterministic seed (u64) to control randomness
+    #[arg(long)]
+    seed: Option<u64>,
+    /// Derive the seed from a date (YYYY-MM-DD, default today) so anyone
+    /// can reproduce the draw for that period
+    #[arg(long, value_name = "DATE", num_args = 0..=1, conflicts_with = "seed")]
+    seed_from_date: Option<Option<NaiveDate>>,
+    /// Keep the draw pending for this long (e.g. "24h", "2d") so members can object
+    #[arg(long)]
+    grace: Option<String>,
+    /// Print why each member was or wasn't eligible in this draw
+    #[arg(long)]
+    explain: bool,
+    /// Comma-separated members who are away; left out of this round only
+    #[arg(long)]
+    exclude: Option<String>,
+    /// Comma-separated volunteers who take this round; the other seats
+    /// are drawn as usual
+    #[arg(long)]
+    include: Option<String>,
+    /// Show who would be picked without printing an updated book
+    /// (pass --seed to get the same draw later)
+    #[arg(long)]
+    dry_run: bool,
+    /// Assign even though the book already has an assignment from today
+    #[arg(long)]
+    force: bool,
+    /// Also rank N stand-ins (1 if no number is given) for promote-backup
+    #[arg(
+        long,
+        value_name = "N",
+        num_args = 0..=1,
+        default_missing_value = "1",
+        conflicts_with = "grace"
+    )]
+    with_backup: Option<usize>,
+    /// Record why this round went the way it did in the history
+    #[arg(long)]
+    note: Option<String>,
+    /// Also list everyone in escalation order (primary, secondary, ...),
+    /// the assignees first and then the others by load
+    #[arg(long)]
+    escalation: bool,
+    /// Commit this many consecutive rounds at once, e.g. a month ahead;
+    /// --exclude applies to every round
+    #[arg(
+        long,
+        default_value_t = 1,
+        value_parser = clap::value_parser!(u32).range(1..),
+        conflicts_with_all = ["grace", "include", "with_backup", "duty"]
+    )]
+    rounds: u32,
+    /// Roll this further duty (see add-duty) instead of the book's own;
+    /// "all" rolls the book's own and every further duty
+    #[arg(long)]
+    duty: Option<String>,
+}
+
+/// The settings `config` can change; unset ones are left alone.
+#[derive(Args, Debug)]
+struct ConfigArgs {
+    /// How many people to assign each time
+    #[arg(long)]
+    people: Option<usize>,
+    /// Interval in days
+    #[arg(long)]
+    interval: Option<usize>,
+    /// Favor members who served longest ago when counts are equal
+    #[arg(long)]
+    anti_streak: Option<bool>,
+    /// Leave out members drawn in the last N rounds when others can fill
+    /// the round (0 turns it off)
+    #[arg(long)]
+    avoid_recent: Option<u8>,
+    /// How to order members with equal counts
+    #[arg(long, value_enum)]
+    tie_break: Option<TieBreak>,
+    /// How the assignees are picked
+    #[arg(long, value_enum)]
+    strategy: Option<StrategyKind>,
+    #[command(flatten)]
+    reset: ResetArgs,
+    /// How many past rounds the book keeps for `history` (default 10, 0 keeps none)
+    #[arg(long)]
+    history_limit: Option<u8>,
+}
+
+/// How a book resets counts, shared by create and config.
+#[derive(Args, Debug)]
+struct ResetArgs {
+    /// Reset everyone's count when anyone reaches this (default 5)
+    #[arg(long, value_parser = clap::value_parser!(u8).range(1..))]
+    reset_threshold: Option<u8>,
+    /// How counts are brought back down (default: zero)
+    #[arg(long, value_enum)]
+    reset_policy: Option<ResetPolicy>,
 }
 
 #[derive(Subcommand)]
 enum Commands {
     /// Create a new とうばんのしょ
     Create {
-        /// How many people to assign each time
-        #[arg(long)]
-        people: usize,
-        /// Interval in days
-        #[arg(long)]
-        interval: usize,
-        /// Comma-separated member names, e.g. "たろう,はなこ,じろう"
-        #[arg(long)]
-        members: Option<String>,
+        #[command(flatten)]
+        opts: CreateArgs,
     },
     /// Show the contents of a とうばんのしょ (pass the hiragana string)
     Show {
         #[arg(long)]
         book: String,
+        /// Print the book and its warnings as JSON
+        #[arg(long)]
+        json: bool,
+    },
+    /// List member names and counts (fast even for very large books)
+    ListMembers {
+        #[arg(long)]
+        book: String,
     },
     /// Add a member (returns updated とうばんのしょ)
     AddMember {
@@ -60,6 +244,12 @@ enum Commands {
         book: String,
         #[arg(long)]
         member: String,
+        /// Add as a substitute (backup) member
+        #[arg(long)]
+        substitute: bool,
+        /// Flag as new: always pair with an experienced member for the first N assignments
+        #[arg(long, value_name = "N")]
+        onboarding: Option<u8>,
     },
     /// Remove a member (returns updated とうばんのしょ)
     RemoveMember {
@@ -68,223 +258,3246 @@ enum Commands {
         #[arg(long)]
         member: String,
     },
+    /// Change book settings (returns updated とうばんのしょ)
+    Config {
+        #[arg(long)]
+        book: String,
+        #[command(flatten)]
+        settings: ConfigArgs,
+    },
+    /// Check a とうばんのしょ for problems (exits nonzero if any are found)
+    Lint {
+        #[arg(long)]
+        book: String,
+    },
+    /// Compare two とうばんのしょ by meaning, ignoring encoding differences
+    /// (exits nonzero if they differ)
+    Eq { a: String, b: String },
+    /// Show per-member statistics (lifetime totals and last duty)
+    #[command(subcommand_negates_reqs = true)]
+    Stats {
+        #[arg(long, required = true)]
+        book: Option<String>,
+        #[command(subcommand)]
+        cmd: Option<StatsCommands>,
+    },
+    /// Replace the duty checklist (returns updated とうばんのしょ)
+    SetChecklist {
+        #[arg(long)]
+        book: String,
+        /// Comma-separated tasks; an empty string clears the checklist
+        #[arg(long)]
+        checklist: String,
+    },
+    /// Attach a tag to a member (returns updated とうばんのしょ)
+    Tag {
+        #[arg(long)]
+        book: String,
+        #[arg(long)]
+        member: String,
+        #[arg(long)]
+        tag: String,
+    },
+    /// Remove a tag from a member (returns updated とうばんのしょ)
+    Untag {
+        #[arg(long)]
+        book: String,
+        #[arg(long)]
+        member: String,
+        #[arg(long)]
+        tag: String,
+    },
+    /// Replace the tags every assignment must cover (returns updated とうばんのしょ)
+    SetRequiredTags {
+        #[arg(long)]
+        book: String,
+        /// Comma-separated tags ("floor:*" for one per group); an empty string clears the rule
+        #[arg(long)]
+        tags: String,
+    },
+    /// Replace the role slots handed out in each assignment (returns updated とうばんのしょ)
+    SetRoles {
+        #[arg(long)]
+        book: String,
+        /// Comma-separated role names, e.g. "リーダー,記録係"; an empty string clears them
+        #[arg(long)]
+        roles: String,
+    },
+    /// Split each period into shifts with their own headcounts (returns updated とうばんのしょ)
+    SetShifts {
+        #[arg(long)]
+        book: String,
+        /// Comma-separated "name:people" pairs, e.g. "朝:1,昼:2,夜:1"; an
+        /// empty string clears them
+        #[arg(long)]
+        shifts: String,
+    },
+    /// Replace a member's preferred and avoided shifts (returns updated とうばんのしょ)
+    SetShiftPrefs {
+        #[arg(long)]
+        book: String,
+        #[arg(long)]
+        member: String,
+        /// Comma-separated shifts the member would rather work
+        #[arg(long, default_value = "")]
+        prefer: String,
+        /// Comma-separated shifts the member must never be put on
+        #[arg(long, default_value = "")]
+        avoid: String,
+    },
+    /// Replace a member's recurring exemptions (returns updated とうばんのしょ)
+    SetExempt {
+        #[arg(long)]
+        book: String,
+        #[arg(long)]
+        member: String,
+        /// Comma-separated rules: "week1".."week5" (Nth week of the month),
+        /// "mon".."sun", or both as "week1-mon"; an empty string clears them
+        #[arg(long)]
+        rules: String,
+    },
+    /// Put a member in a team for `--strategy per-team` (returns updated とうばんのしょ)
+    SetTeam {
+        #[arg(long)]
+        book: String,
+        #[arg(long)]
+        member: String,
+        /// Team name; an empty string takes the member out of every team
+        #[arg(long)]
+        team: String,
+    },
+    /// Make a member drawn more or less often (returns updated とうばんのしょ)
+    SetWeight {
+        #[arg(long)]
+        book: String,
+        #[arg(long)]
+        member: String,
+        /// 1 is normal; 0.5 is drawn about half as often, 2 about twice
+        #[arg(long)]
+        weight: f64,
+    },
+    /// Level everyone's count after members joined or left mid-cycle, so
+    /// the load from here on is even (returns updated とうばんのしょ)
+    Rebalance {
+        #[arg(long)]
+        book: String,
+        /// Show the new counts without printing an updated book
+        #[arg(long)]
+        dry_run: bool,
+    },
+    /// Add a further duty rotated over the same roster, with its own counts
+    /// (returns updated とうばんのしょ)
+    AddDuty {
+        #[arg(long)]
+        book: String,
+        #[arg(long)]
+        name: String,
+        /// How many people to assign to it each time
+        #[arg(long, default_value_t = 1)]
+        people: usize,
+    },
+    /// Remove a further duty and its counts (returns updated とうばんのしょ)
+    RemoveDuty {
+        #[arg(long)]
+        book: String,
+        #[arg(long)]
+        name: String,
+    },
+    /// Never draw two members in the same round (returns updated とうばんのしょ)
+    AddConflict {
+        #[arg(long)]
+        book: String,
+        a: String,
+        b: String,
+    },
+    /// Let two members be drawn together again (returns updated とうばんのしょ)
+    RemoveConflict {
+        #[arg(long)]
+        book: String,
+        a: String,
+        b: String,
+    },
+    /// Always draw two members together, e.g. a trainer and a trainee;
+    /// they take two of the seats (returns updated とうばんのしょ)
+    AddPair {
+        #[arg(long)]
+        book: String,
+        a: String,
+        b: String,
+    },
+    /// Draw two paired members independently again (returns updated とうばんのしょ)
+    RemovePair {
+        #[arg(long)]
+        book: String,
+        a: String,
+        b: String,
+    },
+    /// Record that an assignee has seen their duty (returns updated とうばんのしょ)
+    Ack {
+        #[arg(long)]
+        book: String,
+        #[arg(long)]
+        member: String,
+    },
+    /// Show the current assignees and who has not acknowledged yet
+    Remind {
+        #[arg(long)]
+        book: String,
+    },
+    /// Shorten a とうばんのしょ that has grown too long: drop shown handover
+    /// notes (and more on request) and re-encode in the --codec alphabet
+    Compact {
+        #[arg(long)]
+        book: String,
+        /// Also drop notes left for the next assignees
+        #[arg(long)]
+        notes: bool,
+        /// Also drop lifetime totals, last-assigned times and role/shift tallies
+        #[arg(long)]
+        stats: bool,
+        /// Also drop the record of past rounds
+        #[arg(long)]
+        history: bool,
+    },
+    /// List the latest rounds, newest first
+    History {
+        #[arg(long)]
+        book: String,
+    },
+    /// Print a one-line channel topic: the current assignees and the next due date
+    Topic {
+        #[arg(long)]
+        book: String,
+        /// Also set this Slack channel's topic via the Web API
+        /// (conversations.setTopic); needs a build with the `slack` feature
+        #[arg(long)]
+        slack_channel: Option<String>,
+        /// Bot token for --slack-channel, with the channels:manage scope
+        /// (groups:write for private channels)
+        #[arg(long, env = "SLACK_BOT_TOKEN", hide_env_values = true)]
+        slack_token: Option<String>,
+    },
+    /// One member's view: this period, the next expected duty, past rounds
+    /// and open trade offers, as plain text for a direct message
+    Me {
+        #[arg(long)]
+        book: String,
+        #[arg(long)]
+        member: String,
+    },
+    /// Overview of several books at once: assignees, next due date and
+    /// overdue warnings, the most urgent first
+    Dashboard {
+        /// A book, optionally labelled as NAME=BOOK; repeat for each rotation
+        #[arg(long = "book", required = true)]
+        books: Vec<String>,
+        /// Lay the books out as a kanban board on one screen, in columns
+        /// for overdue, due today, upcoming and never assigned (width from
+        /// $COLUMNS, else 100); ignored with --screen-reader
+        #[arg(long)]
+        board: bool,
+    },
+    /// Post, list and accept duty trade offers
+    Trades {
+        #[command(subcommand)]
+        cmd: TradesCommands,
+    },
+    /// Write the current assignment as an Atom feed, for feed readers and
+    /// intranet portals (publish the file after every assign)
+    Feed {
+        #[arg(long)]
+        book: String,
+        /// URL the feed is published at; used as its id
+        #[arg(long)]
+        link: Option<String>,
+        /// Write to this file instead of stdout
+        #[arg(short, long)]
+        output: Option<PathBuf>,
+    },
+    /// Let someone cover for the scheduled assignee on given dates, as in
+    /// an on-call rotation
+    Override {
+        #[command(subcommand)]
+        cmd: OverrideCommands,
+    },
+    /// Who is on duty on a date, overrides included (projected for future
+    /// periods)
+    Who {
+        #[arg(long)]
+        book: String,
+        /// YYYY-MM-DD; today if omitted
+        #[arg(long)]
+        date: Option<NaiveDate>,
+    },
+    /// Export members as vCards or import them from contacts
+    Contacts {
+        #[command(subcommand)]
+        cmd: ContactsCommands,
+    },
+    /// Leave handover notes for the next assignees
+    Note {
+        #[command(subcommand)]
+        cmd: NoteCommands,
+    },
+    /// Simulate upcoming periods with and without a roster change and compare everyone's load
+    Whatif {
+        #[arg(long)]
+        book: String,
+        /// Comma-separated members to leave out of the simulated roster
+        #[arg(long)]
+        remove: String,
+        /// How many periods to simulate
+        #[arg(long, default_value_t = 12)]
+        periods: usize,
+        /// Seed shared by both simulations, so only the roster change differs
+        #[arg(long, default_value_t = 0)]
+        seed: u64,
+    },
+    /// List the current assignment and project the coming periods: dates
+    /// follow the interval, assignees a seeded rotation (a forecast, not a
+    /// commitment)
+    Schedule {
+        #[arg(long)]
+        book: String,
+        /// Only list this member's duties
+        #[arg(long)]
+        member: Option<String>,
+        /// How many periods to project
+        #[arg(long, default_value_t = 8)]
+        periods: usize,
+        /// Seed for the projected draws
+        #[arg(long, default_value_t = 0)]
+        seed: u64,
+        #[arg(long, value_enum, default_value_t = ScheduleFormat::Text)]
+        format: ScheduleFormat,
+        /// List everyone per period in escalation order instead of just the
+        /// assignees (text and csv only; csv gains a rank column)
+        #[arg(long)]
+        escalation: bool,
+        /// Write to this file instead of stdout
+        #[arg(short, long)]
+        output: Option<PathBuf>,
+    },
     /// Assign this period (returns selected members + updated とうばんのしょ)
     Assign {
         #[arg(long)]
         book: String,
+        #[command(flatten)]
+        opts: AssignArgs,
+    },
+    /// Finalize a pending assignment (returns selected members + updated とうばんのしょ)
+    Confirm {
+        #[arg(long)]
+        book: String,
+    },
+    /// Hand a current assignee's seat to the first backup from
+    /// `assign --with-backup`, moving the count with it
+    PromoteBackup {
+        #[arg(long)]
+        book: String,
+        /// The assignee who cannot serve
+        #[arg(long)]
+        member: String,
+    },
+    /// Promise that a member who sat out takes the next round; assign seats
+    /// them first (returns updated とうばんのしょ)
+    Defer {
+        #[arg(long)]
+        book: String,
+        #[arg(long)]
+        member: String,
+    },
+    /// Exchange two members' counts and last duty after they traded turns
+    /// between themselves (returns updated とうばんのしょ)
+    Swap {
+        #[arg(long)]
+        book: String,
+        #[arg(long)]
+        a: String,
+        #[arg(long)]
+        b: String,
+    },
+    /// Hand a current assignee's seat to someone else mid-period, moving
+    /// the count with it
+    Substitute {
+        #[arg(long)]
+        book: String,
+        /// The assignee who cannot serve
+        #[arg(long)]
+        out: String,
+        /// Who takes over; without it the member with the lowest count who
+        /// is free is picked
+        #[arg(long = "in")]
+        into: Option<String>,
+        /// Optional deterministic seed (u64) to control randomness
+        #[arg(long)]
+        seed: Option<u64>,
+    },
+    /// Take back the latest assign (pending or committed), restoring counts
+    Undo {
+        #[arg(long)]
+        book: String,
+    },
+    /// Redraw the pending (or else the most recent) assignment without re-picking its members
+    Reroll {
+        #[arg(long)]
+        book: String,
+        /// Comma-separated members who objected; they are left out of the redraw
+        #[arg(long)]
+        object: Option<String>,
         /// Optional deterministic seed (u64) to control randomness
         #[arg(long)]
         seed: Option<u64>,
     },
 }
 
-// --------------------- Base64URL <-> Hiragana (one-shot mapping) ---------------------
-fn base64url_char_to_hiragana(ch: char) -> Option<char> {
-    // map base64url char -> index 0..63
-    let idx = match ch as u8 {
-        b'A'..=b'Z' => (ch as u8 - b'A') as u32,      // 0..25
-        b'a'..=b'z' => (ch as u8 - b'a') as u32 + 26, // 26..51
-        b'0'..=b'9' => (ch as u8 - b'0') as u32 + 52, // 52..61
-        b'-' => 62,
-        b'_' => 63,
-        _ => return None,
-    };
-    let cp = HIRAGANA_START + (idx % BASE64_LEN);
-    std::char::from_u32(cp)
-}
-
-fn hiragana_char_to_base64url(ch: char) -> Option<char> {
-    let cp = ch as u32;
-    if cp < HIRAGANA_START || cp >= HIRAGANA_START + BASE64_LEN {
-        return None;
-    }
-    let idx = cp - HIRAGANA_START; // 0..63
-    match idx {
-        0..=25 => std::char::from_u32((b'A' + idx as u8) as u32),
-        26..=51 => std::char::from_u32((b'a' + (idx as u8 - 26)) as u32),
-        52..=61 => std::char::from_u32((b'0' + (idx as u8 - 52)) as u32),
-        62 => Some('-'),
-        63 => Some('_'),
-        _ => None,
+impl Commands {
+    /// Whether the command prints an updated book; --expect-revision only
+    /// guards these.
+    fn writes(&self) -> bool {
+        match self {
+            Commands::Show { .. }
+            | Commands::ListMembers { .. }
+            | Commands::Lint { .. }
+            | Commands::Eq { .. }
+            | Commands::Stats { .. }
+            | Commands::Remind { .. }
+            | Commands::History { .. }
+            | Commands::Topic { .. }
+            | Commands::Me { .. }
+            | Commands::Dashboard { .. }
+            | Commands::Feed { .. }
+            | Commands::Who { .. }
+            | Commands::Whatif { .. }
+            | Commands::Schedule { .. }
+            | Commands::Trades {
+                cmd: TradesCommands::List { .. },
+            }
+            | Commands::Override {
+                cmd: OverrideCommands::List { .. },
+            }
+            | Commands::Contacts {
+                cmd: ContactsCommands::Export { .. },
+            } => false,
+            Commands::Assign { opts, .. } => !opts.dry_run,
+            Commands::Rebalance { dry_run, .. } => !dry_run,
+            _ => true,
+        }
     }
 }
 
-fn base64url_to_hiragana(b64: &str) -> Result<String> {
-    let mut out = String::with_capacity(b64.len());
-    for ch in b64.chars() {
-        let hira = base64url_char_to_hiragana(ch)
-            .ok_or_else(|| anyhow!("invalid base64url char encountered: {:?}", ch))?;
-        out.push(hira);
-    }
-    Ok(out)
+#[derive(Subcommand)]
+enum StatsCommands {
+    /// Dump the recorded rounds, one row per member (date, duty, member),
+    /// for spreadsheets or pandas
+    Export {
+        #[arg(long)]
+        book: String,
+        #[arg(long, value_enum, default_value_t = ExportFormat::Csv)]
+        format: ExportFormat,
+        /// Write to this file instead of stdout
+        #[arg(short, long)]
+        output: Option<PathBuf>,
+    },
 }
 
-fn hiragana_to_base64url(hira: &str) -> Result<String> {
-    let mut out = String::with_capacity(hira.chars().count());
-    for ch in hira.chars() {
-        let b = hiragana_char_to_base64url(ch)
-            .ok_or_else(|| anyhow!("invalid hiragana char encountered: {:?}", ch))?;
-        out.push(b);
-    }
-    Ok(out)
+#[derive(Clone, Copy, ValueEnum)]
+enum ExportFormat {
+    Csv,
 }
 
-// --------------------- Encode / Decode Book ---------------------
-fn encode_book(book: &Book) -> Result<String> {
-    let json = serde_json::to_vec(book).context("serialize book to json")?;
-    let b64 = URL_SAFE_NO_PAD.encode(&json);
-    base64url_to_hiragana(&b64)
+#[derive(Clone, Copy, ValueEnum)]
+enum ScheduleFormat {
+    Text,
+    /// iCalendar, for calendar subscriptions
+    Ics,
+    /// One row per assignee and period, e.g. for label printing
+    Csv,
+    /// Printable HTML name slips, one page per assignee and period
+    Slips,
 }
 
-fn decode_book(hira: &str) -> Result<Book> {
-    let b64 = hiragana_to_base64url(hira)?;
-    let bytes = URL_SAFE_NO_PAD
-        .decode(&b64)
-        .context("base64url decode failed; maybe corrupted とうばんのしょ")?;
-    let book = serde_json::from_slice::<Book>(&bytes).context("json decode failed")?;
-    Ok(book)
+#[derive(Subcommand)]
+enum TradesCommands {
+    /// Offer one of your duties for trade (returns updated とうばんのしょ)
+    Post {
+        #[arg(long)]
+        book: String,
+        #[arg(long)]
+        member: String,
+        /// Which duty is on offer, e.g. "6/10"
+        #[arg(long)]
+        when: String,
+    },
+    /// List open trade offers
+    List {
+        #[arg(long)]
+        book: String,
+    },
+    /// Take over an offered duty (returns updated とうばんのしょ)
+    Accept {
+        #[arg(long)]
+        book: String,
+        #[arg(long)]
+        id: u32,
+        /// Member taking over the duty
+        #[arg(long)]
+        member: String,
+    },
 }
 
-// --------------------- Utilities ---------------------
-fn split_members_arg(s: &str) -> Vec<String> {
-    s.split(',')
-        .map(|p| p.trim().to_string())
-        .filter(|x| !x.is_empty())
-        .collect()
+#[derive(Subcommand)]
+enum OverrideCommands {
+    /// Have a member cover for the scheduled assignee on a range of dates;
+    /// counts are not touched (returns updated とうばんのしょ)
+    Add {
+        #[arg(long)]
+        book: String,
+        /// Who is on duty instead
+        #[arg(long)]
+        member: String,
+        /// First covered date (YYYY-MM-DD)
+        #[arg(long)]
+        from: NaiveDate,
+        /// Last covered date (YYYY-MM-DD), included
+        #[arg(long)]
+        to: NaiveDate,
+        /// The assignee being covered; without it the member covers the
+        /// whole assignment
+        #[arg(long = "for")]
+        replaces: Option<String>,
+    },
+    /// List the overrides, oldest first (later ones win)
+    List {
+        #[arg(long)]
+        book: String,
+    },
+    /// Remove an override (returns updated とうばんのしょ)
+    Remove {
+        #[arg(long)]
+        book: String,
+        #[arg(long)]
+        id: u32,
+    },
 }
 
-// --------------------- Command Implementations ---------------------
-fn cmd_create(people: usize, interval: usize, members: Option<String>) -> Result<()> {
-    if people == 0 {
-        return Err(anyhow!("--people must be >= 1"));
-    }
-    let members_vec = members.map(|s| split_members_arg(&s)).unwrap_or_default();
-    let members_struct = members_vec
-        .into_iter()
-        .map(|name| Member { name, count: 0 })
-        .collect::<Vec<_>>();
-    let book = Book {
-        people,
-        interval,
-        members: members_struct,
-    };
-    let hira = encode_book(&book)?;
-    println!(":桜: あたらしい とうばんのしょ が できました。");
-    println!("{}", hira);
-    Ok(())
+#[derive(Subcommand)]
+enum ContactsCommands {
+    /// Write every member as a vCard 3.0 entry
+    Export {
+        #[arg(long)]
+        book: String,
+        /// Write to this file instead of stdout
+        #[arg(short, long)]
+        output: Option<PathBuf>,
+    },
+    /// Add members from a vCard file or a Google Contacts CSV export (returns updated とうばんのしょ)
+    Import {
+        #[arg(long)]
+        book: String,
+        /// .vcf or .csv file; the format is detected from the contents
+        file: PathBuf,
+    },
 }
 
-fn cmd_show(book_str: String) -> Result<()> {
-    let book = decode_book(&book_str)?;
-    println!(":本: とうばんのしょ の なかみ：");
-    println!(":上半身シルエット_2: とうばん人数: {}", book.people);
-    println!(":リピート: 間隔（日）: {}", book.interval);
-    println!(":上半身シルエット_1: メンバー一覧:");
+#[derive(Subcommand)]
+enum NoteCommands {
+    /// Attach a short note for the next assignees (returns updated とうばんのしょ)
+    Add {
+        #[arg(long)]
+        book: String,
+        /// Current assignee writing the note
+        #[arg(long)]
+        member: String,
+        /// The note, e.g. "ゴミ袋が残り2枚"
+        text: String,
+    },
+}
+
+// --------------------- Output ---------------------
+static SCREEN_READER: AtomicBool = AtomicBool::new(false);
+
+fn screen_reader() -> bool {
+    SCREEN_READER.load(Ordering::Relaxed)
+}
+
+static LENIENT: AtomicBool = AtomicBool::new(false);
+
+/// Decode `text`, repairing kana typos under `--lenient`, and check it is
+/// at the `--expect-revision`.
+fn decode_book(text: &str) -> touban::Result<Book> {
+    let book = if LENIENT.load(Ordering::Relaxed) {
+        decode_lenient(text)?
+    } else {
+        touban::decode_book(text)?
+    };
+    match EXPECT_REVISION.get() {
+        Some(&expected) if expected != book.revision => Err(ToubanError::StaleBook {
+            expected,
+            found: book.revision,
+        }),
+        _ => Ok(book),
+    }
+}
+
+static EXPECT_REVISION: OnceLock<u32> = OnceLock::new();
+
+/// Decode `text` repairing kana typos, and report each repair on stderr.
+fn decode_lenient(text: &str) -> touban::Result<Book> {
+    let (book, fixes) = decode_book_lenient(text)?;
+    print_corrections(&fixes);
+    Ok(book)
+}
+
+/// The JSON of the book in `text`, for [`view_book`]; repairs typos under
+/// `--lenient` like [`decode_book`].
+fn decode_book_bytes(text: &str) -> touban::Result<Vec<u8>> {
+    if !LENIENT.load(Ordering::Relaxed) {
+        return touban::decode_book_bytes(text);
+    }
+    let (bytes, fixes) = decode_book_bytes_lenient(text)?;
+    print_corrections(&fixes);
+    Ok(bytes)
+}
+
+fn print_corrections(fixes: &[Correction]) {
+    for f in fixes {
+        eprintln!(
+            "{}",
+            plain(format!(
+                ":警告: {} 文字目の「{}」を「{}」として読みました",
+                f.pos + 1,
+                f.from,
+                f.to
+            ))
+        );
+    }
+}
+
+static CODEC: OnceLock<&'static dyn BookCodec> = OnceLock::new();
+static AVOID_WORDS: AtomicBool = AtomicBool::new(false);
+
+/// Encode `book` as its next revision with the alphabet chosen by
+/// `--codec`, breaking up words under `--avoid-words`.
+fn encode_book(book: &Book) -> touban::Result<String> {
+    let text = encode_book_update(book, *CODEC.get_or_init(|| &touban::HIRAGANA))?;
+    if AVOID_WORDS.load(Ordering::Relaxed) {
+        return Ok(touban::break_words(&text));
+    }
+    Ok(text)
+}
+
+static ALSO_BASE64: AtomicBool = AtomicBool::new(false);
+
+/// Print a freshly encoded book, followed by its base64url form under
+/// `--also-base64`.
+fn print_book(text: &str) {
+    println!("{}", text);
+    if ALSO_BASE64.load(Ordering::Relaxed) {
+        let text = touban::strip_word_breaks(text);
+        let codec = touban::detect_codec(&text).unwrap_or(&touban::HIRAGANA);
+        let b64: touban::Result<String> =
+            touban::text_chars_to_base64url(codec, text.chars()).collect();
+        if let Ok(b64) = b64 {
+            println!("{}", b64);
+        }
+    }
+}
+
+/// Drop the leading emoji code (":本: ") in screen-reader mode.
+fn plain(line: String) -> String {
+    if !screen_reader() {
+        return line;
+    }
+    let body = line.trim_start_matches('\n');
+    let lead = &line[..line.len() - body.len()];
+    if let Some(rest) = body.strip_prefix(':') {
+        if let Some(end) = rest.find(": ") {
+            if !rest[..end].contains(' ') {
+                return format!("{}{}", lead, &rest[end + 2..]);
+            }
+        }
+    }
+    line
+}
+
+/// Print a line, without its emoji in screen-reader mode.
+macro_rules! say {
+    ($($arg:tt)*) => {
+        println!("{}", plain(format!($($arg)*)))
+    };
+}
+
+/// Print a list line: " - text", or "label: text" in screen-reader mode.
+macro_rules! item {
+    ($label:expr, $($arg:tt)*) => {
+        if screen_reader() {
+            println!("{}: {}", $label, format_args!($($arg)*))
+        } else {
+            println!(" - {}", format_args!($($arg)*))
+        }
+    };
+}
+
+fn shift_header(shift: &Shift) -> String {
+    if screen_reader() {
+        format!("シフト {}、{}人：", shift.name, shift.people)
+    } else {
+        format!(":時計: {}（{}人）：", shift.name, shift.people)
+    }
+}
+
+fn role_mark(role: &str) -> String {
+    if screen_reader() {
+        format!(" 役割 {}", role)
+    } else {
+        format!(" 【{}】", role)
+    }
+}
+
+// --------------------- Utilities ---------------------
+fn split_list_arg(s: &str) -> Vec<String> {
+    s.split(',')
+        .map(|p| p.trim().to_string())
+        .filter(|x| !x.is_empty())
+        .collect()
+}
+
+/// Indices of the named members without repeats, failing on the first
+/// unknown name.
+fn member_indices(book: &Book, names: &[String]) -> Result<Vec<usize>> {
+    let mut out = Vec::new();
+    for name in names {
+        let i = book
+            .members
+            .iter()
+            .position(|m| m.name == *name)
+            .ok_or_else(|| ToubanError::MemberNotFound(name.clone()))?;
+        if !out.contains(&i) {
+            out.push(i);
+        }
+    }
+    Ok(out)
+}
+
+/// Parse durations such as "30m", "24h" or "2d" into seconds.
+fn parse_duration(s: &str) -> Result<u64> {
+    let s = s.trim();
+    let (num, unit) = s.split_at(s.find(|c: char| !c.is_ascii_digit()).unwrap_or(s.len()));
+    let n: u64 = num
+        .parse()
+        .map_err(|_| ToubanError::Invalid(format!("invalid duration: {:?}", s)))?;
+    let secs = match unit {
+        "s" => 1,
+        "m" => 60,
+        "h" | "" => 60 * 60,
+        "d" => 24 * 60 * 60,
+        _ => return Err(ToubanError::Invalid(format!("invalid duration unit: {:?}", s)).into()),
+    };
+    n.checked_mul(secs)
+        .ok_or_else(|| ToubanError::Invalid(format!("duration too long: {:?}", s)).into())
+}
+
+fn format_remaining(deadline: u64) -> String {
+    let left = deadline.saturating_sub(unix_now());
+    if left == 0 {
+        return "猶予期間は終了".to_string();
+    }
+    format!("確定まで あと{}時間{}分", left / 3600, left % 3600 / 60)
+}
+
+fn format_date(unix: u64) -> String {
+    Local
+        .timestamp_opt(unix as i64, 0)
+        .single()
+        .map(|d| d.format("%Y-%m-%d").to_string())
+        .unwrap_or_else(|| "?".to_string())
+}
+
+fn print_warnings(warnings: Vec<Warning>) {
+    for w in warnings {
+        eprintln!("{}", plain(format!(":警告: {}", w)));
+    }
+}
+
+fn print_checklist(checklist: &[String]) {
+    if checklist.is_empty() {
+        return;
+    }
+    say!(":クリップボード: やること:");
+    for item in checklist {
+        if screen_reader() {
+            item!("やること", "{}", item);
+        } else {
+            item!("やること", "[ ] {}", item);
+        }
+    }
+}
+
+// --------------------- Command Implementations ---------------------
+fn cmd_create(opts: CreateArgs) -> Result<()> {
+    let CreateArgs {
+        template,
+        people,
+        interval,
+        members,
+        substitutes,
+        checklist,
+        require_tags,
+        reset,
+    } = opts;
+    let mut builder = match template {
+        Some(t) => BookBuilder::from_template(t),
+        None => BookBuilder::default(),
+    };
+    if let Some(p) = people {
+        builder = builder.people(p);
+    }
+    if let Some(i) = interval {
+        builder = builder.interval(i);
+    }
+    if let Some(c) = checklist {
+        builder = builder.checklist(split_list_arg(&c));
+    }
+    if let Some(n) = reset.reset_threshold {
+        builder = builder.reset_threshold(n);
+    }
+    if let Some(p) = reset.reset_policy {
+        builder = builder.reset_policy(p);
+    }
+    let book = builder
+        .members(members.map(|s| split_list_arg(&s)).unwrap_or_default())
+        .substitutes(substitutes.map(|s| split_list_arg(&s)).unwrap_or_default())
+        .required_tags(require_tags.map(|s| split_list_arg(&s)).unwrap_or_default())
+        .build()?;
+    let hira = encode_book(&book)?;
+    say!(":桜: あたらしい とうばんのしょ が できました。");
+    print_book(&hira);
+    Ok(())
+}
+
+fn cmd_show(book_str: String, json: bool) -> Result<()> {
+    if json {
+        let book = decode_book(&book_str)?;
+        let out = serde_json::json!({
+            "book": book,
+            "warnings": book_warnings(&book),
+        });
+        println!("{}", serde_json::to_string_pretty(&out)?);
+        return Ok(());
+    }
+    let bytes = decode_book_bytes(&book_str)?;
+    let book = view_book(&bytes)?;
+    print_warnings(book.warnings());
+    say!(":本: とうばんのしょ の なかみ（版 {}）：", book.revision);
+    say!(":上半身シルエット_2: とうばん人数: {}", book.people);
+    say!(":リピート: 間隔（日）: {}", book.interval);
+    say!(":上半身シルエット_1: メンバー一覧:");
+    for m in &book.members {
+        let mut tags = if m.tags.is_empty() {
+            String::new()
+        } else {
+            format!(" [{}]", m.tags.join(", "))
+        };
+        if m.onboarding > 0 {
+            tags.push_str(&format!(" (新人: あと{}回ペア)", m.onboarding));
+        }
+        for (role, n) in m.role_counts.iter().chain(&m.shift_counts) {
+            if screen_reader() {
+                tags.push_str(&format!(" {} {}回", role, n));
+            } else {
+                tags.push_str(&format!(" {}×{}", role, n));
+            }
+        }
+        if !m.prefer_shifts.is_empty() {
+            tags.push_str(&format!(" 希望:{}", m.prefer_shifts.join("/")));
+        }
+        if !m.avoid_shifts.is_empty() {
+            tags.push_str(&format!(" 不可:{}", m.avoid_shifts.join("/")));
+        }
+        if !m.exempt.is_empty() {
+            tags.push_str(&format!(" 休み:{}", m.exempt.join("/")));
+        }
+        if let Some(team) = &m.team {
+            tags.push_str(&format!(" チーム:{}", team));
+        }
+        if m.weight != 0 {
+            tags.push_str(&format!(" 重み{}", m.weight() as f64 / 100.0));
+        }
+        if let Some(t) = m.last_assigned_at {
+            tags.push_str(&format!(" 通算{}回 最終{}", m.total, format_date(t)));
+        }
+        if m.substitute {
+            item!("メンバー", "{} (補欠, {}回){}", m.name, m.count, tags);
+        } else {
+            item!("メンバー", "{} ({}回){}", m.name, m.count, tags);
+        }
+    }
+    if !book.required_tags.is_empty() {
+        say!(":盾: 必須タグ: {}", book.required_tags.join(", "));
+    }
+    if !book.roles.is_empty() {
+        say!(":名札: 役割: {}", book.roles.join(", "));
+    }
+    if !book.shifts.is_empty() {
+        let shifts: Vec<String> = book
+            .shifts
+            .iter()
+            .map(|s| format!("{} {}人", s.name, s.people))
+            .collect();
+        say!(":時計: シフト: {}", shifts.join(", "));
+    }
+    for d in &book.duties {
+        let counts: Vec<String> = d
+            .counts
+            .iter()
+            .map(|(name, n)| format!("{} {}回", name, n))
+            .collect();
+        say!(":クリップボード: 当番「{}」 {}人", d.name, d.people);
+        if !d.current.is_empty() {
+            item!("今回", "今回: {}", d.current.join(", "));
+        }
+        if !counts.is_empty() {
+            item!("カウント", "カウント: {}", counts.join(", "));
+        }
+    }
+    if !book.conflicts.is_empty() {
+        let pairs: Vec<String> = book
+            .conflicts
+            .iter()
+            .map(|(a, b)| format!("{} と {}", a, b))
+            .collect();
+        say!(":交差した剣: 同じ回にしない: {}", pairs.join(", "));
+    }
+    if !book.pairs.is_empty() {
+        let pairs: Vec<String> = book
+            .pairs
+            .iter()
+            .map(|(a, b)| format!("{} と {}", a, b))
+            .collect();
+        say!(":二人組: いつも いっしょ: {}", pairs.join(", "));
+    }
+    if !book.deferred.is_empty() {
+        say!(
+            ":指切り: 次のとうばんを約束した人: {}",
+            book.deferred.join(", ")
+        );
+    }
+    for p in &book.planned {
+        let names: Vec<&str> = p.seats.iter().map(|a| a.name.as_str()).collect();
+        say!(
+            ":カレンダー: {} からの回は決定済み: {}",
+            format_date(p.at),
+            names.join(", ")
+        );
+    }
+    match book.reset_policy {
+        ResetPolicy::Zero if book.reset_threshold != 0 => say!(
+            ":反時計回り矢印: だれかの回数が {} 回になったら 全員のカウントをリセットします",
+            book.reset_threshold()
+        ),
+        ResetPolicy::Zero => {}
+        ResetPolicy::Subtract => {
+            say!(":反時計回り矢印: 毎回 いちばん少ない回数を全員から引きます")
+        }
+        ResetPolicy::Never => say!(":反時計回り矢印: カウントはリセットしません"),
+    }
+    if book.history_limit.is_some() {
+        say!(":本: 記録は最新 {} 回分まで残します", book.history_limit());
+    }
+    if book.avoid_recent > 0 {
+        say!(
+            ":カレンダー: 直近 {} 回にとうばんをした人は できるだけ外します",
+            book.avoid_recent
+        );
+    }
+    if book.anti_streak {
+        say!(":カレンダー: 最近とうばんをした人は選ばれにくくなります");
+    }
+    if book.strategy == StrategyKind::RoundRobin {
+        if let Some(next) = book.members.get(book.rr_cursor) {
+            say!(
+                ":時計回り矢印: 名簿の順に回します（次は {} から）",
+                next.name
+            );
+        }
+    }
+    if book.strategy == StrategyKind::PerTeam {
+        say!(":名札: チームごとに 1 人ずつ選びます");
+    }
+    if !book.tie_break.is_random() {
+        let order = match book.tie_break {
+            TieBreak::Name => "名前順",
+            TieBreak::Insertion => "登録順",
+            TieBreak::Hash => "ハッシュ順",
+            TieBreak::Random => "ランダム",
+        };
+        say!(":天秤: 同じ回数の人は {} で選びます", order);
+    }
+    print_checklist(&book.checklist);
+    print_pending(book.pending.as_ref());
+    Ok(())
+}
+
+fn cmd_lint(book_str: String) -> Result<()> {
+    let book = decode_book(&book_str)?;
+    let mut problems = lint_book(&book);
+    if let Err(e) = touban::check::all(&book_str) {
+        problems.push(e.to_string());
+    }
+    if problems.is_empty() {
+        say!(":白いチェックマーク: 問題は見つかりませんでした。");
+        return Ok(());
+    }
+    say!(":警告: 問題が見つかりました:");
+    for p in &problems {
+        item!("問題", "{}", p);
+    }
+    Err(anyhow!("{} 件の問題が見つかりました", problems.len()))
+}
+
+fn cmd_eq(a: String, b: String) -> Result<()> {
+    let book_a = decode_book(&a)?;
+    let book_b = decode_book(&b)?;
+    if book_a.semantic_eq(&book_b) {
+        say!(":イコール: 同じ内容です。");
+        return Ok(());
+    }
+    say!(
+        ":ノットイコール: 内容がちがいます: {}",
+        book_a.diff_fields(&book_b).join(", ")
+    );
+    Err(anyhow!("とうばんのしょ の内容がちがいます"))
+}
+
+fn cmd_stats(book_str: String) -> Result<()> {
+    let book = decode_book(&book_str)?;
+    let all: u32 = book.members.iter().map(|m| m.total).sum();
+    say!(":棒グラフ: とうばんの記録:");
+    for m in &book.members {
+        let share = if all == 0 {
+            0.0
+        } else {
+            m.total as f64 * 100.0 / all as f64
+        };
+        let last = m
+            .last_assigned_at
+            .map(format_date)
+            .unwrap_or_else(|| "-".to_string());
+        item!(
+            "メンバー",
+            "{}: 通算{}回 ({:.1}%) / 現在{}回 / 最終 {}",
+            m.name,
+            m.total,
+            share,
+            m.count,
+            last
+        );
+    }
+    Ok(())
+}
+
+fn csv_field(s: &str) -> String {
+    if s.contains([',', '"', '\n', '\r']) {
+        format!("\"{}\"", s.replace('"', "\"\""))
+    } else {
+        s.to_string()
+    }
+}
+
+fn cmd_stats_export(book_str: String, format: ExportFormat, output: Option<PathBuf>) -> Result<()> {
+    let book = decode_book(&book_str)?;
+    let mut out = String::new();
+    match format {
+        ExportFormat::Csv => {
+            // tidy: one row per member of each round of each duty
+            let mut rounds: Vec<(u64, &str, &[String])> = book
+                .history
+                .iter()
+                .map(|h| (h.at, "とうばん", h.members.as_slice()))
+                .collect();
+            for d in &book.duties {
+                rounds.extend(
+                    d.history
+                        .iter()
+                        .map(|r| (r.at, d.name.as_str(), r.members.as_slice())),
+                );
+            }
+            rounds.sort_by_key(|&(at, ..)| at);
+            out.push_str("date,duty,member\n");
+            for (at, duty, members) in rounds {
+                for m in members {
+                    out.push_str(&format!(
+                        "{},{},{}\n",
+                        format_date(at),
+                        csv_field(duty),
+                        csv_field(m)
+                    ));
+                }
+            }
+        }
+    }
+    match output {
+        Some(path) => {
+            std::fs::write(&path, out).with_context(|| format!("write {}", path.display()))?
+        }
+        None => print!("{}", out),
+    }
+    Ok(())
+}
+
+fn xml_escape(s: &str) -> String {
+    s.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+fn rfc3339(ts: u64) -> String {
+    Local
+        .timestamp_opt(ts as i64, 0)
+        .single()
+        .map(|d| d.to_rfc3339())
+        .unwrap_or_default()
+}
+
+fn cmd_feed(book_str: String, link: Option<String>, output: Option<PathBuf>) -> Result<()> {
+    let book = decode_book(&book_str)?;
+    let id = link
+        .clone()
+        .unwrap_or_else(|| "urn:touban:feed".to_string());
+    // the draw time identifies the entry, so readers keep one per period
+    let drawn_at = book
+        .current
+        .iter()
+        .filter_map(|a| {
+            book.members
+                .iter()
+                .find(|m| m.name == a.name)?
+                .last_assigned_at
+        })
+        .max();
+    let mut out = String::new();
+    out.push_str("<?xml version=\"1.0\" encoding=\"utf-8\"?>\n");
+    out.push_str("<feed xmlns=\"http://www.w3.org/2005/Atom\">\n");
+    out.push_str("  <title>とうばん</title>\n");
+    out.push_str(&format!("  <id>{}</id>\n", xml_escape(&id)));
+    if let Some(link) = &link {
+        out.push_str(&format!(
+            "  <link rel=\"self\" href=\"{}\"/>\n",
+            xml_escape(link)
+        ));
+    }
+    out.push_str(&format!(
+        "  <updated>{}</updated>\n",
+        rfc3339(drawn_at.unwrap_or_else(unix_now))
+    ));
+    if let (false, Some(ts)) = (book.current.is_empty(), drawn_at) {
+        let names: Vec<&str> = book.current.iter().map(|a| a.name.as_str()).collect();
+        let mut content = format!("今回のとうばん: {}", names.join(", "));
+        if let Some(n) = book.current_round().and_then(|h| h.note.as_ref()) {
+            content.push_str(&format!("\nメモ: {}", n));
+        }
+        if !book.checklist.is_empty() {
+            content.push_str(&format!("\nやること: {}", book.checklist.join(", ")));
+        }
+        for n in &book.handover {
+            content.push_str(&format!("\n{} さんから: {}", n.member, n.text));
+        }
+        out.push_str("  <entry>\n");
+        out.push_str(&format!(
+            "    <title>とうばん: {}</title>\n",
+            xml_escape(&names.join(", "))
+        ));
+        out.push_str(&format!("    <id>{}#{}</id>\n", xml_escape(&id), ts));
+        out.push_str(&format!("    <updated>{}</updated>\n", rfc3339(ts)));
+        out.push_str("    <author><name>touban</name></author>\n");
+        out.push_str(&format!(
+            "    <content type=\"text\">{}</content>\n",
+            xml_escape(&content)
+        ));
+        out.push_str("  </entry>\n");
+    }
+    out.push_str("</feed>\n");
+    match output {
+        Some(path) => {
+            std::fs::write(&path, out).with_context(|| format!("write {}", path.display()))?
+        }
+        None => print!("{}", out),
+    }
+    Ok(())
+}
+
+fn vcard_escape(s: &str) -> String {
+    s.replace('\\', "\\\\")
+        .replace(',', "\\,")
+        .replace(';', "\\;")
+        .replace('\n', "\\n")
+}
+
+fn vcard_unescape(s: &str) -> String {
+    let mut out = String::new();
+    let mut chars = s.chars();
+    while let Some(c) = chars.next() {
+        if c != '\\' {
+            out.push(c);
+            continue;
+        }
+        match chars.next() {
+            Some('n') | Some('N') => out.push('\n'),
+            Some(e) => out.push(e),
+            None => {}
+        }
+    }
+    out
+}
+
+/// Split one CSV record; quoted fields may contain commas and doubled quotes.
+fn split_csv_line(line: &str) -> Vec<String> {
+    let mut fields = vec![String::new()];
+    let mut quoted = false;
+    let mut chars = line.chars().peekable();
+    while let Some(c) = chars.next() {
+        match c {
+            '"' if quoted && chars.peek() == Some(&'"') => {
+                chars.next();
+                fields.last_mut().unwrap().push('"');
+            }
+            '"' => quoted = !quoted,
+            ',' if !quoted => fields.push(String::new()),
+            c => fields.last_mut().unwrap().push(c),
+        }
+    }
+    fields
+}
+
+/// Names from a vCard file (FN properties, with folded lines joined).
+fn vcard_names(text: &str) -> Vec<String> {
+    let mut lines: Vec<String> = Vec::new();
+    for l in text.lines() {
+        match (l.strip_prefix([' ', '\t']), lines.last_mut()) {
+            (Some(rest), Some(prev)) => prev.push_str(rest),
+            _ => lines.push(l.to_string()),
+        }
+    }
+    lines
+        .iter()
+        .filter_map(|l| {
+            let (key, value) = l.split_once(':')?;
+            let prop = key.split(';').next()?;
+            prop.eq_ignore_ascii_case("FN")
+                .then(|| vcard_unescape(value))
+        })
+        .collect()
+}
+
+/// Names from a Google Contacts CSV export: the "Name" column, or else
+/// "First Name" and "Last Name" joined.
+fn contacts_csv_names(text: &str) -> Result<Vec<String>> {
+    let mut rows = text.lines().map(split_csv_line);
+    let header = rows.next().unwrap_or_default();
+    let col = |name: &str| header.iter().position(|h| h.trim() == name);
+    let (name, first, last) = (col("Name"), col("First Name"), col("Last Name"));
+    if name.is_none() && first.is_none() && last.is_none() {
+        return Err(ToubanError::Invalid(
+            "CSV に Name / First Name / Last Name の列がありません".to_string(),
+        )
+        .into());
+    }
+    let get = |row: &[String], c: Option<usize>| {
+        c.and_then(|c| row.get(c))
+            .map(|s| s.trim().to_string())
+            .unwrap_or_default()
+    };
+    Ok(rows
+        .map(|row| match get(&row, name) {
+            n if !n.is_empty() => n,
+            _ => format!("{} {}", get(&row, first), get(&row, last)),
+        })
+        .collect())
+}
+
+fn cmd_contacts_export(book_str: String, output: Option<PathBuf>) -> Result<()> {
+    let book = decode_book(&book_str)?;
+    let mut out = String::new();
     for m in &book.members {
-        println!(" - {} ({}回)", m.name, m.count);
+        let name = vcard_escape(&m.name);
+        out.push_str(&format!(
+            "BEGIN:VCARD\r\nVERSION:3.0\r\nFN:{}\r\nN:{};;;;\r\nEND:VCARD\r\n",
+            name, name
+        ));
+    }
+    match output {
+        Some(path) => {
+            std::fs::write(&path, out).with_context(|| format!("write {}", path.display()))?
+        }
+        None => print!("{}", out),
+    }
+    Ok(())
+}
+
+fn cmd_contacts_import(book_str: String, file: PathBuf) -> Result<()> {
+    let mut book = decode_book(&book_str)?;
+    let text =
+        std::fs::read_to_string(&file).with_context(|| format!("read {}", file.display()))?;
+    let text = text.trim_start_matches('\u{feff}');
+    let names = if text
+        .trim_start()
+        .to_ascii_uppercase()
+        .starts_with("BEGIN:VCARD")
+    {
+        vcard_names(text)
+    } else {
+        contacts_csv_names(text)?
+    };
+    let mut added = Vec::new();
+    for name in names {
+        let name = name.trim().to_string();
+        if name.is_empty() || book.members.iter().any(|m| m.name == name) {
+            continue;
+        }
+        book.add_member(name.clone(), false, 0)?;
+        added.push(name);
+    }
+    let hira = encode_book(&book)?;
+    say!(
+        ":上半身シルエット_1: {} 人を追加しました: {}",
+        added.len(),
+        added.join(", ")
+    );
+    print_book(&hira);
+    Ok(())
+}
+
+fn cmd_list_members(book_str: String) -> Result<()> {
+    let bytes = decode_book_bytes(&book_str)?;
+    let view = view_book(&bytes)?;
+    print_warnings(view.warnings());
+    say!(
+        ":上半身シルエット_1: メンバー一覧 ({}人, とうばん人数 {}):",
+        view.members.len(),
+        view.people
+    );
+    for m in &view.members {
+        if m.substitute {
+            item!("メンバー", "{} (補欠, {}回)", m.name, m.count);
+        } else {
+            item!("メンバー", "{} ({}回)", m.name, m.count);
+        }
+    }
+    Ok(())
+}
+
+fn cmd_add_member(
+    book_str: String,
+    member: String,
+    substitute: bool,
+    onboarding: Option<u8>,
+) -> Result<()> {
+    let mut book = decode_book(&book_str)?;
+    book.add_member(member, substitute, onboarding.unwrap_or(0))?;
+    let hira = encode_book(&book)?;
+    say!(":上半身シルエット_1: メンバーを追加しました。");
+    print_book(&hira);
+    Ok(())
+}
+
+fn cmd_remove_member(book_str: String, member: String) -> Result<()> {
+    let mut book = decode_book(&book_str)?;
+    book.remove_member(&member)?;
+    let hira = encode_book(&book)?;
+    say!(":ハロー: メンバーを削除しました。");
+    print_book(&hira);
+    Ok(())
+}
+
+fn cmd_tag(book_str: String, member: String, tag: String) -> Result<()> {
+    let mut book = decode_book(&book_str)?;
+    let m = find_member_mut(&mut book, &member)?;
+    if !m.tags.contains(&tag) {
+        m.tags.push(tag);
+    }
+    let hira = encode_book(&book)?;
+    say!(":ラベル: {} さんの タグを更新しました。", member);
+    print_book(&hira);
+    Ok(())
+}
+
+fn cmd_untag(book_str: String, member: String, tag: String) -> Result<()> {
+    let mut book = decode_book(&book_str)?;
+    let m = find_member_mut(&mut book, &member)?;
+    let before = m.tags.len();
+    m.tags.retain(|t| *t != tag);
+    if m.tags.len() == before {
+        return Err(ToubanError::Invalid(format!(
+            "「{}」さんに タグ「{}」はありません",
+            member, tag
+        ))
+        .into());
+    }
+    let hira = encode_book(&book)?;
+    say!(":ラベル: {} さんの タグを更新しました。", member);
+    print_book(&hira);
+    Ok(())
+}
+
+fn cmd_set_required_tags(book_str: String, tags: String) -> Result<()> {
+    let mut book = decode_book(&book_str)?;
+    book.required_tags = split_list_arg(&tags);
+    let hira = encode_book(&book)?;
+    say!(":盾: 必須タグを更新しました。");
+    print_book(&hira);
+    Ok(())
+}
+
+fn cmd_set_roles(book_str: String, roles: String) -> Result<()> {
+    let mut book = decode_book(&book_str)?;
+    book.roles = split_list_arg(&roles);
+    let hira = encode_book(&book)?;
+    say!(":名札: 役割を更新しました。");
+    print_book(&hira);
+    Ok(())
+}
+
+fn cmd_set_shifts(book_str: String, shifts: String) -> Result<()> {
+    let mut book = decode_book(&book_str)?;
+    let mut parsed = Vec::new();
+    for item in split_list_arg(&shifts) {
+        let (name, people) = item
+            .rsplit_once(':')
+            .and_then(|(n, p)| Some((n.trim(), p.trim().parse::<usize>().ok()?)))
+            .filter(|&(n, p)| !n.is_empty() && p > 0)
+            .ok_or_else(|| {
+                ToubanError::Invalid(format!("「{}」は シフト名:人数 の形ではありません", item))
+            })?;
+        if parsed.iter().any(|s: &Shift| s.name == name) {
+            return Err(ToubanError::Invalid(format!("シフト「{}」が重複しています", name)).into());
+        }
+        parsed.push(Shift {
+            name: name.to_string(),
+            people,
+        });
+    }
+    if !parsed.is_empty() {
+        book.people = parsed.iter().map(|s| s.people).sum();
+    }
+    book.shifts = parsed;
+    let hira = encode_book(&book)?;
+    say!(
+        ":時計: シフトを更新しました。（とうばん人数: {}）",
+        book.people
+    );
+    print_book(&hira);
+    Ok(())
+}
+
+fn cmd_set_shift_prefs(
+    book_str: String,
+    member: String,
+    prefer: String,
+    avoid: String,
+) -> Result<()> {
+    let mut book = decode_book(&book_str)?;
+    let (prefer, avoid) = (split_list_arg(&prefer), split_list_arg(&avoid));
+    for s in prefer.iter().chain(&avoid) {
+        if !book.shifts.iter().any(|x| x.name == *s) {
+            return Err(ToubanError::Invalid(format!("シフト「{}」はありません", s)).into());
+        }
+    }
+    if let Some(s) = prefer.iter().find(|s| avoid.contains(s)) {
+        return Err(ToubanError::Invalid(format!(
+            "シフト「{}」が希望と不可の両方に入っています",
+            s
+        ))
+        .into());
+    }
+    let m = find_member_mut(&mut book, &member)?;
+    m.prefer_shifts = prefer;
+    m.avoid_shifts = avoid;
+    let hira = encode_book(&book)?;
+    say!(":時計: {} さんのシフトの希望を更新しました。", member);
+    print_book(&hira);
+    Ok(())
+}
+
+fn cmd_set_exempt(book_str: String, member: String, rules: String) -> Result<()> {
+    let mut book = decode_book(&book_str)?;
+    let rules = split_list_arg(&rules);
+    for rule in &rules {
+        parse_exempt_rule(rule)?;
+    }
+    find_member_mut(&mut book, &member)?.exempt = rules;
+    let hira = encode_book(&book)?;
+    say!(":カレンダー: {} さんの除外ルールを更新しました。", member);
+    print_book(&hira);
+    Ok(())
+}
+
+fn cmd_set_team(book_str: String, member: String, team: String) -> Result<()> {
+    let mut book = decode_book(&book_str)?;
+    let team = team.trim().to_string();
+    let m = find_member_mut(&mut book, &member)?;
+    if team.is_empty() {
+        m.team = None;
+        say!(":名札: {} さんをチームから外しました。", member);
+    } else {
+        say!(":名札: {} さんを チーム「{}」に入れました。", member, team);
+        m.team = Some(team);
+    }
+    let hira = encode_book(&book)?;
+    print_book(&hira);
+    Ok(())
+}
+
+fn cmd_set_weight(book_str: String, member: String, weight: f64) -> Result<()> {
+    let mut book = decode_book(&book_str)?;
+    if !(0.01..=10.0).contains(&weight) {
+        return Err(ToubanError::Invalid(
+            "--weight は 0.01 から 10 までにしてください".to_string(),
+        )
+        .into());
+    }
+    find_member_mut(&mut book, &member)?.set_weight((weight * 100.0).round() as u16)?;
+    let hira = encode_book(&book)?;
+    say!(":天秤: {} さんの重みを {} にしました。", member, weight);
+    print_book(&hira);
+    Ok(())
+}
+
+fn cmd_rebalance(book_str: String, dry_run: bool) -> Result<()> {
+    let mut book = decode_book(&book_str)?;
+    let changed = rebalance_counts(&mut book);
+    if changed.is_empty() {
+        say!(":天秤: カウントは もう ならされています。");
+    } else {
+        say!(":天秤: カウントを ならしました：");
+        for (i, before) in changed {
+            let m = &book.members[i];
+            item!("カウント", "{}: {}回 → {}回", m.name, before, m.count);
+        }
+    }
+    if dry_run {
+        say!("\n:虫眼鏡: お試しです。とうばんのしょ は変わっていません。");
+        return Ok(());
+    }
+    let hira = encode_book(&book)?;
+    print_book(&hira);
+    Ok(())
+}
+
+fn cmd_add_duty(book_str: String, name: String, people: usize) -> Result<()> {
+    let mut book = decode_book(&book_str)?;
+    book.add_duty(&name, people)?;
+    let hira = encode_book(&book)?;
+    say!(
+        ":クリップボード: 当番「{}」（{} 人）を追加しました。",
+        name,
+        people
+    );
+    print_book(&hira);
+    Ok(())
+}
+
+fn cmd_remove_duty(book_str: String, name: String) -> Result<()> {
+    let mut book = decode_book(&book_str)?;
+    book.remove_duty(&name)?;
+    let hira = encode_book(&book)?;
+    say!(":クリップボード: 当番「{}」を削除しました。", name);
+    print_book(&hira);
+    Ok(())
+}
+
+fn cmd_add_conflict(book_str: String, a: String, b: String) -> Result<()> {
+    let mut book = decode_book(&book_str)?;
+    book.add_conflict(&a, &b)?;
+    let hira = encode_book(&book)?;
+    say!(
+        ":交差した剣: {} さんと {} さんは 同じ回に選ばれなくなりました。",
+        a,
+        b
+    );
+    print_book(&hira);
+    Ok(())
+}
+
+fn cmd_remove_conflict(book_str: String, a: String, b: String) -> Result<()> {
+    let mut book = decode_book(&book_str)?;
+    book.remove_conflict(&a, &b)?;
+    let hira = encode_book(&book)?;
+    say!(
+        ":握手: {} さんと {} さんは 同じ回にも選ばれるようになりました。",
+        a,
+        b
+    );
+    print_book(&hira);
+    Ok(())
+}
+
+fn cmd_add_pair(book_str: String, a: String, b: String) -> Result<()> {
+    let mut book = decode_book(&book_str)?;
+    book.add_pair(&a, &b)?;
+    let hira = encode_book(&book)?;
+    say!(
+        ":二人組: {} さんと {} さんは いつも いっしょに選ばれます。",
+        a,
+        b
+    );
+    print_book(&hira);
+    Ok(())
+}
+
+fn cmd_remove_pair(book_str: String, a: String, b: String) -> Result<()> {
+    let mut book = decode_book(&book_str)?;
+    book.remove_pair(&a, &b)?;
+    let hira = encode_book(&book)?;
+    say!(":二人組: {} さんと {} さんの組を解きました。", a, b);
+    print_book(&hira);
+    Ok(())
+}
+
+fn cmd_config(book_str: String, c: ConfigArgs) -> Result<()> {
+    let mut book = decode_book(&book_str)?;
+    if let Some(p) = c.people {
+        if p == 0 {
+            return Err(ToubanError::Invalid("--people must be >= 1".to_string()).into());
+        }
+        if !book.shifts.is_empty() {
+            return Err(ToubanError::Invalid(
+                "シフトがあるときは set-shifts で人数を変えてください".to_string(),
+            )
+            .into());
+        }
+        book.people = p;
+    }
+    if let Some(i) = c.interval {
+        book.interval = i;
+    }
+    if let Some(a) = c.anti_streak {
+        book.anti_streak = a;
+    }
+    if let Some(n) = c.avoid_recent {
+        book.avoid_recent = n;
+    }
+    if let Some(t) = c.tie_break {
+        book.tie_break = t;
+    }
+    if let Some(s) = c.strategy {
+        book.strategy = s;
+    }
+    if let Some(n) = c.reset.reset_threshold {
+        book.set_reset_threshold(n)?;
+    }
+    if let Some(p) = c.reset.reset_policy {
+        book.reset_policy = p;
+    }
+    if let Some(n) = c.history_limit {
+        book.set_history_limit(n);
+    }
+    if book.avoid_recent as usize > book.history_limit() {
+        return Err(ToubanError::Invalid(format!(
+            "--avoid-recent は 記録を残す回数（{} 回）以下にしてください",
+            book.history_limit()
+        ))
+        .into());
+    }
+    let hira = encode_book(&book)?;
+    say!(":歯車: 設定を更新しました。");
+    print_book(&hira);
+    Ok(())
+}
+
+fn cmd_set_checklist(book_str: String, checklist: String) -> Result<()> {
+    let mut book = decode_book(&book_str)?;
+    book.checklist = split_list_arg(&checklist);
+    let hira = encode_book(&book)?;
+    say!(":クリップボード: やることを更新しました。");
+    print_book(&hira);
+    Ok(())
+}
+
+/// Print, for every member, why they were or weren't picked by
+/// `pick_members` (call before the counts are bumped).
+fn explain_selection(book: &Book, excluded: &[usize], included: &[usize], selected: &[usize]) {
+    let (regulars, substitutes) = member_pools(book, excluded);
+    let min_of = |pool: &[usize]| pool.iter().map(|&i| book.members[i].count).min();
+    let shortfall = book.people.saturating_sub(regulars.len());
+    let required = expand_required_tags(book);
+    let recent = recent_assignees(book);
+    say!(":虫眼鏡: 選ばれた理由:");
+    for (i, m) in book.members.iter().enumerate() {
+        let picked = selected.contains(&i);
+        let pool = if m.substitute {
+            &substitutes
+        } else {
+            &regulars
+        };
+        let minc = min_of(pool).unwrap_or(0);
+        // everyone with a lower count was picked, so this tier filled the rest
+        let filled_up = pool
+            .iter()
+            .filter(|&&j| book.members[j].count < m.count)
+            .all(|j| selected.contains(j));
+        let reason = if excluded.contains(&i) {
+            "除外されています".to_string()
+        } else if included.contains(&i) {
+            "自分から引き受けました".to_string()
+        } else if picked && book.deferred.contains(&m.name) {
+            "前回休んだ分を 約束どおり引き受けました".to_string()
+        } else if recent.contains(&i) && !picked {
+            format!(
+                "直近 {} 回以内にとうばんをしたため 外れました",
+                book.avoid_recent
+            )
+        } else if m.substitute && shortfall == 0 && !picked {
+            "補欠（通常メンバーで足りています）".to_string()
+        } else if book.strategy == StrategyKind::RoundRobin {
+            if picked {
+                "名簿の順番で回ってきました".to_string()
+            } else {
+                "今回は順番ではありません".to_string()
+            }
+        } else if book.strategy == StrategyKind::PerTeam && m.team.is_none() {
+            "チームに入っていません".to_string()
+        } else if picked && m.count == minc {
+            format!("最少回数 {} 回の候補から抽選で選ばれました", minc)
+        } else if picked && m.tags.iter().any(|t| required.contains(t)) {
+            format!("必須タグのため 回数 {} 回でも選ばれました", m.count)
+        } else if picked && filled_up {
+            format!(
+                "回数の少ない人だけでは足りず 回数 {} 回から選ばれました",
+                m.count
+            )
+        } else if picked {
+            format!("新人とのペアのため 回数 {} 回でも選ばれました", m.count)
+        } else if m.count == minc {
+            format!("最少回数 {} 回の候補でしたが 抽選で外れました", minc)
+        } else {
+            format!("回数 {} 回（最少は {} 回）", m.count, minc)
+        };
+        if screen_reader() {
+            let mark = if picked {
+                "選ばれた"
+            } else {
+                "選ばれなかった"
+            };
+            println!("{}: {}、{}", mark, m.name, reason);
+        } else {
+            let mark = if picked { "○" } else { "×" };
+            println!(" {} {}: {}", mark, m.name, reason);
+        }
+    }
+}
+
+/// Added to --seed for the tie-breaks of --escalation, which must not use up
+/// draws from the selection's own generator.
+const ESCALATION_SEED_OFFSET: u64 = 0x6573_6361;
+
+/// The generator for --escalation orders, apart from the selection's.
+fn escalation_rng(seed: Option<u64>) -> ChaCha8Rng {
+    make_rng(seed.map(|s| s.wrapping_add(ESCALATION_SEED_OFFSET)))
+}
+
+fn make_rng(seed: Option<u64>) -> ChaCha8Rng {
+    // deterministic if seed given
+    match seed {
+        Some(s) => ChaCha8Rng::seed_from_u64(s),
+        None => ChaCha8Rng::from_entropy(),
+    }
+}
+
+/// Report the assignment just committed, shift by shift.
+fn print_assignment(book: &Book) {
+    let entry = book.current_round();
+    print_round(book, &book.current, entry, "今週のとうばん");
+    print_checklist(&book.checklist);
+    print_handover(book);
+}
+
+/// The seats of one round under `title`, with the note and number from its
+/// history `entry`.
+fn print_round(book: &Book, seats: &[Assignee], entry: Option<&HistoryEntry>, title: &str) {
+    say!(":ダーツ: {}：", title);
+    let mut last_shift = None;
+    for a in seats {
+        if let Some(s) = book
+            .shifts
+            .iter()
+            .find(|s| Some(&s.name) == a.shift.as_ref())
+        {
+            if last_shift != a.shift.as_ref() {
+                say!("{}", shift_header(s));
+            }
+        }
+        last_shift = a.shift.as_ref();
+        let Some(m) = book.members.iter().find(|m| m.name == a.name) else {
+            continue;
+        };
+        let role_label = a.role.as_deref().map(role_mark).unwrap_or_default();
+        if m.substitute {
+            item!(
+                "とうばん",
+                "{} (補欠, {}回め){}",
+                m.name,
+                m.count,
+                role_label
+            );
+        } else {
+            item!("とうばん", "{} ({}回め){}", m.name, m.count, role_label);
+        }
+    }
+    if !book.backups.is_empty() {
+        say!(
+            ":ベンチ: 控え（この順に代わります）: {}",
+            book.backups.join(", ")
+        );
+    }
+    if let Some(h) = entry {
+        if let Some(n) = &h.note {
+            say!(":メモ: この回のメモ: {}", n);
+        }
+        say!(":ラベル: 割り当て番号: {:08x}", h.nonce);
+    }
+}
+
+fn print_handover(book: &Book) {
+    if book.handover.is_empty() {
+        return;
+    }
+    say!(":メモ: 前回のとうばんからの引き継ぎ：");
+    for n in &book.handover {
+        item!("引き継ぎ", "{} ({} さん)", n.text, n.member);
+    }
+}
+
+fn print_pending(pending: Option<&Pending>) {
+    let Some(p) = pending else {
+        return;
+    };
+    say!(
+        ":砂時計: 仮のとうばん（{}）：",
+        format_remaining(p.deadline)
+    );
+    for name in &p.members {
+        item!("仮のとうばん", "{}", name);
+    }
+}
+
+fn cmd_assign(book_str: String, opts: AssignArgs) -> Result<()> {
+    let AssignArgs {
+        mut seed,
+        seed_from_date,
+        grace,
+        explain,
+        exclude,
+        include,
+        dry_run,
+        force,
+        with_backup,
+        note,
+        escalation,
+        rounds,
+        duty,
+    } = opts;
+    let mut book = decode_book(&book_str)?;
+    // the book's own duty, then the further ones
+    let (own, duties) = match duty.as_deref() {
+        None => (true, Vec::new()),
+        Some("all") => (true, book.duties.iter().map(|d| d.name.clone()).collect()),
+        Some(name) => {
+            if !book.duties.iter().any(|d| d.name == name) {
+                return Err(ToubanError::Invalid(format!(
+                    "当番「{}」はありません（add-duty で追加してください）",
+                    name
+                ))
+                .into());
+            }
+            (false, vec![name.to_string()])
+        }
+    };
+    if !own
+        && (grace.is_some()
+            || include.is_some()
+            || with_backup.is_some()
+            || note.is_some()
+            || explain
+            || escalation)
+    {
+        return Err(ToubanError::Invalid(
+            "--grace, --include, --with-backup, --note, --explain, --escalation は この とうばんのしょ 自体の当番にだけ使えます"
+                .to_string(),
+        )
+        .into());
+    }
+    // two organizers drawing from the same book would fork it; seating a
+    // planned round draws nothing
+    let today = format_date(unix_now());
+    let drawn_today =
+        |at: Option<u64>| !force && !dry_run && at.is_some_and(|t| format_date(t) == today);
+    if own && book.planned.is_empty() && drawn_today(book.drawn_at) {
+        let (nonce, members) = book
+            .current_round()
+            .map_or((0, String::new()), |h| (h.nonce, h.members.join(", ")));
+        return Err(ToubanError::Invalid(format!(
+            "今日はもう割り当て済みです（割り当て番号 {:08x}: {}）。もう一度割り当てるなら --force を付けてください",
+            nonce, members
+        ))
+        .into());
+    }
+    if let Some(d) = book
+        .duties
+        .iter()
+        .find(|d| duties.contains(&d.name) && drawn_today(d.drawn_at))
+    {
+        return Err(ToubanError::Invalid(format!(
+            "今日はもう「{}」を割り当て済みです（{}）。もう一度割り当てるなら --force を付けてください",
+            d.name,
+            d.current.join(", ")
+        ))
+        .into());
+    }
+    if own {
+        print_warnings(book_warnings(&book));
+    }
+    let exclude = exclude.map(|s| split_list_arg(&s)).unwrap_or_default();
+    let excluded = member_indices(&book, &exclude)?;
+    let included = member_indices(
+        &book,
+        &include.map(|s| split_list_arg(&s)).unwrap_or_default(),
+    )?;
+    if let Some(date) = seed_from_date {
+        let date = date.unwrap_or_else(|| Local::now().date_naive());
+        let s = touban::date_seed(date);
+        say!(
+            ":サイコロ: {} から決めた乱数のたね: {}",
+            date.format("%Y-%m-%d"),
+            s
+        );
+        seed = Some(s);
+    }
+    let mut rng = make_rng(seed);
+    let mut order_rng = escalation_rng(seed);
+    // a round planned by an earlier --rounds is seated instead of drawn
+    let planned = own && !book.planned.is_empty();
+    if planned {
+        touban::assign(&mut book, &mut rng)?;
+        say!(":カレンダー: assign --rounds で決めてあった回です。");
+        print_assignment(&book);
+    }
+    // --duty NAME rolls only that duty
+    let rounds = if own && !planned { rounds } else { 0 };
+    let now = unix_now();
+    for k in 1..=rounds {
+        // round k starts k-1 intervals from today and is stamped so
+        let at = now + (k as u64 - 1) * book.interval as u64 * 86400;
+        let date = touban::local_date(at).unwrap_or_else(|| Local::now().date_naive());
+        let Draw {
+            selected: selected_idx,
+            mut exempt,
+            reset,
+            reset_substitutes,
+        } = draw_on(&mut book, date, &excluded, &included, &mut rng)?;
+        exempt.extend(&excluded);
+        let subtracted = book.reset_policy == ResetPolicy::Subtract;
+        match (reset, subtracted) {
+            (false, _) => {}
+            (true, false) => say!(":反時計回り矢印: 全員のカウントをリセットしました。"),
+            (true, true) => {
+                say!(":反時計回り矢印: 全員のカウントから いちばん少ない回数を引きました。")
+            }
+        }
+        match (reset_substitutes, subtracted) {
+            (false, _) => {}
+            (true, false) => say!(":反時計回り矢印: 補欠のカウントをリセットしました。"),
+            (true, true) => {
+                say!(":反時計回り矢印: 補欠のカウントから いちばん少ない回数を引きました。")
+            }
+        }
+        if explain {
+            explain_selection(&book, &exempt, &included, &selected_idx);
+        }
+        let order: Vec<String> = if escalation {
+            escalation_order(&book, &exempt, &selected_idx, &mut order_rng)
+                .iter()
+                .map(|&i| book.members[i].name.clone())
+                .collect()
+        } else {
+            Vec::new()
+        };
+        if let Some(g) = &grace {
+            book.pending = Some(Pending {
+                members: selected_idx
+                    .iter()
+                    .map(|&i| book.members[i].name.clone())
+                    .collect(),
+                deadline: unix_now()
+                    .checked_add(parse_duration(g)?)
+                    .ok_or_else(|| ToubanError::Invalid(format!("duration too long: {:?}", g)))?,
+                // a reroll must not bring back someone who is away
+                objected: exclude.clone(),
+                note: note.clone(),
+            });
+            print_pending(book.pending.as_ref());
+            print_escalation(&order);
+        } else {
+            let backups = with_backup
+                .map(|n| pick_backups(&book, &exempt, &selected_idx, n, &mut rng))
+                .unwrap_or_default();
+            // later rounds are counted now but seated when their day comes
+            if k == 1 {
+                apply_assignment(&mut book, &selected_idx);
+            } else {
+                plan_round(&mut book, &selected_idx, at);
+            }
+            set_round_note(&mut book, note.clone());
+            book.backups = backups
+                .iter()
+                .map(|&i| book.members[i].name.clone())
+                .collect();
+            if rounds == 1 {
+                print_assignment(&book);
+            } else {
+                let seats = book.planned.last().filter(|_| k > 1);
+                let title = if book.interval == 0 {
+                    format!("第{}回のとうばん", k)
+                } else {
+                    format!("第{}回のとうばん（{}〜）", k, format_date(at))
+                };
+                print_round(
+                    &book,
+                    seats.map_or(&book.current, |p| &p.seats),
+                    book.history.last(),
+                    &title,
+                );
+            }
+            print_escalation(&order);
+        }
+    }
+    if rounds > 1 {
+        print_checklist(&book.checklist);
+        print_handover(&book);
+    }
+    for name in &duties {
+        let selected = assign_duty(&mut book, name, &excluded, &mut rng)
+            .with_context(|| format!("当番「{}」", name))?;
+        say!(":ダーツ: 今週の{}：", name);
+        for i in selected {
+            item!("とうばん", "{}", book.members[i].name);
+        }
     }
+    if dry_run {
+        say!("\n:虫眼鏡: お試しです。とうばんのしょ は変わっていません。");
+        return Ok(());
+    }
+    let hira = encode_book(&book)?;
+    say!("\n:青い本: とうばんのしょ（更新後）:");
+    print_book(&hira);
     Ok(())
 }
 
-fn cmd_add_member(book_str: String, member: String) -> Result<()> {
-    let mut book = decode_book(&book_str)?;
-    if book.members.iter().any(|m| m.name == member) {
-        return Err(anyhow!("メンバー「{}」は既に存在します", member));
+fn print_escalation(order: &[String]) {
+    if order.is_empty() {
+        return;
     }
-    let avg = if book.members.is_empty() {
-        0
-    } else {
-        let s: usize = book.members.iter().map(|m| m.count as usize).sum();
-        ((s as f64) / (book.members.len() as f64)).round() as u8
+    say!(":電話: 呼び出し順:");
+    for (k, name) in order.iter().enumerate() {
+        item!("呼び出し順", "{}番手 {}", k + 1, name);
+    }
+}
+
+/// Run `periods` draws on `book` without printing them and return how many times each
+/// member was picked.
+fn simulate(mut book: Book, periods: usize, seed: u64) -> Result<Vec<(String, u32)>> {
+    let before: Vec<(String, u32)> = book
+        .members
+        .iter()
+        .map(|m| (m.name.clone(), m.total))
+        .collect();
+    for round in touban::rotation(&book, seed).take(periods) {
+        book = round?.book;
+    }
+    Ok(before
+        .into_iter()
+        .zip(&book.members)
+        .map(|((name, total), m)| (name, m.total - total))
+        .collect())
+}
+
+fn ics_escape(s: &str) -> String {
+    s.replace('\\', "\\\\")
+        .replace(';', "\\;")
+        .replace(',', "\\,")
+        .replace('\n', "\\n")
+}
+
+/// Fold an iCalendar content line at 75 octets (RFC 5545 3.1).
+fn ics_line(out: &mut String, line: &str) {
+    let mut len = 0;
+    for c in line.chars() {
+        if len + c.len_utf8() > 75 {
+            out.push_str("\r\n ");
+            len = 1;
+        }
+        out.push(c);
+        len += c.len_utf8();
+    }
+    out.push_str("\r\n");
+}
+
+fn cmd_schedule(
+    book_str: String,
+    member: Option<String>,
+    periods_ahead: usize,
+    seed: u64,
+    format: ScheduleFormat,
+    escalation: bool,
+    output: Option<PathBuf>,
+) -> Result<()> {
+    let book = decode_book(&book_str)?;
+    if book.interval == 0 {
+        return Err(ToubanError::Invalid(
+            "間隔が 0 日の とうばんのしょ には 予定の日付がありません".to_string(),
+        )
+        .into());
+    }
+    if escalation && !matches!(format, ScheduleFormat::Text | ScheduleFormat::Csv) {
+        return Err(
+            ToubanError::Invalid("--escalation は text と csv でだけ使えます".to_string()).into(),
+        );
+    }
+    if let Some(name) = &member {
+        if !book.members.iter().any(|m| m.name == *name) {
+            return Err(ToubanError::MemberNotFound(name.clone()).into());
+        }
+    }
+    let start = next_due_date(&book).unwrap_or_else(|| Local::now().date_naive());
+    let interval = chrono::Days::new(book.interval as u64);
+    let mut rows = Vec::new();
+    // with --escalation each period lists everyone, the assignees first
+    let mut rng = escalation_rng(Some(seed));
+    let mut ranked = |book: &Book, selected: Vec<String>| {
+        if !escalation {
+            return selected;
+        }
+        let idx: Vec<usize> = selected
+            .iter()
+            .filter_map(|n| book.members.iter().position(|m| m.name == *n))
+            .collect();
+        escalation_order(book, &[], &idx, &mut rng)
+            .iter()
+            .map(|&i| book.members[i].name.clone())
+            .collect()
     };
-    book.members.push(Member {
-        name: member,
-        count: avg as u8,
+    let current: Vec<String> = book.current.iter().map(|a| a.name.clone()).collect();
+    // rounds planned by assign --rounds sit between the current one and
+    // the projection
+    let planned = book.planned.iter().filter_map(|p| {
+        let names = p.seats.iter().map(|a| a.name.clone()).collect();
+        Some((touban::local_date(p.at)?, names))
     });
+    let first_planned = planned.clone().next().map(|(d, _)| d);
+    let current_start = first_planned.unwrap_or(start) - interval;
+    let mut periods: Vec<(NaiveDate, Vec<String>, Book)> =
+        std::iter::once((current_start, current))
+            .chain(planned)
+            .filter(|(_, seated)| !seated.is_empty())
+            .map(|(d, seated)| (d, seated, book.clone()))
+            .collect();
+    // projected like assign draws: exemptions of each date and deferrals
+    for round in touban::rotation(&book, seed).take(periods_ahead) {
+        let round = round?;
+        periods.push((round.date, round.selected, round.book));
+    }
+    for (d, seated, at) in periods {
+        // whoever covers the period's first day per the overrides
+        let names = on_duty(&book, d, &seated);
+        if member.as_ref().is_none_or(|m| names.contains(m)) {
+            rows.push((d, ranked(&at, names)));
+        }
+    }
+    let out = match format {
+        ScheduleFormat::Text => {
+            let sep = if escalation { " > " } else { ", " };
+            rows.iter()
+                .map(|(d, names)| format!("{} {}\n", d.format("%Y-%m-%d"), names.join(sep)))
+                .collect()
+        }
+        ScheduleFormat::Csv => {
+            let mut out = if escalation {
+                "start,end,name,rank\n"
+            } else {
+                "start,end,name\n"
+            }
+            .to_string();
+            for (d, names) in &rows {
+                let end = *d + interval - chrono::Days::new(1);
+                for (k, name) in names.iter().enumerate() {
+                    if member.as_ref().is_none_or(|m| m == name) {
+                        out.push_str(&format!(
+                            "{},{},{}",
+                            d.format("%Y-%m-%d"),
+                            end.format("%Y-%m-%d"),
+                            csv_field(name)
+                        ));
+                        if escalation {
+                            out.push_str(&format!(",{}", k + 1));
+                        }
+                        out.push('\n');
+                    }
+                }
+            }
+            out
+        }
+        ScheduleFormat::Slips => {
+            let mut out = String::from(
+                "<!DOCTYPE html>\n<html lang=\"ja\">\n<head>\n<meta charset=\"utf-8\">\n\
+                 <title>とうばん</title>\n<style>\n\
+                 @page { size: A4 landscape; margin: 20mm; }\n\
+                 .slip { page-break-after: always; text-align: center; padding-top: 40mm; }\n\
+                 .label { font-size: 32pt; }\n\
+                 .name { font-size: 96pt; font-weight: bold; }\n\
+                 .dates { font-size: 24pt; }\n\
+                 </style>\n</head>\n<body>\n",
+            );
+            let label = if book.interval == 7 {
+                "今週の当番"
+            } else {
+                "今回の当番"
+            };
+            for (d, names) in &rows {
+                let end = *d + interval - chrono::Days::new(1);
+                for name in names {
+                    if member.as_ref().is_some_and(|m| m != name) {
+                        continue;
+                    }
+                    out.push_str(&format!(
+                        "<div class=\"slip\">\n<div class=\"label\">{}</div>\n\
+                         <div class=\"name\">{}</div>\n\
+                         <div class=\"dates\">{} 〜 {}</div>\n</div>\n",
+                        label,
+                        xml_escape(name),
+                        d.format("%-m月%-d日"),
+                        end.format("%-m月%-d日")
+                    ));
+                }
+            }
+            out.push_str("</body>\n</html>\n");
+            out
+        }
+        ScheduleFormat::Ics => {
+            let stamp = chrono::Utc::now().format("%Y%m%dT%H%M%SZ");
+            let mut out = String::new();
+            ics_line(&mut out, "BEGIN:VCALENDAR");
+            ics_line(&mut out, "VERSION:2.0");
+            ics_line(&mut out, "PRODID:-//touban//touban//JA");
+            let calname = match &member {
+                Some(m) => format!("とうばん ({})", m),
+                None => "とうばん".to_string(),
+            };
+            ics_line(&mut out, &format!("X-WR-CALNAME:{}", ics_escape(&calname)));
+            for (d, names) in &rows {
+                let end = *d + chrono::Days::new(book.interval as u64);
+                let shown: Vec<&str> = names
+                    .iter()
+                    .map(String::as_str)
+                    .filter(|n| member.as_deref().is_none_or(|m| m == *n))
+                    .collect();
+                // one event per member and period, so the UID stays put when
+                // only the other assignees change
+                let uid_name = member.as_deref().unwrap_or("all");
+                ics_line(&mut out, "BEGIN:VEVENT");
+                ics_line(
+                    &mut out,
+                    &format!("UID:{}-{}@touban", d.format("%Y%m%d"), ics_escape(uid_name)),
+                );
+                ics_line(&mut out, &format!("DTSTAMP:{}", stamp));
+                ics_line(
+                    &mut out,
+                    &format!("DTSTART;VALUE=DATE:{}", d.format("%Y%m%d")),
+                );
+                ics_line(
+                    &mut out,
+                    &format!("DTEND;VALUE=DATE:{}", end.format("%Y%m%d")),
+                );
+                ics_line(
+                    &mut out,
+                    &format!(
+                        "SUMMARY:{}",
+                        ics_escape(&format!("とうばん: {}", shown.join(", ")))
+                    ),
+                );
+                if !book.checklist.is_empty() {
+                    let items: Vec<String> =
+                        book.checklist.iter().map(|i| format!("・{}", i)).collect();
+                    ics_line(
+                        &mut out,
+                        &format!(
+                            "DESCRIPTION:{}",
+                            ics_escape(&format!("やること:\n{}", items.join("\n")))
+                        ),
+                    );
+                }
+                ics_line(&mut out, "TRANSP:TRANSPARENT");
+                ics_line(&mut out, "END:VEVENT");
+            }
+            ics_line(&mut out, "END:VCALENDAR");
+            out
+        }
+    };
+    match output {
+        Some(path) => {
+            std::fs::write(&path, out).with_context(|| format!("write {}", path.display()))?
+        }
+        None => print!("{}", out),
+    }
+    Ok(())
+}
+
+fn cmd_whatif(book_str: String, remove: String, periods: usize, seed: u64) -> Result<()> {
+    let book = decode_book(&book_str)?;
+    let remove = split_list_arg(&remove);
+    if let Some(name) = remove
+        .iter()
+        .find(|n| !book.members.iter().any(|m| m.name == **n))
+    {
+        return Err(ToubanError::MemberNotFound(name.clone()).into());
+    }
+    let mut changed = book.clone();
+    for name in &remove {
+        changed.remove_member(name)?;
+    }
+    let now = simulate(book, periods, seed)?;
+    let after = simulate(changed, periods, seed)?;
+    say!(
+        ":水晶玉: {} 人が抜けた場合の {} 回分の見込み：",
+        remove.len(),
+        periods
+    );
+    for (name, n) in &now {
+        match after.iter().find(|(n, _)| n == name).map(|&(_, a)| a) {
+            Some(a) => item!(
+                "メンバー",
+                "{}: {}回 → {}回 ({:+})",
+                name,
+                n,
+                a,
+                a as i64 - *n as i64
+            ),
+            None => item!("メンバー", "{}: {}回 → 抜ける", name, n),
+        }
+    }
+    Ok(())
+}
+
+fn cmd_confirm(book_str: String) -> Result<()> {
+    let mut book = decode_book(&book_str)?;
+    let pending = book
+        .pending
+        .take()
+        .ok_or_else(|| ToubanError::Invalid("確定待ちの とうばんはありません".to_string()))?;
+    let selected_idx: Vec<usize> = pending
+        .members
+        .iter()
+        .filter_map(|name| book.members.iter().position(|m| m.name == *name))
+        .collect();
+    apply_assignment(&mut book, &selected_idx);
+    set_round_note(&mut book, pending.note);
+    print_assignment(&book);
     let hira = encode_book(&book)?;
-    println!(":上半身シルエット_1: メンバーを追加しました。");
-    println!("{}", hira);
+    say!("\n:青い本: とうばんのしょ（更新後）:");
+    print_book(&hira);
     Ok(())
 }
 
-fn cmd_remove_member(book_str: String, member: String) -> Result<()> {
+/// Attach `note` to the round just committed.
+fn set_round_note(book: &mut Book, note: Option<String>) {
+    let note = note.map(|n| n.trim().to_string()).filter(|n| !n.is_empty());
+    if let Some(h) = book.current_round_mut() {
+        h.note = note;
+    }
+}
+
+fn cmd_undo(book_str: String) -> Result<()> {
+    let mut book = decode_book(&book_str)?;
+    let names = undo_assignment(&mut book)?;
+    say!(
+        ":反時計回り矢印: {} の とうばんを取り消しました。",
+        names.join(", ")
+    );
+    let hira = encode_book(&book)?;
+    say!("\n:青い本: とうばんのしょ（更新後）:");
+    print_book(&hira);
+    Ok(())
+}
+
+/// Redraw the pending assignment, or the committed current assignment when
+/// nothing is pending.
+fn cmd_reroll(book_str: String, object: Option<String>, seed: Option<u64>) -> Result<()> {
     let mut book = decode_book(&book_str)?;
-    let before = book.members.len();
-    book.members.retain(|m| m.name != member);
-    if book.members.len() == before {
-        return Err(anyhow!("メンバー「{}」は見つかりませんでした", member));
+    let objections = object.map(|s| split_list_arg(&s)).unwrap_or_default();
+    match &mut book.pending {
+        Some(pending) => {
+            for name in objections {
+                if !pending.members.contains(&name) {
+                    return Err(ToubanError::Invalid(format!(
+                        "「{}」は仮のとうばんに入っていません",
+                        name
+                    ))
+                    .into());
+                }
+                if !pending.objected.contains(&name) {
+                    pending.objected.push(name);
+                }
+            }
+        }
+        None if !objections.is_empty() => {
+            return Err(ToubanError::Invalid(
+                "--object は確定待ちの とうばんにだけ使えます".to_string(),
+            )
+            .into());
+        }
+        None => {}
     }
+    reroll_assignment(&mut book, &mut make_rng(seed))?;
+    if book.pending.is_some() {
+        print_pending(book.pending.as_ref());
+    } else {
+        print_assignment(&book);
+    }
+    let hira = encode_book(&book)?;
+    say!("\n:青い本: とうばんのしょ（更新後）:");
+    print_book(&hira);
+    Ok(())
+}
+
+fn cmd_promote_backup(book_str: String, member: String) -> Result<()> {
+    let mut book = decode_book(&book_str)?;
+    let promoted = promote_backup(&mut book, &member)?;
+    say!(
+        ":ベンチ: {} さんの代わりに 控えの {} さんが とうばんです。",
+        member,
+        promoted
+    );
+    print_assignment(&book);
+    let hira = encode_book(&book)?;
+    say!("\n:青い本: とうばんのしょ（更新後）:");
+    print_book(&hira);
+    Ok(())
+}
+
+fn cmd_defer(book_str: String, member: String) -> Result<()> {
+    let mut book = decode_book(&book_str)?;
+    book.defer(&member)?;
+    let hira = encode_book(&book)?;
+    say!(":指切り: {} さんは 次のとうばんを引き受けます。", member);
+    print_book(&hira);
+    Ok(())
+}
+
+fn cmd_swap(book_str: String, a: String, b: String) -> Result<()> {
+    let mut book = decode_book(&book_str)?;
+    book.swap_members(&a, &b)?;
+    let hira = encode_book(&book)?;
+    say!(":握手: {} さんと {} さんの回数を入れ替えました。", a, b);
+    print_book(&hira);
+    Ok(())
+}
+
+fn cmd_substitute(
+    book_str: String,
+    out: String,
+    into: Option<String>,
+    seed: Option<u64>,
+) -> Result<()> {
+    let mut book = decode_book(&book_str)?;
+    let mut rng = make_rng(seed);
+    let name = touban::substitute(&mut book, &out, into.as_deref(), &mut rng)?;
+    say!(
+        ":反時計回り矢印: {} さんの代わりに {} さんが とうばんです。",
+        out,
+        name
+    );
+    print_assignment(&book);
     let hira = encode_book(&book)?;
-    println!(":ハロー: メンバーを削除しました。");
-    println!("{}", hira);
+    say!("\n:青い本: とうばんのしょ（更新後）:");
+    print_book(&hira);
     Ok(())
 }
 
-fn cmd_assign(book_str: String, seed: Option<u64>) -> Result<()> {
+fn cmd_ack(book_str: String, member: String) -> Result<()> {
     let mut book = decode_book(&book_str)?;
-    if book.members.is_empty() {
-        return Err(anyhow!("メンバーがいません"));
+    book.ack(&member)?;
+    let hira = encode_book(&book)?;
+    say!(":OKサイン: {} さんが とうばんを 確認しました。", member);
+    print_book(&hira);
+    Ok(())
+}
+
+fn cmd_remind(book_str: String) -> Result<()> {
+    let book = decode_book(&book_str)?;
+    if book.current.is_empty() {
+        return Err(
+            ToubanError::Invalid("まだ とうばんが 割り当てられていません".to_string()).into(),
+        );
     }
-    // reset when any count >= 5
-    if book.members.iter().map(|m| m.count).max().unwrap_or(0) >= 5 {
-        for m in &mut book.members {
-            m.count = 0;
+    say!(":ベル: 今回のとうばん：");
+    let mut last_shift = None;
+    for a in &book.current {
+        if let Some(s) = book
+            .shifts
+            .iter()
+            .find(|s| Some(&s.name) == a.shift.as_ref())
+        {
+            if last_shift != a.shift.as_ref() {
+                say!("{}", shift_header(s));
+            }
+        }
+        last_shift = a.shift.as_ref();
+        let mark = if a.acked { "確認済み" } else { "未確認" };
+        match &a.role {
+            Some(r) => item!("とうばん", "{}{} ({})", a.name, role_mark(r), mark),
+            None => item!("とうばん", "{} ({})", a.name, mark),
         }
-        println!(":反時計回り矢印: 全員のカウントをリセットしました。");
     }
-    // find min count
-    let minc = book.members.iter().map(|m| m.count).min().unwrap_or(0);
-    // collect candidates (by index to later update counts)
-    let mut candidates_idx: Vec<usize> = book
-        .members
+    let pending: Vec<&str> = book
+        .current
         .iter()
-        .enumerate()
-        .filter(|(_, m)| m.count == minc)
-        .map(|(i, _)| i)
+        .filter(|a| !a.acked)
+        .map(|a| a.name.as_str())
         .collect();
-    // shuffle (deterministic if seed given)
-    if let Some(s) = seed {
-        let mut rng = ChaCha8Rng::seed_from_u64(s);
-        candidates_idx.shuffle(&mut rng);
+    if !pending.is_empty() {
+        say!(":砂時計: まだ確認していない人: {}", pending.join(", "));
+    }
+    if !book.backups.is_empty() {
+        say!(
+            ":ベンチ: 控え（この順に代わります）: {}",
+            book.backups.join(", ")
+        );
+    }
+    let current: Vec<String> = book.current.iter().map(|a| a.name.clone()).collect();
+    let today = on_duty(&book, Local::now().date_naive(), &current);
+    if today != current {
+        say!(
+            ":反時計回り矢印: 今日は代わりの予定があります: {}",
+            today.join(", ")
+        );
+    }
+    print_checklist(&book.checklist);
+    print_handover(&book);
+    Ok(())
+}
+
+fn cmd_compact(book_str: String, c: Compaction) -> Result<()> {
+    let mut book = decode_book(&book_str)?;
+    book.compact(c);
+    // every alphabet spends one character per digit, so --codec is kept
+    let text = encode_book(&book)?;
+    say!(
+        ":圧縮: とうばんのしょ を小さくしました（{} 文字 → {} 文字）。",
+        book_str.chars().count(),
+        text.chars().count()
+    );
+    print_book(&text);
+    Ok(())
+}
+
+fn cmd_history(book_str: String) -> Result<()> {
+    let book = decode_book(&book_str)?;
+    if book.history.is_empty() {
+        say!(":本: まだ記録がありません。");
+        return Ok(());
+    }
+    say!(":本: これまでのとうばん（新しい順）：");
+    for h in book.history.iter().rev() {
+        let mut line = format!("{} {}", format_date(h.at), h.members.join(", "));
+        if let Some(n) = &h.note {
+            line.push_str(&format!("（{}）", n));
+        }
+        if !h.substitutions.is_empty() {
+            let subs: Vec<String> = h
+                .substitutions
+                .iter()
+                .map(|(out, into)| format!("{}→{}", out, into))
+                .collect();
+            line.push_str(&format!(" 交代: {}", subs.join(", ")));
+        }
+        if !h.acked.is_empty() {
+            line.push_str(&format!(" 確認: {}", h.acked.join(", ")));
+        }
+        if !h.notes.is_empty() {
+            let notes: Vec<String> = h
+                .notes
+                .iter()
+                .map(|n| format!("{}「{}」", n.member, n.text))
+                .collect();
+            line.push_str(&format!(" 引き継ぎ: {}", notes.join(", ")));
+        }
+        item!("記録", "{}", line);
+    }
+    Ok(())
+}
+
+/// Meant to be piped into a chat API call (e.g. Slack's
+/// conversations.setTopic) after each assignment; no emoji codes, no book.
+fn cmd_topic(
+    book_str: String,
+    slack_channel: Option<String>,
+    slack_token: Option<String>,
+) -> Result<()> {
+    let book = decode_book(&book_str)?;
+    let current: Vec<String> = book.current.iter().map(|a| a.name.clone()).collect();
+    let names = on_duty(&book, Local::now().date_naive(), &current);
+    let mut topic = if names.is_empty() {
+        "とうばん: 未定".to_string()
     } else {
-        let mut rng = thread_rng();
-        candidates_idx.shuffle(&mut rng);
-    }
-    let take = min(book.people, candidates_idx.len());
-    let selected_idx = &candidates_idx[0..take];
-    println!(":ダーツ: 今週のとうばん：");
-    for &i in selected_idx {
-        // increment count with wrap >5 -> 0
-        let newc = book.members[i].count.saturating_add(1);
-        book.members[i].count = if newc > 5 { 0 } else { newc };
+        format!("とうばん: {}", names.join(", "))
+    };
+    if let Some(d) = next_due_date(&book) {
+        topic.push_str(&format!(" / 次回: {}", d.format("%Y-%m-%d")));
+    }
+    println!("{}", topic);
+    if let Some(channel) = slack_channel {
+        let token = slack_token.ok_or_else(|| {
+            ToubanError::Invalid(
+                "--slack-channel には --slack-token か SLACK_BOT_TOKEN が必要です".to_string(),
+            )
+        })?;
+        set_slack_topic(&token, &channel, &topic)?;
+    }
+    Ok(())
+}
+
+const SLACK_SET_TOPIC: &str = "https://slack.com/api/conversations.setTopic";
+
+#[cfg(feature = "slack")]
+fn set_slack_topic(token: &str, channel: &str, topic: &str) -> Result<()> {
+    let body = serde_json::json!({ "channel": channel, "topic": topic }).to_string();
+    let text = ureq::post(SLACK_SET_TOPIC)
+        .header("Authorization", &format!("Bearer {}", token))
+        .header("Content-Type", "application/json; charset=utf-8")
+        .send(&body)
+        .map_err(|e| anyhow!("Slack に接続できませんでした: {}", e))?
+        .body_mut()
+        .read_to_string()
+        .map_err(|e| anyhow!("Slack の応答を読めませんでした: {}", e))?;
+    // the Web API answers 200 even on failure and reports it in "ok"
+    let reply: serde_json::Value = serde_json::from_str(&text)
+        .map_err(|e| anyhow!("Slack の応答を読めませんでした: {}", e))?;
+    if reply["ok"] != true {
+        return Err(anyhow!(
+            "Slack がトピックの更新を断りました: {}",
+            reply["error"].as_str().unwrap_or("unknown_error")
+        ));
+    }
+    Ok(())
+}
+
+#[cfg(not(feature = "slack"))]
+fn set_slack_topic(_token: &str, _channel: &str, _topic: &str) -> Result<()> {
+    Err(anyhow!(
+        "この touban は slack 機能なしでビルドされています（{} を curl で呼ぶか、--features slack でビルドしてください）",
+        SLACK_SET_TOPIC
+    ))
+}
+
+/// Like `topic`, meant to be sent on by a bot: no emoji codes, no book.
+/// The next duty comes from the same seed-0 projection `schedule` uses.
+fn cmd_me(book_str: String, member: String) -> Result<()> {
+    let book = decode_book(&book_str)?;
+    let m = book
+        .members
+        .iter()
+        .find(|m| m.name == member)
+        .ok_or_else(|| ToubanError::MemberNotFound(member.clone()))?;
+    println!("{} さんのとうばん", m.name);
+    match book.current.iter().find(|a| a.name == member) {
+        Some(a) => {
+            let role = a.role.as_deref().map(role_mark).unwrap_or_default();
+            let mark = if a.acked { "確認済み" } else { "未確認" };
+            println!("今回: とうばんです{} ({})", role, mark);
+        }
+        None => println!("今回: とうばんではありません"),
+    }
+    if let Some(p) = book
+        .pending
+        .as_ref()
+        .filter(|p| p.members.contains(&member))
+    {
         println!(
-            " - {} ({}回め)",
-            book.members[i].name, book.members[i].count
+            "仮のとうばんに入っています（{}）",
+            format_remaining(p.deadline)
+        );
+    }
+    if let Some(p) = book
+        .planned
+        .iter()
+        .find(|p| p.seats.iter().any(|a| a.name == member))
+    {
+        println!("次回の予定: {}（決定済み）", format_date(p.at));
+    } else {
+        // a few laps of the roster; rarely drawn members may not come up at all
+        let horizon = book.members.len() * 2;
+        let next = touban::rotation(&book, 0)
+            .take(horizon)
+            .map_while(|r| r.ok())
+            .position(|r| r.selected.contains(&member));
+        match (next, next_due_date(&book)) {
+            (Some(k), Some(start)) if book.interval > 0 => {
+                let d = start + chrono::Days::new((k * book.interval) as u64);
+                println!("次回の予定: {} ごろ", d.format("%Y-%m-%d"));
+            }
+            (Some(k), _) => println!("次回の予定: {} 回あと", k + 1),
+            (None, _) => println!("次回の予定: 当面ありません"),
+        }
+    }
+    match m.last_assigned_at {
+        Some(at) => println!("これまで: {} 回（最後は {}）", m.total, format_date(at)),
+        None => println!("これまで: {} 回", m.total),
+    }
+    let dates: Vec<String> = book
+        .history
+        .iter()
+        .rev()
+        .filter(|h| h.members.contains(&member))
+        .map(|h| format_date(h.at))
+        .collect();
+    if !dates.is_empty() {
+        println!("最近の記録: {}", dates.join(", "));
+    }
+    for t in book.trades.iter().filter(|t| t.member == member) {
+        println!("交換の募集中: #{} ({})", t.id, t.when);
+    }
+    Ok(())
+}
+
+fn cmd_dashboard(args: Vec<String>, board: bool) -> Result<()> {
+    let mut rows = Vec::new();
+    for (i, arg) in args.iter().enumerate() {
+        let (label, text) = match arg.split_once('=') {
+            Some((label, text)) => (label.to_string(), text),
+            None => (format!("{}冊目", i + 1), arg.as_str()),
+        };
+        let book = decode_book(text)
+            .with_context(|| format!("「{}」の とうばんのしょ を読めません", label))?;
+        rows.push((next_due_date(&book), label, book));
+    }
+    // undated books (never assigned) go last
+    rows.sort_by_key(|(due, _, _)| (due.is_none(), *due));
+    let today = Local::now().date_naive();
+    if board && !screen_reader() {
+        print_board(&rows, today);
+        return Ok(());
+    }
+    for (n, (due, label, book)) in rows.iter().enumerate() {
+        if n > 0 {
+            println!();
+        }
+        say!(":クリップボード: {}", label);
+        let names: Vec<&str> = book.current.iter().map(|a| a.name.as_str()).collect();
+        if names.is_empty() {
+            item!("とうばん", "とうばん: 未定");
+        } else {
+            item!("とうばん", "とうばん: {}", names.join(", "));
+        }
+        match due {
+            Some(d) => item!("次回", "次回: {}", d.format("%Y-%m-%d")),
+            None => item!("次回", "次回: 未定"),
+        }
+        if book.pending.is_some() {
+            item!("確定待ち", "確定待ちの割り当てがあります");
+        }
+        let unacked = book.current.iter().filter(|a| !a.acked).count();
+        if unacked > 0 {
+            item!("未確認", "未確認 {} 人", unacked);
+        }
+        if let Some(d) = due.filter(|d| *d < today) {
+            say!(
+                ":警告: 次回の日付を {} 日過ぎています",
+                (today - d).num_days()
+            );
+        }
+    }
+    Ok(())
+}
+
+/// Terminal columns a string takes up: East Asian wide characters (kana,
+/// kanji, full-width forms) count double.
+fn display_width(s: &str) -> usize {
+    s.chars()
+        .map(|c| match c as u32 {
+            0x1100..=0x115f
+            | 0x2e80..=0xa4cf
+            | 0xac00..=0xd7a3
+            | 0xf900..=0xfaff
+            | 0xfe30..=0xfe4f
+            | 0xff00..=0xff60
+            | 0xffe0..=0xffe6
+            | 0x1f300..=0x1faff
+            | 0x20000..=0x3fffd => 2,
+            _ => 1,
+        })
+        .sum()
+}
+
+/// `s` broken into lines of at most `width` columns, between words where
+/// possible (an empty string stays one empty line).
+fn wrap(s: &str, width: usize) -> Vec<String> {
+    let mut lines = vec![String::new()];
+    for word in s.split_inclusive(' ') {
+        let last = lines.last().expect("one line");
+        if !last.is_empty() && display_width(last) + display_width(word.trim_end()) > width {
+            lines.push(String::new());
+        }
+        for c in word.chars() {
+            let line = lines.last_mut().expect("one line");
+            if !line.is_empty()
+                && display_width(line) + display_width(c.encode_utf8(&mut [0; 4])) > width
+            {
+                lines.push(String::new());
+            }
+            let line = lines.last_mut().expect("one line");
+            if !(line.is_empty() && c == ' ') {
+                line.push(c);
+            }
+        }
+    }
+    lines.iter().map(|l| l.trim_end().to_string()).collect()
+}
+
+/// `s` cut to `width` columns (marking the cut with "…") and padded to it.
+fn fit(s: &str, width: usize) -> String {
+    let mut out = String::new();
+    let mut used = 0;
+    for c in s.chars() {
+        let w = display_width(c.encode_utf8(&mut [0; 4]));
+        if used + w > width || (used + w == width && display_width(s) > width) {
+            out.push('…');
+            used += 1;
+            break;
+        }
+        out.push(c);
+        used += w;
+    }
+    out.push_str(&" ".repeat(width.saturating_sub(used)));
+    out
+}
+
+/// The dashboard as a kanban board: one column per lane, one card per book,
+/// the most urgent first in each lane.
+fn print_board(rows: &[(Option<NaiveDate>, String, Book)], today: NaiveDate) {
+    const LANES: [&str; 4] = ["期限切れ", "今日", "これから", "未定"];
+    let mut lanes: [Vec<Vec<String>>; 4] = Default::default();
+    for (due, label, book) in rows {
+        let lane = match due {
+            Some(d) if *d < today => 0,
+            Some(d) if *d == today => 1,
+            Some(_) => 2,
+            None => 3,
+        };
+        let names: Vec<&str> = book.current.iter().map(|a| a.name.as_str()).collect();
+        let mut card = vec![
+            format!("■ {}", label),
+            if names.is_empty() {
+                "とうばん: 未定".to_string()
+            } else {
+                format!("とうばん: {}", names.join(", "))
+            },
+        ];
+        if let Some(d) = due {
+            card.push(format!("次回: {}", d.format("%Y-%m-%d")));
+        }
+        if let Some(d) = due.filter(|d| *d < today) {
+            card.push(format!("{} 日超過", (today - d).num_days()));
+        }
+        if book.pending.is_some() {
+            card.push("確定待ちあり".to_string());
+        }
+        let unacked = book.current.iter().filter(|a| !a.acked).count();
+        if unacked > 0 {
+            card.push(format!("未確認 {} 人", unacked));
+        }
+        lanes[lane].push(card);
+    }
+    let width = std::env::var("COLUMNS")
+        .ok()
+        .and_then(|c| c.parse::<usize>().ok())
+        .unwrap_or(100);
+    let col = (width.saturating_sub(3 * (LANES.len() - 1)) / LANES.len()).max(8);
+    // each column is its cards one after another, a blank line between
+    // them; long lines wrap within the column
+    let columns: Vec<Vec<String>> = lanes
+        .iter()
+        .map(|cards| {
+            cards
+                .join(&String::new())
+                .iter()
+                .flat_map(|line| wrap(line, col))
+                .collect()
+        })
+        .collect();
+    let header: Vec<String> = LANES
+        .iter()
+        .zip(&lanes)
+        .map(|(name, cards)| fit(&format!("{}（{}）", name, cards.len()), col))
+        .collect();
+    let rule = "─".repeat(col);
+    println!("{}", header.join(" │ ").trim_end());
+    println!("{}", vec![rule; LANES.len()].join("─┼─"));
+    let height = columns.iter().map(Vec::len).max().unwrap_or(0);
+    for r in 0..height {
+        let line: Vec<String> = columns
+            .iter()
+            .map(|c| fit(c.get(r).map_or("", String::as_str), col))
+            .collect();
+        println!("{}", line.join(" │ ").trim_end());
+    }
+}
+
+fn cmd_note_add(book_str: String, member: String, text: String) -> Result<()> {
+    let mut book = decode_book(&book_str)?;
+    book.add_note(&member, &text)?;
+    let hira = encode_book(&book)?;
+    say!(":メモ: 次のとうばんへの引き継ぎを残しました。");
+    print_book(&hira);
+    Ok(())
+}
+
+fn cmd_trades_post(book_str: String, member: String, when: String) -> Result<()> {
+    let mut book = decode_book(&book_str)?;
+    find_member_mut(&mut book, &member)?;
+    let id = book.trades.iter().map(|t| t.id).max().unwrap_or(0) + 1;
+    book.trades.push(Trade { id, member, when });
+    let hira = encode_book(&book)?;
+    say!(":握手: 交換の募集を出しました。(#{})", id);
+    print_book(&hira);
+    Ok(())
+}
+
+fn cmd_override_add(
+    book_str: String,
+    member: String,
+    (from, to): (NaiveDate, NaiveDate),
+    replaces: Option<String>,
+) -> Result<()> {
+    let mut book = decode_book(&book_str)?;
+    for name in std::iter::once(&member).chain(&replaces) {
+        if !book.members.iter().any(|m| m.name == *name) {
+            return Err(ToubanError::MemberNotFound(name.clone()).into());
+        }
+    }
+    if from > to {
+        return Err(
+            ToubanError::Invalid("--from は --to より前の日付にしてください".to_string()).into(),
+        );
+    }
+    if replaces.as_ref() == Some(&member) {
+        return Err(ToubanError::Invalid("自分の代わりには入れません".to_string()).into());
+    }
+    // ones that have run out are of no more use
+    let today = Local::now().date_naive();
+    book.overrides
+        .retain(|o| o.to.parse::<NaiveDate>().map_or(true, |to| to >= today));
+    let id = book.overrides.iter().map(|o| o.id).max().unwrap_or(0) + 1;
+    let covered = replaces
+        .as_ref()
+        .map(|r| format!("{} さん", r))
+        .unwrap_or_else(|| "とうばん".to_string());
+    book.overrides.push(Override {
+        id,
+        member: member.clone(),
+        from: from.format("%Y-%m-%d").to_string(),
+        to: to.format("%Y-%m-%d").to_string(),
+        replaces,
+    });
+    let hira = encode_book(&book)?;
+    say!(
+        ":反時計回り矢印: {} から {} まで {} さんが {}の代わりに入ります。(#{})",
+        from.format("%Y-%m-%d"),
+        to.format("%Y-%m-%d"),
+        member,
+        covered,
+        id
+    );
+    print_book(&hira);
+    Ok(())
+}
+
+fn cmd_override_list(book_str: String) -> Result<()> {
+    let book = decode_book(&book_str)?;
+    if book.overrides.is_empty() {
+        say!(":反時計回り矢印: 代わりの予定はありません。");
+        return Ok(());
+    }
+    say!(":反時計回り矢印: 代わりの予定（あとのものが優先）:");
+    for o in &book.overrides {
+        let covered = o
+            .replaces
+            .as_ref()
+            .map(|r| format!("{} さん", r))
+            .unwrap_or_else(|| "全員".to_string());
+        item!(
+            "代わり",
+            "#{} {}〜{} {} さん（{}の代わり）",
+            o.id,
+            o.from,
+            o.to,
+            o.member,
+            covered
+        );
+    }
+    Ok(())
+}
+
+fn cmd_override_remove(book_str: String, id: u32) -> Result<()> {
+    let mut book = decode_book(&book_str)?;
+    let before = book.overrides.len();
+    book.overrides.retain(|o| o.id != id);
+    if book.overrides.len() == before {
+        return Err(
+            ToubanError::Invalid(format!("代わりの予定 #{} は見つかりませんでした", id)).into(),
         );
     }
     let hira = encode_book(&book)?;
-    println!("\n:青い本: とうばんのしょ（更新後）:");
-    println!("{}", hira);
+    say!(":反時計回り矢印: 代わりの予定 #{} を取り消しました。", id);
+    print_book(&hira);
+    Ok(())
+}
+
+fn cmd_who(book_str: String, date: Option<NaiveDate>) -> Result<()> {
+    let book = decode_book(&book_str)?;
+    let date = date.unwrap_or_else(|| Local::now().date_naive());
+    let current: Vec<String> = book.current.iter().map(|a| a.name.clone()).collect();
+    // dates from the next due date on belong to projected periods, earlier
+    // ones to a round planned by assign --rounds or the current one
+    let (scheduled, label) = match next_due_date(&book) {
+        Some(start) if date >= start && book.interval > 0 => {
+            let k = (date - start).num_days() as usize / book.interval;
+            let round = touban::rotation(&book, 0)
+                .nth(k)
+                .ok_or_else(|| ToubanError::Invalid("見込みを出せませんでした".to_string()))??;
+            (round.selected, "（見込み）")
+        }
+        _ => match planned_on(&book, date) {
+            Some(p) => (
+                p.seats.iter().map(|a| a.name.clone()).collect(),
+                "（決定済み）",
+            ),
+            None => (current, ""),
+        },
+    };
+    let names = on_duty(&book, date, &scheduled);
+    say!(
+        ":カレンダー: {} のとうばん{}：",
+        date.format("%Y-%m-%d"),
+        label
+    );
+    if names.is_empty() {
+        item!("とうばん", "未定");
+    }
+    for n in &names {
+        if scheduled.contains(n) {
+            item!("とうばん", "{}", n);
+        } else {
+            item!("とうばん", "{} (代わり)", n);
+        }
+    }
+    Ok(())
+}
+
+fn cmd_trades_list(book_str: String) -> Result<()> {
+    let book = decode_book(&book_str)?;
+    if book.trades.is_empty() {
+        say!(":握手: 交換の募集はありません。");
+        return Ok(());
+    }
+    say!(":握手: 交換の募集一覧:");
+    for t in &book.trades {
+        item!("募集", "#{} {} さん ({})", t.id, t.member, t.when);
+    }
+    Ok(())
+}
+
+/// The accepting member takes the offering member's seat in the current
+/// assignment over, counts included; see [`accept_trade`].
+fn cmd_trades_accept(book_str: String, id: u32, member: String) -> Result<()> {
+    let mut book = decode_book(&book_str)?;
+    let trade = accept_trade(&mut book, id, &member)?;
+    let hira = encode_book(&book)?;
+    say!(
+        ":握手: {} さんが {} さんの とうばん ({}) を引き受けました。",
+        member,
+        trade.member,
+        trade.when
+    );
+    print_book(&hira);
     Ok(())
 }
 
 // --------------------- main ---------------------
+/// Exit status for a failed command. 2 is left to clap's usage errors;
+/// the error kinds scripts most often need to tell apart get their own.
+fn exit_code(e: &anyhow::Error) -> i32 {
+    match e.downcast_ref::<ToubanError>() {
+        Some(ToubanError::CorruptedBook(_)) => 3,
+        Some(ToubanError::MemberNotFound(_)) => 4,
+        Some(ToubanError::DuplicateMember(_)) => 5,
+        Some(ToubanError::EmptyRoster) => 6,
+        Some(ToubanError::StaleBook { .. }) => 7,
+        _ => 1,
+    }
+}
+
 fn main() -> Result<()> {
-    let cli = Cli::parse();
+    let args: Vec<String> = std::env::args().collect();
+    let matches = help::localize(Cli::command(), Lang::detect(&args)).get_matches_from(&args);
+    let cli = Cli::from_arg_matches(&matches).unwrap_or_else(|e| e.exit());
+    set_limits(Limits {
+        max_members: cli.limits.max_members,
+        max_name_len: cli.limits.max_name_len,
+        max_book_len: cli.limits.max_book_len,
+        max_duties: cli.limits.max_duties,
+    });
+    SCREEN_READER.store(cli.screen_reader, Ordering::Relaxed);
+    LENIENT.store(cli.lenient, Ordering::Relaxed);
+    if let Some(n) = cli.expect_revision.filter(|_| cli.cmd.writes()) {
+        let _ = EXPECT_REVISION.set(n);
+    }
+    AVOID_WORDS.store(cli.avoid_words, Ordering::Relaxed);
+    ALSO_BASE64.store(cli.also_base64, Ordering::Relaxed);
+    if let Some(codec) = touban::codec_by_name(&cli.codec) {
+        let _ = CODEC.set(codec);
+    }
     let res = match cli.cmd {
-        Commands::Create {
-            people,
-            interval,
-            members,
-        } => cmd_create(people, interval, members),
-        Commands::Show { book } => cmd_show(book),
-        Commands::AddMember { book, member } => cmd_add_member(book, member),
+        Commands::Create { opts } => cmd_create(opts),
+        Commands::Show { book, json } => cmd_show(book, json),
+        Commands::ListMembers { book } => cmd_list_members(book),
+        Commands::AddMember {
+            book,
+            member,
+            substitute,
+            onboarding,
+        } => cmd_add_member(book, member, substitute, onboarding),
         Commands::RemoveMember { book, member } => cmd_remove_member(book, member),
-        Commands::Assign { book, seed } => cmd_assign(book, seed),
+        Commands::Tag { book, member, tag } => cmd_tag(book, member, tag),
+        Commands::Untag { book, member, tag } => cmd_untag(book, member, tag),
+        Commands::SetRequiredTags { book, tags } => cmd_set_required_tags(book, tags),
+        Commands::SetRoles { book, roles } => cmd_set_roles(book, roles),
+        Commands::Config { book, settings } => cmd_config(book, settings),
+        Commands::Lint { book } => cmd_lint(book),
+        Commands::Eq { a, b } => cmd_eq(a, b),
+        Commands::Stats { book, cmd } => match cmd {
+            Some(StatsCommands::Export {
+                book,
+                format,
+                output,
+            }) => cmd_stats_export(book, format, output),
+            None => cmd_stats(book.unwrap_or_default()),
+        },
+        Commands::SetChecklist { book, checklist } => cmd_set_checklist(book, checklist),
+        Commands::SetShifts { book, shifts } => cmd_set_shifts(book, shifts),
+        Commands::SetShiftPrefs {
+            book,
+            member,
+            prefer,
+            avoid,
+        } => cmd_set_shift_prefs(book, member, prefer, avoid),
+        Commands::SetExempt {
+            book,
+            member,
+            rules,
+        } => cmd_set_exempt(book, member, rules),
+        Commands::SetWeight {
+            book,
+            member,
+            weight,
+        } => cmd_set_weight(book, member, weight),
+        Commands::SetTeam { book, member, team } => cmd_set_team(book, member, team),
+        Commands::Rebalance { book, dry_run } => cmd_rebalance(book, dry_run),
+        Commands::AddDuty { book, name, people } => cmd_add_duty(book, name, people),
+        Commands::RemoveDuty { book, name } => cmd_remove_duty(book, name),
+        Commands::AddConflict { book, a, b } => cmd_add_conflict(book, a, b),
+        Commands::RemoveConflict { book, a, b } => cmd_remove_conflict(book, a, b),
+        Commands::AddPair { book, a, b } => cmd_add_pair(book, a, b),
+        Commands::RemovePair { book, a, b } => cmd_remove_pair(book, a, b),
+        Commands::Ack { book, member } => cmd_ack(book, member),
+        Commands::PromoteBackup { book, member } => cmd_promote_backup(book, member),
+        Commands::Defer { book, member } => cmd_defer(book, member),
+        Commands::Swap { book, a, b } => cmd_swap(book, a, b),
+        Commands::Substitute {
+            book,
+            out,
+            into,
+            seed,
+        } => cmd_substitute(book, out, into, seed),
+        Commands::Remind { book } => cmd_remind(book),
+        Commands::Compact {
+            book,
+            notes,
+            stats,
+            history,
+        } => cmd_compact(
+            book,
+            Compaction {
+                notes,
+                stats,
+                history,
+            },
+        ),
+        Commands::History { book } => cmd_history(book),
+        Commands::Topic {
+            book,
+            slack_channel,
+            slack_token,
+        } => cmd_topic(book, slack_channel, slack_token),
+        Commands::Me { book, member } => cmd_me(book, member),
+        Commands::Dashboard { books, board } => cmd_dashboard(books, board),
+        Commands::Feed { book, link, output } => cmd_feed(book, link, output),
+        Commands::Contacts { cmd } => match cmd {
+            ContactsCommands::Export { book, output } => cmd_contacts_export(book, output),
+            ContactsCommands::Import { book, file } => cmd_contacts_import(book, file),
+        },
+        Commands::Note { cmd } => match cmd {
+            NoteCommands::Add { book, member, text } => cmd_note_add(book, member, text),
+        },
+        Commands::Override { cmd } => match cmd {
+            OverrideCommands::Add {
+                book,
+                member,
+                from,
+                to,
+                replaces,
+            } => cmd_override_add(book, member, (from, to), replaces),
+            OverrideCommands::List { book } => cmd_override_list(book),
+            OverrideCommands::Remove { book, id } => cmd_override_remove(book, id),
+        },
+        Commands::Who { book, date } => cmd_who(book, date),
+        Commands::Trades { cmd } => match cmd {
+            TradesCommands::Post { book, member, when } => cmd_trades_post(book, member, when),
+            TradesCommands::List { book } => cmd_trades_list(book),
+            TradesCommands::Accept { book, id, member } => cmd_trades_accept(book, id, member),
+        },
+        Commands::Assign { book, opts } => cmd_assign(book, opts),
+        Commands::Whatif {
+            book,
+            remove,
+            periods,
+            seed,
+        } => cmd_whatif(book, remove, periods, seed),
+        Commands::Schedule {
+            book,
+            member,
+            periods,
+            seed,
+            format,
+            escalation,
+            output,
+        } => cmd_schedule(book, member, periods, seed, format, escalation, output),
+        Commands::Confirm { book } => cmd_confirm(book),
+        Commands::Undo { book } => cmd_undo(book),
+        Commands::Reroll { book, object, seed } => cmd_reroll(book, object, seed),
     };
     if let Err(e) = res {
         eprintln!("Error: {}", e);
-        std::process::exit(1);
+        std::process::exit(exit_code(&e));
     }
     Ok(())
 }
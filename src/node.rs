@@ -0,0 +1,56 @@
+//! Node.js bindings (feature `napi`): decode, encode and assign on
+//! とうばんのしょ strings, for chat bots written in JavaScript.
+//!
+//! ```text
+//! cargo build --lib --release --features napi
+//! cp target/release/libtouban.so touban.node
+//! ```
+//!
+//! (Build with `--lib`: the CLI binary cannot link the addon symbols.)
+//!
+//! ```js
+//! const touban = require('./touban.node');
+//! const next = touban.assign(book, 20240610);
+//! console.log(JSON.parse(touban.decodeBook(next)).current);
+//! ```
+//!
+//! Errors are thrown as JS `Error`s carrying the message of the
+//! [`ToubanError`](crate::ToubanError).
+
+use crate::{assign as assign_book, decode_book, encode_book, encode_book_update, Book, HIRAGANA};
+use napi::{Error, Result};
+use napi_derive::napi;
+use rand::SeedableRng;
+use rand_chacha::ChaCha8Rng;
+
+fn js_err(e: impl std::fmt::Display) -> Error {
+    Error::from_reason(e.to_string())
+}
+
+/// Decode a とうばんのしょ into the JSON of its book.
+#[napi(js_name = "decodeBook")]
+pub fn decode_book_json(book: String) -> Result<String> {
+    let book = decode_book(&book).map_err(js_err)?;
+    serde_json::to_string(&book).map_err(js_err)
+}
+
+/// Encode the JSON of a book into a とうばんのしょ.
+#[napi(js_name = "encodeBook")]
+pub fn encode_book_json(json: String) -> Result<String> {
+    let book: Book = serde_json::from_str(&json).map_err(js_err)?;
+    encode_book(&book).map_err(js_err)
+}
+
+/// Draw the next assignment, or seat the round planned by `touban assign
+/// --rounds` once its day has come, and return the updated とうばんのしょ.
+/// The same `seed` gives the same draw as `touban assign --seed`.
+#[napi]
+pub fn assign(book: String, seed: Option<i64>) -> Result<String> {
+    let mut book = decode_book(&book).map_err(js_err)?;
+    let mut rng = match seed {
+        Some(s) => ChaCha8Rng::seed_from_u64(s as u64),
+        None => ChaCha8Rng::from_entropy(),
+    };
+    assign_book(&mut book, &mut rng).map_err(js_err)?;
+    encode_book_update(&book, &HIRAGANA).map_err(js_err)
+}
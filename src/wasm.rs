@@ -0,0 +1,68 @@
+//! JavaScript bindings (feature `wasm-bindgen`): create, show, add, remove
+//! and assign on とうばんのしょ strings, for embedding in a web page.
+//!
+//! ```text
+//! cargo build --lib --release --target wasm32-unknown-unknown --features wasm-bindgen
+//! wasm-bindgen --target web target/wasm32-unknown-unknown/release/touban.wasm --out-dir pkg
+//! ```
+//!
+//! Every function takes and returns the hiragana string; errors are thrown
+//! as JS `Error`s carrying the message of the [`ToubanError`].
+
+use crate::{
+    assign as assign_book, book_warnings, decode_book, encode_book, encode_book_update,
+    BookBuilder, HIRAGANA,
+};
+use rand::SeedableRng;
+use rand_chacha::ChaCha8Rng;
+use wasm_bindgen::prelude::*;
+
+/// Create a book assigning `people` members every `interval` days.
+#[wasm_bindgen]
+pub fn create(people: usize, interval: usize, members: Vec<String>) -> Result<String, JsError> {
+    let book = BookBuilder::new(people, interval)
+        .members(members)
+        .build()?;
+    Ok(encode_book(&book)?)
+}
+
+/// The book as JSON: `{"book": ..., "warnings": [...]}`, as `touban show
+/// --json` prints it.
+#[wasm_bindgen]
+pub fn show(book: &str) -> Result<String, JsError> {
+    let book = decode_book(book)?;
+    let out = serde_json::json!({
+        "book": book,
+        "warnings": book_warnings(&book),
+    });
+    Ok(out.to_string())
+}
+
+#[wasm_bindgen(js_name = addMember)]
+pub fn add_member(book: &str, name: String) -> Result<String, JsError> {
+    let mut book = decode_book(book)?;
+    book.add_member(name, false, 0)?;
+    Ok(encode_book_update(&book, &HIRAGANA)?)
+}
+
+#[wasm_bindgen(js_name = removeMember)]
+pub fn remove_member(book: &str, name: &str) -> Result<String, JsError> {
+    let mut book = decode_book(book)?;
+    book.remove_member(name)?;
+    Ok(encode_book_update(&book, &HIRAGANA)?)
+}
+
+/// Draw the next assignment, or seat the round planned by `touban assign
+/// --rounds` once its day has come, and return the updated book; the
+/// assignees are in `book.current` of [`show`]. The same `seed` gives the
+/// same draw as `touban assign --seed`.
+#[wasm_bindgen]
+pub fn assign(book: &str, seed: Option<u64>) -> Result<String, JsError> {
+    let mut book = decode_book(book)?;
+    let mut rng = match seed {
+        Some(s) => ChaCha8Rng::seed_from_u64(s),
+        None => ChaCha8Rng::from_entropy(),
+    };
+    assign_book(&mut book, &mut rng)?;
+    Ok(encode_book_update(&book, &HIRAGANA)?)
+}
@@ -0,0 +1,1069 @@
+//! End-to-end checks of the `touban` binary: each command gets the book
+//! string from the previous one, as a user would pass it along.
+
+use std::process::Command;
+
+use chrono::Datelike;
+use serde_json::Value;
+
+fn touban(args: &[&str]) -> String {
+    let out = Command::new(env!("CARGO_BIN_EXE_touban"))
+        .args(args)
+        .output()
+        .expect("run touban");
+    assert!(
+        out.status.success(),
+        "touban {:?} failed: {}",
+        args,
+        String::from_utf8_lossy(&out.stderr)
+    );
+    String::from_utf8(out.stdout).expect("utf-8 output")
+}
+
+/// The updated book: the last line of a mutating command's output.
+fn book_of(out: &str) -> String {
+    out.lines().last().expect("book line").to_string()
+}
+
+fn show_json(book: &str) -> Value {
+    let out = touban(&["show", "--book", book, "--json"]);
+    let v: Value = serde_json::from_str(&out).expect("show --json");
+    v["book"].clone()
+}
+
+fn names(v: &Value) -> Vec<&str> {
+    v.as_array()
+        .expect("array")
+        .iter()
+        .map(|n| n.as_str().expect("name"))
+        .collect()
+}
+
+#[test]
+fn assign_rounds_stamps_each_round_at_its_period() {
+    let book = book_of(&touban(&[
+        "create",
+        "--people",
+        "1",
+        "--interval",
+        "7",
+        "--members",
+        "a,b,c,d",
+    ]));
+    let book = book_of(&touban(&[
+        "assign", "--book", &book, "--rounds", "3", "--seed", "1",
+    ]));
+    let b = show_json(&book);
+    let history = b["history"].as_array().expect("history");
+    assert_eq!(history.len(), 3);
+    let at: Vec<u64> = history.iter().map(|h| h["at"].as_u64().unwrap()).collect();
+    assert_eq!(at[1] - at[0], 7 * 86400);
+    assert_eq!(at[2] - at[1], 7 * 86400);
+    // round 1 is on duty now; rounds 2 and 3 wait for their periods
+    let current: Vec<&str> = b["current"]
+        .as_array()
+        .expect("current")
+        .iter()
+        .map(|a| a["name"].as_str().unwrap())
+        .collect();
+    assert_eq!(current, names(&history[0]["members"]));
+    let planned = b["planned"].as_array().expect("planned");
+    assert_eq!(planned.len(), 2);
+    assert_eq!(planned[0]["at"].as_u64(), Some(at[1]));
+    assert_eq!(planned[1]["at"].as_u64(), Some(at[2]));
+    // each member was counted once
+    let totals: u64 = b["members"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|m| m["total"].as_u64().unwrap_or(0))
+        .sum();
+    assert_eq!(totals, 3);
+}
+
+#[test]
+fn compact_keeps_the_chosen_codec() {
+    let book = book_of(&touban(&[
+        "create",
+        "--people",
+        "1",
+        "--interval",
+        "7",
+        "--members",
+        "a,b,c",
+    ]));
+    let out = touban(&["compact", "--book", &book, "--codec", "katakana"]);
+    let compacted = book_of(&out);
+    assert!(compacted.chars().all(|c| ('ァ'..='ヿ').contains(&c)));
+    assert!(out.contains("文字"));
+}
+
+#[test]
+fn expect_revision_guards_only_writes() {
+    let book = book_of(&touban(&[
+        "create",
+        "--people",
+        "1",
+        "--interval",
+        "7",
+        "--members",
+        "a,b,c",
+    ]));
+    // read-only commands ignore a stale revision
+    touban(&["show", "--book", &book, "--expect-revision", "9"]);
+    touban(&["eq", &book, &book, "--expect-revision", "9"]);
+    let out = Command::new(env!("CARGO_BIN_EXE_touban"))
+        .args(["assign", "--book", &book, "--expect-revision", "9"])
+        .output()
+        .expect("run touban");
+    assert_eq!(out.status.code(), Some(7));
+}
+
+#[test]
+fn escalation_leaves_seeded_draws_alone() {
+    let book = book_of(&touban(&[
+        "create",
+        "--people",
+        "1",
+        "--interval",
+        "7",
+        "--members",
+        "a,b,c,d,e",
+    ]));
+    let picks = |extra: &[&str]| {
+        let mut args = vec!["assign", "--book", &book, "--rounds", "4", "--seed", "5"];
+        args.extend(extra);
+        touban(&args)
+            .lines()
+            .filter(|l| l.starts_with(" - ") && !l.contains("番手"))
+            .map(String::from)
+            .collect::<Vec<_>>()
+    };
+    assert_eq!(picks(&[]), picks(&["--escalation"]));
+}
+
+#[test]
+fn member_ics_lists_only_the_member_with_the_checklist() {
+    let book = book_of(&touban(&[
+        "create",
+        "--people",
+        "2",
+        "--interval",
+        "7",
+        "--members",
+        "a,b,c,d",
+    ]));
+    let book = book_of(&touban(&[
+        "set-checklist",
+        "--book",
+        &book,
+        "--checklist",
+        "ゴミ袋を交換",
+    ]));
+    let ics = touban(&[
+        "schedule",
+        "--book",
+        &book,
+        "--member",
+        "a",
+        "--format",
+        "ics",
+        "--periods",
+        "6",
+    ]);
+    assert!(ics.contains("SUMMARY:とうばん: a\r\n"));
+    assert!(!ics.contains("SUMMARY:とうばん: a\\,"));
+    assert!(ics.contains("DESCRIPTION:やること:\\n・ゴミ袋を交換\r\n"));
+}
+
+#[test]
+fn schedule_honours_exemptions() {
+    let book = book_of(&touban(&[
+        "create",
+        "--people",
+        "1",
+        "--interval",
+        "7",
+        "--members",
+        "a,b",
+    ]));
+    // weekly periods from today all fall on today's weekday
+    let weekday = chrono::Local::now().weekday().to_string().to_lowercase();
+    let book = book_of(&touban(&[
+        "set-exempt",
+        "--book",
+        &book,
+        "--member",
+        "a",
+        "--rules",
+        &weekday,
+    ]));
+    let out = touban(&["schedule", "--book", &book, "--periods", "4"]);
+    assert_eq!(out.lines().count(), 4);
+    assert!(out.lines().all(|l| l.ends_with(" b")), "{}", out);
+}
+
+#[test]
+fn stats_export_writes_one_row_per_member_and_duty() {
+    let book = book_of(&touban(&[
+        "create",
+        "--people",
+        "2",
+        "--interval",
+        "7",
+        "--members",
+        "a,b,c",
+    ]));
+    let book = book_of(&touban(&[
+        "add-duty", "--book", &book, "--name", "花", "--people", "1",
+    ]));
+    let book = book_of(&touban(&[
+        "assign", "--book", &book, "--rounds", "3", "--seed", "2",
+    ]));
+    let book = book_of(&touban(&[
+        "assign", "--book", &book, "--duty", "花", "--seed", "2",
+    ]));
+    let csv = touban(&["stats", "export", "--book", &book]);
+    let rows: Vec<Vec<&str>> = csv.lines().map(|l| l.split(',').collect()).collect();
+    assert_eq!(rows[0], ["date", "duty", "member"]);
+    let b = show_json(&book);
+    let mut expected = Vec::new();
+    for h in b["history"].as_array().unwrap() {
+        for m in names(&h["members"]) {
+            expected.push(("とうばん", m));
+        }
+    }
+    let flower = &b["duties"][0]["history"];
+    assert_eq!(flower.as_array().unwrap().len(), 1);
+    for m in names(&flower[0]["members"]) {
+        expected.push(("花", m));
+    }
+    let mut got: Vec<(&str, &str)> = rows[1..].iter().map(|r| (r[1], r[2])).collect();
+    assert_eq!(got.len(), 3 * 2 + 1);
+    got.sort();
+    expected.sort();
+    assert_eq!(got, expected);
+    assert!(rows[1..].iter().all(|r| r[0].len() == "2026-01-01".len()));
+}
+
+#[test]
+fn unknown_members_exit_with_not_found() {
+    let book = book_of(&touban(&[
+        "create",
+        "--people",
+        "1",
+        "--interval",
+        "7",
+        "--members",
+        "a,b",
+    ]));
+    for args in [
+        vec!["ack", "--book", &book, "--member", "zz"],
+        vec!["untag", "--book", &book, "--member", "zz", "--tag", "x"],
+        vec!["note", "add", "--book", &book, "--member", "zz", "x"],
+    ] {
+        let out = Command::new(env!("CARGO_BIN_EXE_touban"))
+            .args(&args)
+            .output()
+            .expect("run touban");
+        assert_eq!(out.status.code(), Some(4), "{:?}", args);
+    }
+}
+
+#[test]
+fn dashboard_board_sorts_books_into_lanes() {
+    let fresh = book_of(&touban(&[
+        "create",
+        "--people",
+        "1",
+        "--interval",
+        "7",
+        "--members",
+        "a,b",
+    ]));
+    let assigned = book_of(&touban(&["assign", "--book", &fresh, "--seed", "1"]));
+    let out = Command::new(env!("CARGO_BIN_EXE_touban"))
+        .args([
+            "dashboard",
+            "--board",
+            "--book",
+            &format!("そうじ={}", assigned),
+            "--book",
+            &format!("ごみ={}", fresh),
+        ])
+        .env("COLUMNS", "100")
+        .output()
+        .expect("run touban");
+    let board = String::from_utf8(out.stdout).expect("utf-8 output");
+    let lines: Vec<&str> = board.lines().collect();
+    assert!(lines[0].contains("これから（1）") && lines[0].contains("未定（1）"));
+    // each card sits in its lane's column
+    let cards = lines[2];
+    let cols: Vec<&str> = cards.split(" │ ").map(str::trim).collect();
+    assert_eq!(cols, ["", "", "■ そうじ", "■ ごみ"]);
+}
+
+#[test]
+fn lenient_show_repairs_a_typo() {
+    let book = book_of(&touban(&[
+        "create",
+        "--people",
+        "1",
+        "--interval",
+        "7",
+        "--members",
+        "a,b",
+    ]));
+    assert!(book.starts_with('た'));
+    let typo = format!("だ{}", &book['た'.len_utf8()..]);
+    let strict = Command::new(env!("CARGO_BIN_EXE_touban"))
+        .args(["show", "--book", &typo])
+        .output()
+        .expect("run touban");
+    assert_eq!(strict.status.code(), Some(3));
+    for cmd in ["show", "list-members"] {
+        let out = Command::new(env!("CARGO_BIN_EXE_touban"))
+            .args(["--lenient", cmd, "--book", &typo])
+            .output()
+            .expect("run touban");
+        assert!(out.status.success(), "{}", cmd);
+        assert!(String::from_utf8_lossy(&out.stderr).contains("1 文字目の「だ」を「た」"));
+        assert_eq!(
+            out.stdout,
+            touban(&[cmd, "--book", &book]).into_bytes(),
+            "{}",
+            cmd
+        );
+    }
+    // whatif decodes the book once, so the repair is reported once
+    let out = Command::new(env!("CARGO_BIN_EXE_touban"))
+        .args(["--lenient", "whatif", "--book", &typo, "--remove", "a"])
+        .output()
+        .expect("run touban");
+    assert!(out.status.success());
+    assert_eq!(
+        String::from_utf8_lossy(&out.stderr)
+            .matches("として読みました")
+            .count(),
+        1
+    );
+}
+
+#[test]
+fn trades_hand_the_seat_over() {
+    let book = book_of(&touban(&[
+        "create",
+        "--people",
+        "1",
+        "--interval",
+        "7",
+        "--members",
+        "a,b",
+    ]));
+    let book = book_of(&touban(&["assign", "--book", &book, "--seed", "1"]));
+    let b = show_json(&book);
+    let giver = b["current"][0]["name"].as_str().unwrap().to_string();
+    let taker = if giver == "a" { "b" } else { "a" };
+    let book = book_of(&touban(&[
+        "trades", "post", "--book", &book, "--member", &giver, "--when", "6/10",
+    ]));
+    // the offering member cannot take their own trade
+    let out = Command::new(env!("CARGO_BIN_EXE_touban"))
+        .args(["trades", "accept", "--book", &book, "--id", "1"])
+        .args(["--member", &giver])
+        .output()
+        .expect("run touban");
+    assert_eq!(out.status.code(), Some(1));
+    let book = book_of(&touban(&[
+        "trades", "accept", "--book", &book, "--id", "1", "--member", taker,
+    ]));
+    let b = show_json(&book);
+    assert_eq!(b["current"][0]["name"], taker);
+    assert!(b["trades"].is_null());
+    let members = b["members"].as_array().unwrap();
+    let total = |name: &str| {
+        members.iter().find(|m| m["name"] == name).unwrap()["total"]
+            .as_u64()
+            .unwrap_or(0)
+    };
+    assert_eq!((total(&giver), total(taker)), (0, 1));
+    assert_eq!(b["history"][0]["members"][0], taker);
+}
+
+#[test]
+fn a_second_draw_on_the_same_day_needs_force() {
+    let book = book_of(&touban(&[
+        "create",
+        "--people",
+        "1",
+        "--interval",
+        "7",
+        "--members",
+        "a,b,c",
+    ]));
+    let book = book_of(&touban(&[
+        "config",
+        "--book",
+        &book,
+        "--history-limit",
+        "0",
+    ]));
+    let book = book_of(&touban(&[
+        "add-duty", "--book", &book, "--name", "花", "--people", "1",
+    ]));
+    let refused = |args: &[&str]| {
+        let out = Command::new(env!("CARGO_BIN_EXE_touban"))
+            .args(args)
+            .output()
+            .expect("run touban");
+        assert_eq!(out.status.code(), Some(1), "{:?}", args);
+        String::from_utf8_lossy(&out.stderr).contains("--force")
+    };
+    // the guard holds even though the history keeps no rounds
+    let drawn = book_of(&touban(&["assign", "--book", &book, "--seed", "1"]));
+    assert!(refused(&["assign", "--book", &drawn, "--seed", "2"]));
+    touban(&["assign", "--book", &drawn, "--seed", "2", "--force"]);
+    // each duty is guarded on its own
+    let both = book_of(&touban(&["assign", "--book", &drawn, "--duty", "花"]));
+    assert!(refused(&["assign", "--book", &both, "--duty", "花"]));
+    touban(&["assign", "--book", &both, "--duty", "花", "--force"]);
+    let flower_only = book_of(&touban(&["assign", "--book", &book, "--duty", "花"]));
+    touban(&["assign", "--book", &flower_only, "--seed", "1"]);
+    // rounds planned by --rounds are not draws of today
+    let planned = book_of(&touban(&[
+        "create",
+        "--people",
+        "1",
+        "--interval",
+        "0",
+        "--members",
+        "a,b",
+    ]));
+    let planned = book_of(&touban(&[
+        "assign", "--book", &planned, "--rounds", "2", "--seed", "1",
+    ]));
+    touban(&["assign", "--book", &planned]);
+}
+
+#[test]
+fn oversized_grace_periods_are_rejected() {
+    let book = book_of(&touban(&[
+        "create",
+        "--people",
+        "1",
+        "--interval",
+        "7",
+        "--members",
+        "a,b",
+    ]));
+    for grace in ["99999999999999999d", "18446744073709551000s"] {
+        let out = Command::new(env!("CARGO_BIN_EXE_touban"))
+            .args(["assign", "--book", &book, "--grace", grace])
+            .output()
+            .expect("run touban");
+        assert_eq!(out.status.code(), Some(1), "{}", grace);
+        assert!(String::from_utf8_lossy(&out.stderr).contains("duration too long"));
+    }
+    touban(&["assign", "--book", &book, "--grace", "2d"]);
+}
+
+#[test]
+fn show_lists_exemption_rules() {
+    let book = book_of(&touban(&[
+        "create",
+        "--people",
+        "1",
+        "--interval",
+        "7",
+        "--members",
+        "a,b",
+    ]));
+    let book = book_of(&touban(&[
+        "set-exempt",
+        "--book",
+        &book,
+        "--member",
+        "a",
+        "--rules",
+        "week1-mon,thu",
+    ]));
+    let out = touban(&["show", "--book", &book]);
+    let line = out.lines().find(|l| l.starts_with(" - a ")).expect("a");
+    assert!(line.contains("休み:thu/week1-mon"), "{}", line);
+    assert_eq!(
+        names(&show_json(&book)["members"][0]["exempt"]),
+        ["thu", "week1-mon"]
+    );
+}
+
+#[test]
+fn acks_are_kept_on_the_seat_and_in_the_history() {
+    let book = book_of(&touban(&[
+        "create",
+        "--people",
+        "2",
+        "--interval",
+        "7",
+        "--members",
+        "a,b,c",
+    ]));
+    let book = book_of(&touban(&["assign", "--book", &book, "--seed", "1"]));
+    let b = show_json(&book);
+    let seated: Vec<String> = b["current"]
+        .as_array()
+        .expect("current")
+        .iter()
+        .map(|a| a["name"].as_str().expect("name").to_string())
+        .collect();
+    let free = ["a", "b", "c"]
+        .into_iter()
+        .find(|n| !seated.iter().any(|s| s == n))
+        .expect("free member");
+    let out = Command::new(env!("CARGO_BIN_EXE_touban"))
+        .args(["ack", "--book", &book, "--member", free])
+        .output()
+        .expect("run touban");
+    assert_eq!(out.status.code(), Some(1));
+    let book = book_of(&touban(&["ack", "--book", &book, "--member", &seated[0]]));
+    let b = show_json(&book);
+    assert_eq!(b["current"][0]["acked"], true);
+    assert!(b["current"][1]["acked"].is_null());
+    assert_eq!(names(&b["history"][0]["acked"]), [seated[0].as_str()]);
+    let out = touban(&["remind", "--book", &book]);
+    assert!(
+        out.contains(&format!("{} (確認済み)", seated[0])),
+        "{}",
+        out
+    );
+    assert!(
+        out.contains(&format!("まだ確認していない人: {}", seated[1])),
+        "{}",
+        out
+    );
+}
+
+#[test]
+fn required_tags_pick_a_holder_every_round() {
+    let book = book_of(&touban(&[
+        "create",
+        "--people",
+        "1",
+        "--interval",
+        "7",
+        "--members",
+        "a,b,c",
+        "--require-tags",
+        "senior",
+    ]));
+    let out = Command::new(env!("CARGO_BIN_EXE_touban"))
+        .args(["assign", "--book", &book])
+        .output()
+        .expect("run touban");
+    assert_eq!(out.status.code(), Some(1));
+    assert!(String::from_utf8_lossy(&out.stderr).contains("senior"));
+    let mut book = book_of(&touban(&[
+        "tag", "--book", &book, "--member", "c", "--tag", "senior",
+    ]));
+    for seed in ["1", "2", "3"] {
+        book = book_of(&touban(&[
+            "assign", "--book", &book, "--seed", seed, "--force",
+        ]));
+        assert_eq!(show_json(&book)["current"][0]["name"], "c");
+    }
+    let book = book_of(&touban(&[
+        "set-required-tags",
+        "--book",
+        &book,
+        "--tags",
+        "",
+    ]));
+    assert!(show_json(&book)["required_tags"].is_null());
+}
+
+#[test]
+fn newcomers_count_down_their_paired_rounds() {
+    let book = book_of(&touban(&[
+        "create",
+        "--people",
+        "2",
+        "--interval",
+        "7",
+        "--members",
+        "a",
+    ]));
+    let book = book_of(&touban(&[
+        "add-member",
+        "--book",
+        &book,
+        "--member",
+        "n",
+        "--onboarding",
+        "1",
+    ]));
+    assert!(touban(&["show", "--book", &book]).contains("新人: あと1回ペア"));
+    let book = book_of(&touban(&["assign", "--book", &book, "--seed", "1"]));
+    let b = show_json(&book);
+    assert!(b["members"][1]["onboarding"].is_null());
+    assert!(!touban(&["show", "--book", &book]).contains("新人"));
+    let undone = book_of(&touban(&["undo", "--book", &book]));
+    assert_eq!(show_json(&undone)["members"][1]["onboarding"], 1);
+}
+
+#[test]
+fn a_grace_period_holds_the_draw_until_confirm() {
+    let book = book_of(&touban(&[
+        "create",
+        "--people",
+        "1",
+        "--interval",
+        "7",
+        "--members",
+        "a,b,c",
+    ]));
+    let pending = book_of(&touban(&[
+        "assign",
+        "--book",
+        &book,
+        "--seed",
+        "1",
+        "--grace",
+        "2h",
+        "--note",
+        "雨",
+        "--exclude",
+        "c",
+    ]));
+    let b = show_json(&pending);
+    let drawn = b["pending"]["members"][0]
+        .as_str()
+        .expect("drawn")
+        .to_string();
+    assert_ne!(drawn, "c");
+    assert!(b["current"].is_null() && b["history"].is_null());
+    assert!(b["members"]
+        .as_array()
+        .expect("members")
+        .iter()
+        .all(|m| m["count"] == 0));
+    let left = b["pending"]["deadline"].as_u64().expect("deadline") as i64
+        - chrono::Utc::now().timestamp();
+    assert!((7100..=7200).contains(&left), "{}", left);
+    let refused = |args: &[&str]| {
+        let out = Command::new(env!("CARGO_BIN_EXE_touban"))
+            .args(args)
+            .output()
+            .expect("run touban");
+        assert_eq!(out.status.code(), Some(1), "{:?}", args);
+    };
+    refused(&["assign", "--book", &pending, "--force"]);
+    // whoever objects stays out of the redraw, as does whoever is away
+    let other = if drawn == "a" { "b" } else { "a" };
+    let pending = book_of(&touban(&[
+        "reroll", "--book", &pending, "--object", &drawn, "--seed", "2",
+    ]));
+    assert_eq!(names(&show_json(&pending)["pending"]["members"]), [other]);
+    let undone = book_of(&touban(&["undo", "--book", &pending]));
+    assert!(show_json(&undone)["pending"].is_null());
+    let book = book_of(&touban(&["confirm", "--book", &pending]));
+    let b = show_json(&book);
+    assert!(b["pending"].is_null());
+    assert_eq!(b["current"][0]["name"], other);
+    assert_eq!(b["history"][0]["note"], "雨");
+    let i = if other == "a" { 0 } else { 1 };
+    assert_eq!(b["members"][i]["count"], 1);
+    refused(&["confirm", "--book", &book]);
+}
+
+#[test]
+fn lenient_updates_write_the_repaired_book() {
+    let book = book_of(&touban(&[
+        "create",
+        "--people",
+        "1",
+        "--interval",
+        "7",
+        "--members",
+        "a,b",
+    ]));
+    let typo = format!("だ{}", &book['た'.len_utf8()..]);
+    let fixed = book_of(&touban(&[
+        "--lenient",
+        "tag",
+        "--book",
+        &typo,
+        "--member",
+        "a",
+        "--tag",
+        "senior",
+    ]));
+    let tagged = book_of(&touban(&[
+        "tag", "--book", &book, "--member", "a", "--tag", "senior",
+    ]));
+    assert_eq!(fixed, tagged);
+}
+
+#[test]
+fn avoid_words_breaks_up_words_in_printed_books() {
+    const WORDS: &[&str] = touban::AVOIDED_WORDS;
+    let create = |extra: &[&str], k: usize| {
+        // a name whose encoding differs in several places from the last
+        let members = format!("{:08x},x", (k as u32).wrapping_mul(2654435761));
+        let mut args = extra.to_vec();
+        args.extend([
+            "create",
+            "--people",
+            "1",
+            "--interval",
+            "7",
+            "--members",
+            &members,
+        ]);
+        book_of(&touban(&args))
+    };
+    let k = (0..200)
+        .find(|&k| WORDS.iter().any(|w| create(&[], k).contains(w)))
+        .expect("a book spelling a word");
+    let (plain, broken) = (create(&[], k), create(&["--avoid-words"], k));
+    assert!(WORDS.iter().all(|w| !broken.contains(w)));
+    assert_eq!(broken.replace('・', ""), plain);
+    // a broken-up book is accepted as it is, and stays broken up
+    let tagged = book_of(&touban(&[
+        "--avoid-words",
+        "tag",
+        "--book",
+        &broken,
+        "--member",
+        "x",
+        "--tag",
+        "t",
+    ]));
+    assert!(WORDS.iter().all(|w| !tagged.contains(w)));
+    assert_eq!(show_json(&tagged)["members"][1]["tags"][0], "t");
+}
+
+#[test]
+fn excluded_members_are_left_out_of_every_round() {
+    let book = book_of(&touban(&[
+        "create",
+        "--people",
+        "1",
+        "--interval",
+        "7",
+        "--members",
+        "a,b,c",
+    ]));
+    let book = book_of(&touban(&[
+        "assign",
+        "--book",
+        &book,
+        "--seed",
+        "1",
+        "--exclude",
+        "a,b",
+        "--rounds",
+        "3",
+    ]));
+    let b = show_json(&book);
+    let drawn: Vec<&str> = b["history"]
+        .as_array()
+        .expect("history")
+        .iter()
+        .map(|h| h["members"][0].as_str().expect("name"))
+        .collect();
+    assert_eq!(drawn, ["c", "c", "c"]);
+    assert_eq!(b["members"][0]["count"], 0);
+    let out = Command::new(env!("CARGO_BIN_EXE_touban"))
+        .args(["assign", "--book", &book, "--exclude", "zz", "--force"])
+        .output()
+        .expect("run touban");
+    assert_eq!(out.status.code(), Some(4));
+}
+
+#[test]
+fn volunteers_are_seated_whatever_their_count() {
+    let book = book_of(&touban(&[
+        "create",
+        "--people",
+        "2",
+        "--interval",
+        "7",
+        "--members",
+        "a,b,c",
+    ]));
+    let mut book = book_of(&touban(&["add-member", "--book", &book, "--member", "v"]));
+    for seed in ["1", "2", "3"] {
+        book = book_of(&touban(&[
+            "assign",
+            "--book",
+            &book,
+            "--seed",
+            seed,
+            "--include",
+            "v",
+            "--force",
+        ]));
+        let b = show_json(&book);
+        assert_eq!(b["current"][0]["name"], "v");
+        assert_eq!(b["current"].as_array().expect("current").len(), 2);
+    }
+    assert_eq!(show_json(&book)["members"][3]["count"], 3);
+    let out = Command::new(env!("CARGO_BIN_EXE_touban"))
+        .args([
+            "assign",
+            "--book",
+            &book,
+            "--include",
+            "a",
+            "--exclude",
+            "a",
+            "--force",
+        ])
+        .output()
+        .expect("run touban");
+    assert_eq!(out.status.code(), Some(1));
+    assert!(String::from_utf8_lossy(&out.stderr).contains("「a」を含めることと除くこと"));
+}
+
+#[test]
+fn round_robin_follows_the_roster() {
+    let book = book_of(&touban(&[
+        "create",
+        "--people",
+        "1",
+        "--interval",
+        "7",
+        "--members",
+        "a,b,c",
+    ]));
+    let mut book = book_of(&touban(&[
+        "config",
+        "--book",
+        &book,
+        "--strategy",
+        "round-robin",
+    ]));
+    let mut drawn = Vec::new();
+    for seed in ["1", "2", "3", "4"] {
+        book = book_of(&touban(&[
+            "assign", "--book", &book, "--seed", seed, "--force",
+        ]));
+        drawn.push(show_json(&book)["current"][0]["name"].clone());
+    }
+    assert_eq!(drawn, ["a", "b", "c", "a"]);
+    assert_eq!(show_json(&book)["rr_cursor"], 1);
+}
+
+#[test]
+fn conflicts_keep_two_members_apart() {
+    let book = book_of(&touban(&[
+        "create",
+        "--people",
+        "2",
+        "--interval",
+        "7",
+        "--members",
+        "a,b,c",
+    ]));
+    let mut book = book_of(&touban(&["add-conflict", "--book", &book, "a", "b"]));
+    assert_eq!(
+        show_json(&book)["conflicts"],
+        serde_json::json!([["a", "b"]])
+    );
+    for seed in ["1", "2", "3", "4"] {
+        book = book_of(&touban(&[
+            "assign", "--book", &book, "--seed", seed, "--force",
+        ]));
+        let b = show_json(&book);
+        let current = b["current"].as_array().expect("current");
+        assert!(current.iter().any(|a| a["name"] == "c"), "{:?}", current);
+    }
+    let book = book_of(&touban(&["remove-conflict", "--book", &book, "b", "a"]));
+    assert!(show_json(&book)["conflicts"].is_null());
+}
+
+#[test]
+fn rebalance_levels_counts_unless_dry_run() {
+    let book = book_of(&touban(&[
+        "create",
+        "--people",
+        "1",
+        "--interval",
+        "7",
+        "--members",
+        "a,b",
+    ]));
+    let book = book_of(&touban(&["assign", "--book", &book, "--seed", "1"]));
+    let waiting = if show_json(&book)["current"][0]["name"] == "a" {
+        "b"
+    } else {
+        "a"
+    };
+    let out = touban(&["rebalance", "--book", &book, "--dry-run"]);
+    assert!(out.contains(&format!("{}: 0回 → 1回", waiting)), "{}", out);
+    assert!(out.contains("お試し"));
+    let book = book_of(&touban(&["rebalance", "--book", &book]));
+    assert!(show_json(&book)["members"]
+        .as_array()
+        .expect("members")
+        .iter()
+        .all(|m| m["count"] == 1));
+    assert!(touban(&["rebalance", "--book", &book, "--dry-run"]).contains("もう ならされています"));
+}
+
+#[test]
+fn pairs_take_their_seats_together() {
+    let book = book_of(&touban(&[
+        "create",
+        "--people",
+        "2",
+        "--interval",
+        "7",
+        "--members",
+        "a,b,c,d",
+    ]));
+    let mut book = book_of(&touban(&["add-pair", "--book", &book, "a", "d"]));
+    for seed in ["1", "2", "3", "4"] {
+        book = book_of(&touban(&[
+            "assign", "--book", &book, "--seed", seed, "--force",
+        ]));
+        let b = show_json(&book);
+        let seated = |n: &str| {
+            b["current"]
+                .as_array()
+                .expect("current")
+                .iter()
+                .any(|a| a["name"] == n)
+        };
+        assert_eq!(seated("a"), seated("d"), "{}", b["current"]);
+    }
+    let book = book_of(&touban(&["remove-pair", "--book", &book, "d", "a"]));
+    assert!(show_json(&book)["pairs"].is_null());
+}
+
+#[test]
+fn per_team_seats_one_member_per_team() {
+    let mut book = book_of(&touban(&[
+        "create",
+        "--people",
+        "1",
+        "--interval",
+        "7",
+        "--members",
+        "a,b,c,d",
+    ]));
+    for (member, team) in [("a", "x"), ("b", "x"), ("c", "y"), ("d", "")] {
+        book = book_of(&touban(&[
+            "set-team", "--book", &book, "--member", member, "--team", team,
+        ]));
+    }
+    let book = book_of(&touban(&[
+        "config",
+        "--book",
+        &book,
+        "--strategy",
+        "per-team",
+    ]));
+    let book = book_of(&touban(&["assign", "--book", &book, "--seed", "1"]));
+    let b = show_json(&book);
+    let teams: Vec<&str> = b["current"]
+        .as_array()
+        .expect("current")
+        .iter()
+        .map(|a| {
+            let name = a["name"].as_str().expect("name");
+            let m = b["members"]
+                .as_array()
+                .expect("members")
+                .iter()
+                .find(|m| m["name"] == name)
+                .expect("member");
+            m["team"].as_str().expect("team")
+        })
+        .collect();
+    assert_eq!(teams, ["x", "y"]);
+    assert!(b["members"][3]["team"].is_null());
+}
+
+#[test]
+fn overrides_show_in_who_for_their_dates() {
+    let book = book_of(&touban(&[
+        "create",
+        "--people",
+        "1",
+        "--interval",
+        "7",
+        "--members",
+        "a,b",
+    ]));
+    let book = book_of(&touban(&["assign", "--book", &book, "--seed", "1"]));
+    let drawn = show_json(&book)["current"][0]["name"]
+        .as_str()
+        .expect("name")
+        .to_string();
+    let cover = if drawn == "a" { "b" } else { "a" };
+    let today = chrono::Local::now().date_naive();
+    let day = |n: i64| (today + chrono::Duration::days(n)).to_string();
+    let book = book_of(&touban(&[
+        "override",
+        "add",
+        "--book",
+        &book,
+        "--member",
+        cover,
+        "--from",
+        &day(0),
+        "--to",
+        &day(2),
+        "--for",
+        &drawn,
+    ]));
+    let who = |date: &str| touban(&["who", "--book", &book, "--date", date]);
+    assert!(who(&day(2)).contains(&format!("{} (代わり)", cover)));
+    assert!(!who(&day(2)).contains(&drawn));
+    assert!(who(&day(3)).contains(&drawn));
+    assert_eq!(show_json(&book)["overrides"][0]["id"], 1);
+    // covering does not touch the counts
+    let i = if cover == "a" { 0 } else { 1 };
+    assert_eq!(show_json(&book)["members"][i]["count"], 0);
+    let book = book_of(&touban(&[
+        "override", "remove", "--book", &book, "--id", "1",
+    ]));
+    assert!(touban(&["who", "--book", &book]).contains(&drawn));
+}
+
+#[test]
+fn a_deferred_member_is_seated_next() {
+    let book = book_of(&touban(&[
+        "create",
+        "--people",
+        "1",
+        "--interval",
+        "7",
+        "--members",
+        "a,b,c",
+    ]));
+    let book = book_of(&touban(&[
+        "assign",
+        "--book",
+        &book,
+        "--seed",
+        "1",
+        "--exclude",
+        "c",
+    ]));
+    let book = book_of(&touban(&["defer", "--book", &book, "--member", "c"]));
+    assert_eq!(names(&show_json(&book)["deferred"]), ["c"]);
+    let out = Command::new(env!("CARGO_BIN_EXE_touban"))
+        .args(["defer", "--book", &book, "--member", "c"])
+        .output()
+        .expect("run touban");
+    assert_eq!(out.status.code(), Some(1));
+    for seed in ["2", "3"] {
+        let next = book_of(&touban(&[
+            "assign", "--book", &book, "--seed", seed, "--force",
+        ]));
+        let b = show_json(&next);
+        assert_eq!(b["current"][0]["name"], "c");
+        assert!(b["deferred"].is_null());
+    }
+}